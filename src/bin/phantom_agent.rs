@@ -18,6 +18,8 @@ mod phantom_agent_snap {
     #[cfg(windows)]
     use phantom_agent::ui::system_tray;
     use phantom_agent::auth::license_manager::LicenseManager;
+    use phantom_agent::ota::bundle_export::BundleExporter;
+    use phantom_agent::ota::component_registry::{load_component_registry, COMPONENT_REGISTRY_FILE};
     use std::{
         env,
         path::{Path, PathBuf},
@@ -25,6 +27,8 @@ mod phantom_agent_snap {
         time::Duration,
     };
     use phantom_agent::utils::verify_ntp;
+    use phantom_agent::rest_request::RestServer;
+    use url::Url;
     use single_instance::SingleInstance;
 
     fn get_hash_manifest_path(user_common_path: &Path) -> PathBuf {
@@ -96,8 +100,8 @@ mod phantom_agent_snap {
         );
         LicenseManager::move_license();
 
-        let config = Config::new();
         let config_path = get_path(user_common_path, Path::new("config"));
+        let config = Config::from_file(&config_path);
         let config_watcher = ConfigWatcher::new(config_path.clone(), logging_configuration::configure_logging);
         config_watcher.watch();
 config_watcher::ConfigWatcher::update_logging_config(&config_path, logging_configuration::configure_logging);
@@ -162,6 +166,64 @@ config_watcher::ConfigWatcher::update_logging_config(&config_path, logging_confi
             println!("{version}");
             std::process::exit(0)
         }
+        if args.len() == 2 && &args[1][..] == "--plan" {
+            std::process::exit(request_local_route("plan", None))
+        }
+        if args.len() == 2 && &args[1][..] == "--history" {
+            std::process::exit(request_local_route("history", None))
+        }
+        if args.len() == 2 && &args[1][..] == "--holds" {
+            std::process::exit(request_local_route("holds", None))
+        }
+        if args.len() == 3 && &args[1][..] == "--release" {
+            std::process::exit(request_local_route(&format!("release/{}", args[2]), None))
+        }
+        // --export [<archive name>] [--key <signing key file>], runs here so a large bundle doesn't time out the local request
+        if (2..=5).contains(&args.len()) && &args[1][..] == "--export" {
+            let key = args.iter().position(|arg| arg == "--key").and_then(|index| args.get(index + 1)).map(PathBuf::from);
+            let name = args.get(2).filter(|name| *name != "--key").map(String::as_str);
+            load_component_registry(get_path(&get_common_path(), Path::new(COMPONENT_REGISTRY_FILE)));
+            let exporter = BundleExporter::new(get_hash_manifest_path(&get_common_path()));
+            match exporter.export(name, key.as_deref()) {
+                Ok(report) => {
+                    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+                    std::process::exit(0)
+                }
+                Err(error) => {
+                    eprintln!("Export failed: {}", error.report());
+                    std::process::exit(1)
+                }
+            }
+        }
+        // --hold <component> [--reason <reason>] [--expires <RFC 3339 time>]
+        if args.len() >= 3 && &args[1][..] == "--hold" {
+            let option = |name: &str| args.iter().position(|arg| arg == name).and_then(|index| args.get(index + 1)).cloned();
+            let hold = serde_json::json!({
+                "component": args[2],
+                "reason": option("--reason").unwrap_or_default(),
+                "expires": option("--expires"),
+            });
+            std::process::exit(request_local_route("hold", Some(hold)))
+        }
+    }
+
+    // Asks the running agent through its local REST listener
+    fn request_local_route(route: &str, body: Option<serde_json::Value>) -> i32 {
+        let url = Url::parse(&format!("http://localhost:{}/{route}", Config::new().ota_rest_port)).unwrap();
+        let response = match body {
+            Some(body) => RestServer::post(&url, &body, None),
+            None => RestServer::get(&url, None),
+        };
+        match response {
+            Ok((response, _)) => {
+                println!("{response}");
+                0
+            }
+            Err((error, code)) => {
+                eprintln!("Request to {url} failed with code {code}: {error}");
+                1
+            }
+        }
     }
 
 
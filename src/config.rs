@@ -1,7 +1,17 @@
-use config::Config as ExternalConfig;
+use config::{Config as ExternalConfig, ConfigError, File, FileFormat};
 use serde::{Deserialize, Serialize};
 use serde_repr::*;
 use std::fmt::{Display, Formatter, Result};
+use std::path::Path;
+use crate::ota::agent_boot_guard::SelfUpdateConfig;
+use crate::ota::artifact_store::ArtifactStoreConfig;
+use crate::ota::bandwidth::BandwidthConfig;
+use crate::ota::busy_detector::BusyConfig;
+use crate::ota::sideload::SideloadConfig;
+use crate::ota::signature::SignatureConfig;
+use crate::ota::maintenance_window::MaintenanceConfig;
+use crate::ota::peer_cache::PeerCacheConfig;
+use crate::ota::retry_backoff::RetryConfig;
 use url::Url;
 
 #[derive(Serialize, Deserialize, Hash, PartialEq, Eq, Clone, Copy)]
@@ -41,6 +51,12 @@ pub fn get_arch() -> ArchType {
     }
 }
 
+// Operator nodes run on Windows or AMD64, vehicles on ARM64
+pub fn is_operator_arch() -> bool {
+    let arch = get_arch();
+    arch == ArchType::WIN || arch == ArchType::AMD64
+}
+
 ///"logging":{
 //       "default_level":2,
 //       "loggers":[
@@ -85,8 +101,7 @@ impl Display for LoggingConfig {
 
 /* -  "core_uri": "http://localhost:8700",
 -  "ota_interval": 3600,
--  "ota_rest_port": 30000,
--  "ota_poll_frequency": 5
+-  "ota_rest_port": 30000
 */
 
 pub struct Config {
@@ -94,30 +109,111 @@ pub struct Config {
     // Intervals are in seconds
     pub ota_interval: u64,
     pub ota_rest_port: u16,
-    pub ota_poll_frequency: u32,
     pub enable_ota: bool,
     pub logging: LoggingConfig,
+    pub maintenance: MaintenanceConfig,
+    pub retry: RetryConfig,
+    pub self_update: SelfUpdateConfig,
+    pub busy: BusyConfig,
+    pub sideload: SideloadConfig,
+    pub peer_cache: PeerCacheConfig,
+    pub bandwidth: BandwidthConfig,
+    pub signatures: SignatureConfig,
+    pub artifact_store: ArtifactStoreConfig,
 }
 
 impl Config {
     pub fn new() -> Config {
         let ota_interval = 3600;
         let ota_rest_port = 30000;
-        let ota_poll_frequency = 5;
         let core_uri = String::from("http://localhost:8700");
         let core_uri = Url::parse(&core_uri).unwrap();
         let enable_ota = true;
 
         let logging = LoggingConfig::default();
+        let maintenance = MaintenanceConfig::default();
+        let retry = RetryConfig::default();
+        let self_update = SelfUpdateConfig::default();
+        let busy = BusyConfig::default();
+        let sideload = SideloadConfig::default();
+        let peer_cache = PeerCacheConfig::default();
+        let bandwidth = BandwidthConfig::default();
+        let signatures = SignatureConfig::default();
+        let artifact_store = ArtifactStoreConfig::default();
 
         Config {
             core_uri,
             ota_interval,
             ota_rest_port,
-            ota_poll_frequency,
+            retry,
             enable_ota,
             logging,
+            maintenance,
+            self_update,
+            busy,
+            sideload,
+            peer_cache,
+            bandwidth,
+            signatures,
+            artifact_store,
+        }
+    }
+
+    // Settings that are only read on startup, logging is handled by the config watcher
+    pub fn from_file(path: &Path) -> Config {
+        let mut config = Config::new();
+        if !path.exists() {
+            log::info!("Config: {} does not exist, using defaults", path.to_string_lossy());
+            return config;
+        }
+        match ExternalConfig::builder()
+            .add_source(File::new(&path.to_string_lossy(), FileFormat::Json))
+            .build() {
+            Ok(settings) => {
+                config.maintenance = Config::get_value_or_default(&settings, "maintenance", MaintenanceConfig::default());
+                config.retry = Config::get_value_or_default(&settings, "retry", RetryConfig::default());
+                config.self_update = Config::get_value_or_default(&settings, "self_update", SelfUpdateConfig::default());
+                config.busy = Config::get_value_or_default(&settings, "busy", BusyConfig::default());
+                config.sideload = Config::get_value_or_default(&settings, "sideload", SideloadConfig::default());
+                config.peer_cache = Config::get_value_or_default(&settings, "peer_cache", PeerCacheConfig::default());
+                config.bandwidth = Config::get_value_or_default(&settings, "bandwidth", BandwidthConfig::default());
+                config.signatures = Config::get_signatures(&settings);
+                config.artifact_store = Config::get_value_or_default(&settings, "artifact_store", ArtifactStoreConfig::default());
+            }
+            Err(e) => {
+                log::error!("Config: Could not read {} ({}), rejecting every package until it's fixed", path.to_string_lossy(), e);
+                config.signatures = SignatureConfig::reject_all();
+            }
+        }
+        if let Err(e) = config.maintenance.validate() {
+            log::error!("Config: Invalid maintenance windows ({}), installing at any time", e);
+            config.maintenance = MaintenanceConfig::default();
+        }
+        if let Err(e) = config.retry.validate() {
+            log::error!("Config: Invalid retry policy ({}), using the default", e);
+            config.retry = RetryConfig::default();
         }
+        if let Err(e) = config.self_update.validate() {
+            log::error!("Config: Invalid self update settings ({}), using the default", e);
+            config.self_update = SelfUpdateConfig::default();
+        }
+        if let Err(e) = config.busy.validate() {
+            log::error!("Config: Invalid busy detectors ({}), only checking the core session", e);
+            config.busy = BusyConfig::default();
+        }
+        if let Err(e) = config.peer_cache.validate() {
+            log::error!("Config: Invalid peer cache ({}), downloading from the links only", e);
+            config.peer_cache.peers = vec![];
+        }
+        if let Err(e) = config.bandwidth.validate() {
+            log::error!("Config: Invalid bandwidth profiles ({}), downloading without a limit", e);
+            config.bandwidth = BandwidthConfig::default();
+        }
+        if let Err(e) = config.signatures.validate() {
+            log::error!("Config: Invalid signature settings ({}), rejecting every package until it's fixed", e);
+            config.signatures = SignatureConfig::reject_all();
+        }
+        config
     }
 
     // settings is external object
@@ -129,6 +225,24 @@ impl Config {
         )
     }
 
+    // Only a missing section turns the checks off, settings that don't parse reject every package
+    fn get_signatures(settings: &ExternalConfig) -> SignatureConfig {
+        match settings.get::<SignatureConfig>("signatures") {
+            Ok(value) => {
+                log::info!("Config: Got signatures value from settings file: {}", value);
+                value
+            }
+            Err(ConfigError::NotFound(_)) => {
+                log::info!("Config: Kept default signatures value: {}", SignatureConfig::default());
+                SignatureConfig::default()
+            }
+            Err(e) => {
+                log::error!("Config: Cannot parse signature settings ({}), rejecting every package until it's fixed", e);
+                SignatureConfig::reject_all()
+            }
+        }
+    }
+
     fn get_value_or_default<'a, T: Display + Deserialize<'a>>(
         settings: &ExternalConfig,
         key: &str,
@@ -4,7 +4,19 @@ use crate::ota::manifest::DOWNLOAD_DIR;
 
 use crate::ota::system_ctl::SystemCtl;
 use crate::ota::ota_status::OTAStatusRestResponse;
-use crate::ota::rest_listener::{create_rest_listener, get_ota_status, rest_listener, set_ota_status};
+use crate::ota::rest_listener::{
+    create_rest_listener, get_maintenance_status, get_ota_status, get_retry_status, rest_listener, set_install_pending, set_maintenance_config,
+    set_ota_status, set_retry_status,
+};
+use crate::ota::cancellation::{cancel_update, set_cancellation_token};
+use crate::ota::component_holds::{active_component_holds, get_holds, hold_component, release_component, set_component_holds};
+use crate::ota::update_history::{get_update_history, set_update_history};
+use crate::ota::baseline_manifest::{baseline_status, load_baseline_manifest};
+use crate::ota::update_plan::{get_update_plan, set_update_planner};
+use crate::ota::bundle_export::{export_route, set_bundle_exporter};
+use crate::ota::bandwidth::{bandwidth_limiter, bandwidth_route, set_bandwidth_config};
+use crate::ota::peer_cache::{artifact_route, set_artifact_index, ARTIFACTS_ROUTE};
+use crate::ota::agent_boot_guard::{AgentBootGuard, AGENT_BOOT_FILE};
 use crate::{
     ota::{ota_error::OTAError, ota_manager::PackageType, manifest::Component},
     service_trait::ServiceTrait,
@@ -13,7 +25,7 @@ use crate::{
 
 use std::{
     cell::RefCell,
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use ota::{version_table::VersionTable};
@@ -48,6 +60,7 @@ pub enum RestMessage {
     UpdateVersionForce,
     GetStatus,
     UpdateBothSides,
+    InstallNow,
 }
 
 #[cfg(unix)]
@@ -73,7 +86,7 @@ pub fn create_ota_service(
                             path.to_string_lossy()
                         );
                         log::error!("{error_msg}");
-                        panic!("{error_msg}");
+                        Err(OTAError::installer("msi", error_msg))
                     }
                     _ => { (ota::snap_installer::SnapInstaller::install)(component, BashExec::exec_arg) }
                 }
@@ -86,13 +99,16 @@ pub fn create_ota_service(
                         let path = component.path.clone().unwrap_or_default();
                         let error_msg = format!( "Unsupported package type on Linux: MSI for path {}", path.to_string_lossy());
                         log::error!("{error_msg}");
-                        panic!("{error_msg}");
+                        Err(OTAError::installer("msi", error_msg))
                     }
                     _ => { (ota::snap_installer::SnapInstaller::uninstall)(component, BashExec::exec_arg) }
                 }
             }
         };
+    check_agent_boot(&hash_manifest_path, &config, install_command);
     create_rest_listener(Some(config.ota_rest_port), );
+    set_maintenance_config(config.maintenance.clone());
+    set_bandwidth_config(config.bandwidth.clone());
 
     let ota_manager = ota::ota_manager::OTAManager::new(
         system_control,
@@ -100,13 +116,27 @@ pub fn create_ota_service(
         config,
         dest_path,
         install_command,
-        update_status_response
+        update_status_response,
+        set_install_pending,
+        set_retry_status,
     );
     set_rest_server_routes(&ota_manager);
     Box::new(ota_manager)
 }
 
+// Counted before anything else starts, so an agent that keeps failing during startup is reverted too
+fn check_agent_boot(hash_manifest_path: &Path, config: &Config, install_command: fn(&Component, bool) -> Result<String, OTAError>) {
+    let agent_boot = AgentBootGuard::new(OTAManager::<SystemCtl>::common_file_path(hash_manifest_path, AGENT_BOOT_FILE));
+    agent_boot.check_on_boot(&config.self_update, install_command);
+}
+
 fn set_rest_server_routes(ota_manager: &OTAManager<SystemCtl>) {
+    set_update_planner(ota_manager.get_update_planner());
+    set_bundle_exporter(ota_manager.get_bundle_exporter());
+    set_cancellation_token(ota_manager.get_cancellation_token());
+    set_component_holds(ota_manager.get_component_holds_path());
+    load_baseline_manifest(ota_manager.get_baseline_manifest_path()); // Validated with the registry defaults
+    set_update_history(ota_manager.get_update_history());
     rest_listener().add_callback(
         "update_version".to_string(),
         Some((ota_manager.get_rest_channel_sender(), RestMessage::UpdateVersion)),
@@ -122,15 +152,82 @@ fn set_rest_server_routes(ota_manager: &OTAManager<SystemCtl>) {
         Some((ota_manager.get_rest_channel_sender(), RestMessage::UpdateBothSides)),
         |_,_| { Ok(LOG_STRING.to_string()) }
     );
+    rest_listener().add_callback(
+        "install_now".to_string(),
+        Some((ota_manager.get_rest_channel_sender(), RestMessage::InstallNow)),
+        |_,_| { Ok(LOG_STRING.to_string()) }
+    );
+    // Picked up by the sideload poll, like a bundle copied into the directory
+    if ota_manager.sideload_allowed() {
+        rest_listener().add_upload(
+            "sideload".to_string(),
+            ota_manager.get_sideload_dir(),
+            ota_manager.get_sideload_max_upload(),
+        );
+    } else {
+        log::info!("Sideload uploads are off, offline bundles need signatures set to required");
+    }
+    if let Some(artifact_index) = ota_manager.get_artifact_index() {
+        set_artifact_index(artifact_index);
+        rest_listener().add_file_route(
+            ARTIFACTS_ROUTE.to_string(),
+            artifact_route,
+        );
+    }
+    rest_listener().add_callback(
+        "holds".to_string(),
+        None,
+        get_holds,
+    );
+    rest_listener().add_callback(
+        "hold".to_string(),
+        None,
+        hold_component,
+    );
+    rest_listener().add_callback(
+        "release".to_string(),
+        None,
+        release_component,
+    );
+    rest_listener().add_callback(
+        "cancel".to_string(),
+        None,
+        cancel_update,
+    );
     rest_listener().add_callback(
         "status".to_string(),
         None,
         |_,_| {
-            let response = get_ota_status();
+            let mut response = serde_json::to_value(get_ota_status()).unwrap();
+            response["maintenance"] = get_maintenance_status().report();
+            response["retry"] = get_retry_status().report();
+            response["holds"] = serde_json::json!(active_component_holds());
+            response["baseline"] = serde_json::json!(baseline_status());
+            response["bandwidth"] = bandwidth_limiter().report();
             let response_string = serde_json::to_string(&response).unwrap();
             Ok(response_string)
         }
     );
+    rest_listener().add_callback(
+        "bandwidth".to_string(),
+        None,
+        bandwidth_route,
+    );
+    rest_listener().add_callback(
+        "history".to_string(),
+        None,
+        get_update_history,
+    );
+    rest_listener().add_callback(
+        "plan".to_string(),
+        None,
+        get_update_plan,
+    );
+    rest_listener().add_callback(
+        "export".to_string(),
+        None,
+        export_route,
+    );
     rest_listener().add_callback(
         "log".to_string(),
         None,
@@ -173,8 +270,6 @@ pub fn create_ota_service(
     config: Config,
 ) -> Box<dyn ServiceTrait> {
     let dest_path = PathBuf::from(DOWNLOAD_DIR);
-    create_rest_listener(Some(config.ota_rest_port));
-
     let install_command =
         |component: &Component, installing: bool| -> Result<String, OTAError> {
             if installing { // Installing the component
@@ -189,7 +284,7 @@ pub fn create_ota_service(
                             path.to_string_lossy()
                         );
                         log::error!("{error_msg}");
-                        panic!("{error_msg}");
+                        Err(OTAError::installer(&component.package_type, error_msg))
                     }
                 }
             }
@@ -205,11 +300,15 @@ pub fn create_ota_service(
                             path.to_string_lossy()
                         );
                         log::error!("{error_msg}");
-                        panic!("{error_msg}");
+                        Err(OTAError::installer(&component.package_type, error_msg))
                     }
                 }
             }
         };
+    check_agent_boot(&hash_manifest_path, &config, install_command);
+    create_rest_listener(Some(config.ota_rest_port));
+    set_maintenance_config(config.maintenance.clone());
+    set_bandwidth_config(config.bandwidth.clone());
 
     let system_control = RefCell::new(SystemCtl::new());
     create_dir_if_not_exists(&dest_path);
@@ -219,7 +318,9 @@ pub fn create_ota_service(
         config,
         dest_path,
         install_command,
-        update_status_response
+        update_status_response,
+        set_install_pending,
+        set_retry_status,
     ));
 
 
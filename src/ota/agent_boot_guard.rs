@@ -0,0 +1,370 @@
+use crate::ota::{
+    manifest::{current_agent_version, Component, ComponentType},
+    ota_error::OTAError,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{Display, Formatter},
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+pub const AGENT_BOOT_FILE: &str = "agent_boot";
+
+//"self_update":{ "confirm_timeout":15, "max_restarts":3 }
+// A new agent must complete a successful OTA cycle within confirm_timeout minutes and max_restarts restarts,
+// otherwise the previous agent package is reinstalled.
+
+fn default_confirm_timeout() -> u64 { 15 }
+fn default_max_restarts() -> u32 { 3 }
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct SelfUpdateConfig {
+    #[serde(default = "default_confirm_timeout")]
+    pub confirm_timeout: u64,
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: u32,
+}
+
+impl Default for SelfUpdateConfig {
+    fn default() -> Self {
+        Self { confirm_timeout: default_confirm_timeout(), max_restarts: default_max_restarts() }
+    }
+}
+
+impl Display for SelfUpdateConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let self_serialized = serde_json::to_string_pretty(&self).unwrap();
+        write!(f, "\n{self_serialized}")
+    }
+}
+
+impl SelfUpdateConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.confirm_timeout == 0 {
+            return Err("confirm_timeout must be positive".to_string());
+        }
+        if self.max_restarts == 0 {
+            return Err("max_restarts must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Written before the agent installs itself, removed once the new agent confirms or is reverted
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PendingAgentBoot {
+    pub from_version: String,
+    pub to_version: String,
+    pub checksum: String,
+    // Package of the running agent (slot A), there is nothing to revert to without it
+    pub previous_package: Option<PathBuf>,
+    pub previous_checksum: String,
+    // Where the agent package is kept, see InstallManager::save_prev_components
+    pub previous_dir: PathBuf,
+    // Copy of the new package (slot B), it becomes the previous package once confirmed
+    pub new_package: PathBuf,
+    // Unix time
+    pub installed_at: i64,
+    pub boots: u32,
+    // Set once the watchdog gave up on the new agent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revert_reason: Option<String>,
+}
+
+// An agent package that was reverted is not installed again, the server has to publish another one
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RejectedAgent {
+    pub version: String,
+    pub checksum: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq)]
+struct AgentBootState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pending: Option<PendingAgentBoot>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rejected: Option<RejectedAgent>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BootCheck {
+    // No self update in progress
+    Idle,
+    // The new agent is up and has the given seconds left to confirm
+    Unconfirmed(u64),
+    // The new agent has to go
+    Revert(String),
+    // The previous agent is running again, the hash manifest still has the new one
+    Reverted(PendingAgentBoot),
+}
+
+pub fn check_boot(pending: &PendingAgentBoot, version: &str, config: &SelfUpdateConfig, now: i64) -> BootCheck {
+    if version != pending.to_version || pending.revert_reason.is_some() {
+        return if version == pending.from_version { BootCheck::Reverted(pending.clone()) } else { BootCheck::Idle };
+    }
+    if pending.boots > config.max_restarts {
+        return BootCheck::Revert(format!("restarted {} times without completing an OTA check", pending.boots - 1));
+    }
+    let deadline = pending.installed_at + (config.confirm_timeout * 60) as i64;
+    if now >= deadline {
+        return BootCheck::Revert(format!("did not complete an OTA check within {} minutes", config.confirm_timeout));
+    }
+    BootCheck::Unconfirmed((deadline - now) as u64)
+}
+
+// The agent package kept in previous/, as the installer expects it
+pub fn previous_agent_component(pending: &PendingAgentBoot, previous_package: &Path) -> Component {
+    ComponentType::phantom_agent.info().apply_to(Component {
+        component: ComponentType::phantom_agent.name().to_string(),
+        checksum: pending.previous_checksum.clone(),
+        version: pending.from_version.clone(),
+        path: Some(previous_package.to_path_buf()),
+        ..Component::empty()
+    })
+}
+
+#[derive(Clone)]
+pub struct AgentBootGuard {
+    path: PathBuf,
+}
+
+impl AgentBootGuard {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    fn read(&self) -> AgentBootState {
+        match fs::read_to_string(&self.path) {
+            Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+                log::error!("Failed to parse {}: {}, ignoring it", self.path.to_string_lossy(), e);
+                AgentBootState::default()
+            }),
+            Err(_) => AgentBootState::default(),
+        }
+    }
+
+    fn write(&self, state: &AgentBootState) -> Result<(), String> {
+        if *state == AgentBootState::default() {
+            return match fs::remove_file(&self.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(format!("Failed to remove {}: {}", self.path.to_string_lossy(), e)),
+                _ => Ok(()),
+            };
+        }
+        // Written on every boot, a power loss in the middle must not lose the pending update
+        let json = serde_json::to_string_pretty(state).map_err(|e| e.to_string())?;
+        let temp_path = self.path.with_extension("tmp");
+        File::create(&temp_path)
+            .and_then(|mut file| {
+                file.write_all(json.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&temp_path, &self.path))
+            .map_err(|e| format!("Failed to write {}: {}", self.path.to_string_lossy(), e))
+    }
+
+    pub fn pending(&self) -> Option<PendingAgentBoot> {
+        self.read().pending
+    }
+
+    pub fn start(&self, pending: PendingAgentBoot) -> Result<(), OTAError> {
+        let state = AgentBootState { pending: Some(pending), ..self.read() };
+        self.write(&state).map_err(OTAError::state_corruption)
+    }
+
+    // Called once per agent start, before anything else is initialized
+    pub fn on_boot(&self, version: &str, config: &SelfUpdateConfig, now: i64) -> BootCheck {
+        let mut state = self.read();
+        let pending = match state.pending.as_mut() {
+            Some(pending) => pending,
+            None => return BootCheck::Idle,
+        };
+        if version == pending.to_version {
+            pending.boots += 1;
+        }
+        let check = check_boot(pending, version, config, now);
+        if let Err(e) = self.write(&state) {
+            log::error!("{}", e);
+        }
+        check
+    }
+
+    // Counts the boot and reverts a new agent that ran out of restarts or time, the rest of the time runs out in the background
+    pub fn check_on_boot(&self, config: &SelfUpdateConfig, install_command: fn(&Component, bool) -> Result<String, OTAError>) {
+        match self.on_boot(&current_agent_version(), config, Utc::now().timestamp()) {
+            BootCheck::Unconfirmed(seconds) => {
+                log::info!("Phantom agent was updated, it has {} seconds to complete an OTA check", seconds);
+                let agent_boot = self.clone();
+                let config = config.clone();
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_secs(seconds));
+                    if let Some(pending) = agent_boot.pending() {
+                        if let BootCheck::Revert(reason) = check_boot(&pending, &current_agent_version(), &config, Utc::now().timestamp()) {
+                            agent_boot.revert(&pending, &reason, install_command);
+                        }
+                    }
+                });
+            }
+            BootCheck::Revert(reason) => {
+                if let Some(pending) = self.pending() {
+                    self.revert(&pending, &reason, install_command);
+                }
+            }
+            // A revert is committed once the hash manifest is loaded
+            BootCheck::Idle | BootCheck::Reverted(_) => {}
+        }
+    }
+
+    pub fn mark_reverting(&self, reason: &str) {
+        let mut state = self.read();
+        if let Some(pending) = state.pending.as_mut() {
+            pending.revert_reason = Some(reason.to_string());
+        }
+        if let Err(e) = self.write(&state) {
+            log::error!("{}", e);
+        }
+    }
+
+    // The new agent completed a check, its package becomes the one to revert to next time
+    pub fn confirm(&self, version: &str) {
+        let mut state = self.read();
+        let pending = match state.pending.take() {
+            Some(pending) if pending.to_version == version && pending.revert_reason.is_none() => pending,
+            _ => return,
+        };
+        log::info!("Phantom agent {} confirmed healthy", version);
+        let previous_dir = &pending.previous_dir;
+        if let Some(file) = pending.new_package.file_name() {
+            let result = fs::remove_dir_all(previous_dir)
+                .or_else(|e| if e.kind() == std::io::ErrorKind::NotFound { Ok(()) } else { Err(e) })
+                .and_then(|_| fs::create_dir_all(previous_dir))
+                .and_then(|_| fs::rename(&pending.new_package, previous_dir.join(file)));
+            if let Err(e) = result {
+                log::error!("Failed to keep the agent package {}: {}", pending.new_package.to_string_lossy(), e);
+            }
+        }
+        if let Some(slot) = pending.new_package.parent() {
+            let _ = fs::remove_dir_all(slot);
+        }
+        if let Err(e) = self.write(&state) {
+            log::error!("{}", e);
+        }
+    }
+
+    // Reinstalls the previous agent package, which stops this process when it succeeds
+    pub fn revert(&self, pending: &PendingAgentBoot, reason: &str, install_command: fn(&Component, bool) -> Result<String, OTAError>) {
+        log::error!("Phantom agent {} {}, reverting to {}", pending.to_version, reason, pending.from_version);
+        let previous_package = match pending.previous_package.clone().filter(|package| package.exists()) {
+            Some(package) => package,
+            None => {
+                log::error!("No previous agent package is kept, staying on {}", pending.to_version);
+                self.cancel();
+                return;
+            }
+        };
+        self.mark_reverting(reason);
+        if let Err(error) = install_command(&previous_agent_component(pending, &previous_package), true) {
+            log::error!("Failed to reinstall phantom agent {}: {}", pending.from_version, error.report());
+        }
+    }
+
+    // The previous agent runs again, the package it replaced is not installed again
+    pub fn reject(&self, pending: &PendingAgentBoot) {
+        let reason = pending.revert_reason.clone().unwrap_or_else(|| "the new agent did not start".to_string());
+        let state = AgentBootState {
+            pending: None,
+            rejected: Some(RejectedAgent { version: pending.to_version.clone(), checksum: pending.checksum.clone(), reason }),
+        };
+        if let Some(slot) = pending.new_package.parent() {
+            let _ = fs::remove_dir_all(slot);
+        }
+        if let Err(e) = self.write(&state) {
+            log::error!("{}", e);
+        }
+    }
+
+    // Install of the new agent failed while we were still running
+    pub fn cancel(&self) {
+        let mut state = self.read();
+        if let Some(pending) = state.pending.take() {
+            if let Some(slot) = pending.new_package.parent() {
+                let _ = fs::remove_dir_all(slot);
+            }
+        }
+        if let Err(e) = self.write(&state) {
+            log::error!("{}", e);
+        }
+    }
+
+    pub fn rejected(&self, checksum: &str) -> Option<RejectedAgent> {
+        self.read().rejected.filter(|rejected| rejected.checksum == checksum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending() -> PendingAgentBoot {
+        PendingAgentBoot {
+            from_version: "1.9.3".to_string(),
+            to_version: "1.9.4".to_string(),
+            checksum: "new".to_string(),
+            previous_package: Some(PathBuf::from("previous/phantom_agent/phantom-agent_1.9.3.snap")),
+            previous_checksum: "old".to_string(),
+            previous_dir: PathBuf::from("previous/phantom_agent"),
+            new_package: PathBuf::from("previous/phantom_agent.next/phantom-agent_1.9.4.snap"),
+            installed_at: 1000,
+            boots: 1,
+            revert_reason: None,
+        }
+    }
+
+    #[test]
+    fn boot_checks() {
+        let config = SelfUpdateConfig::default();
+        assert_eq!(check_boot(&pending(), "1.9.4", &config, 1060), BootCheck::Unconfirmed(840));
+        assert_eq!(
+            check_boot(&pending(), "1.9.4", &config, 1900),
+            BootCheck::Revert("did not complete an OTA check within 15 minutes".to_string())
+        );
+        let crash_loop = PendingAgentBoot { boots: 4, ..pending() };
+        assert_eq!(
+            check_boot(&crash_loop, "1.9.4", &config, 1060),
+            BootCheck::Revert("restarted 3 times without completing an OTA check".to_string())
+        );
+        assert_eq!(check_boot(&pending(), "1.9.3", &config, 1060), BootCheck::Reverted(pending()));
+        assert_eq!(check_boot(&pending(), "2.0.0", &config, 1060), BootCheck::Idle);
+    }
+
+    #[test]
+    fn confirm_keeps_the_new_package() {
+        let dir = std::env::temp_dir().join("agent_boot_guard_test");
+        let _ = fs::remove_dir_all(&dir);
+        let slot = dir.join("phantom_agent.next");
+        fs::create_dir_all(&slot).unwrap();
+        fs::write(slot.join("phantom-agent_1.9.4.snap"), "new").unwrap();
+        let guard = AgentBootGuard::new(dir.join(AGENT_BOOT_FILE));
+        guard.start(PendingAgentBoot {
+            previous_dir: dir.join("phantom_agent"),
+            new_package: slot.join("phantom-agent_1.9.4.snap"),
+            boots: 0,
+            installed_at: 0,
+            ..pending()
+        }).unwrap();
+
+        assert_eq!(guard.on_boot("1.9.4", &SelfUpdateConfig::default(), 60), BootCheck::Unconfirmed(840));
+        assert_eq!(guard.pending().unwrap().boots, 1);
+        guard.confirm("1.9.4");
+        assert!(guard.pending().is_none());
+        assert!(dir.join("phantom_agent/phantom-agent_1.9.4.snap").exists());
+        assert!(!slot.exists());
+        assert!(!dir.join(AGENT_BOOT_FILE).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
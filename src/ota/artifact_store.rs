@@ -0,0 +1,251 @@
+use crate::utils::file_utils::{get_checksum_like, link_or_copy};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const ARTIFACT_STORE_DIR: &str = "artifacts";
+// Dot-prefixed so the peer cache doesn't serve it
+const ARTIFACT_STORE_INDEX: &str = ".index";
+
+//"artifact_store":{
+//       "max_size_mb":4096
+//    }
+// Every verified download is kept once in artifacts/<checksum>/, next to previous/. A component whose checksum is in
+// the store is linked from it instead of being downloaded, so going back to a version, switching servers or
+// re-installing after a purge fetches nothing. The backups in previous/ are hard links to the same files, a roll back
+// whose backup is gone takes the previous package from the store.
+// The least recently used artifacts are removed once the store is bigger than max_size_mb, 0 disables the store.
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct ArtifactStoreConfig {
+    #[serde(default)]
+    pub max_size_mb: u64,
+}
+
+impl Display for ArtifactStoreConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let self_serialized = serde_json::to_string_pretty(&self).unwrap();
+        write!(f, "\n{self_serialized}")
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct StoredArtifact {
+    file: String,
+    size: u64,
+    // Seconds since the epoch
+    last_used: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0)
+}
+
+#[derive(Clone, Debug)]
+pub struct ArtifactStore {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+impl ArtifactStore {
+    // None when the store is disabled
+    pub fn new(dir: PathBuf, config: &ArtifactStoreConfig) -> Option<Self> {
+        (config.max_size_mb > 0).then(|| Self { dir, max_size: config.max_size_mb * 1024 * 1024 })
+    }
+
+    pub fn dir(&self) -> PathBuf {
+        self.dir.clone()
+    }
+
+    // Typed checksums have a colon, which Windows doesn't take in a name
+    fn artifact_dir(&self, key: &str) -> PathBuf {
+        self.dir.join(key.replace(':', "_"))
+    }
+
+    // By lowercase checksum
+    fn index(&self) -> HashMap<String, StoredArtifact> {
+        match fs::read_to_string(self.dir.join(ARTIFACT_STORE_INDEX)) {
+            Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+                log::error!("Failed to parse the artifact store index: {}", e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        }
+    }
+
+    fn save(&self, index: &HashMap<String, StoredArtifact>) {
+        let path = self.dir.join(ARTIFACT_STORE_INDEX);
+        let temp_path = path.with_extension("tmp");
+        let content = serde_json::to_string_pretty(index).unwrap();
+        let result = fs::create_dir_all(&self.dir)
+            .and_then(|_| File::create(&temp_path))
+            .and_then(|mut file| {
+                file.write_all(content.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&temp_path, &path));
+        if let Err(e) = result {
+            log::error!("Failed to save the artifact store index: {}", e);
+        }
+    }
+
+    // Links the stored artifact to path, false when it isn't stored or doesn't match its checksum anymore
+    pub fn fetch(&self, checksum: &str, path: &Path) -> bool {
+        let key = checksum.to_lowercase();
+        let mut index = self.index();
+        let stored = match index.get(&key) {
+            Some(artifact) => self.artifact_dir(&key).join(&artifact.file),
+            None => return false,
+        };
+        let actual_checksum = get_checksum_like(&stored, checksum).unwrap_or_default();
+        if !actual_checksum.eq_ignore_ascii_case(checksum) {
+            log::warn!("Artifact store: {} does not match {} anymore, removing it", stored.to_string_lossy(), checksum);
+            let _ = fs::remove_dir_all(self.artifact_dir(&key));
+            index.remove(&key);
+            self.save(&index);
+            return false;
+        }
+        let _ = fs::remove_file(path);
+        if let Err(e) = link_or_copy(&stored, path) {
+            log::warn!("Artifact store: failed to link {} to {}: {}", stored.to_string_lossy(), path.to_string_lossy(), e);
+            return false;
+        }
+        if let Some(artifact) = index.get_mut(&key) {
+            artifact.last_used = now();
+        }
+        self.save(&index);
+        log::info!("Artifact store: {} is taken from the store, no download is needed", path.to_string_lossy());
+        true
+    }
+
+    // Links the stored artifact into dir under its stored name, for a backup that is gone
+    pub fn fetch_into(&self, checksum: &str, dir: &Path) -> Option<PathBuf> {
+        let file = self.index().get(&checksum.to_lowercase())?.file.clone();
+        fs::create_dir_all(dir).ok()?;
+        let path = dir.join(file);
+        self.fetch(checksum, &path).then_some(path)
+    }
+
+    // Called with a verified download
+    pub fn store(&self, checksum: &str, path: &Path) {
+        let key = checksum.to_lowercase();
+        let mut index = self.index();
+        match index.get_mut(&key) {
+            Some(artifact) => artifact.last_used = now(),
+            None => {
+                let file = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+                let artifact_dir = self.artifact_dir(&key);
+                let _ = fs::remove_dir_all(&artifact_dir);
+                let stored = fs::create_dir_all(&artifact_dir).and_then(|_| link_or_copy(path, &artifact_dir.join(&file)));
+                if let Err(e) = stored {
+                    log::warn!("Artifact store: failed to store {}: {}", path.to_string_lossy(), e);
+                    let _ = fs::remove_dir_all(&artifact_dir);
+                    return;
+                }
+                let size = fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0);
+                index.insert(key.clone(), StoredArtifact { file, size, last_used: now() });
+            }
+        }
+        self.evict(&mut index, &key);
+        self.save(&index);
+    }
+
+    // Space that removing the artifact gives back, none while previous/ or a download links to the same file
+    fn reclaimable_size(&self, key: &str, artifact: &StoredArtifact) -> u64 {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let links = fs::metadata(self.artifact_dir(key).join(&artifact.file)).map(|metadata| metadata.nlink()).unwrap_or(1);
+            if links > 1 {
+                return 0;
+            }
+        }
+        artifact.size
+    }
+
+    // Least recently used first, the artifact that was just stored stays even when it's over the budget alone.
+    // Shared artifacts are left, removing them frees nothing
+    fn evict(&self, index: &mut HashMap<String, StoredArtifact>, keep: &str) {
+        let sizes: HashMap<String, u64> = index.iter().map(|(key, artifact)| (key.clone(), self.reclaimable_size(key, artifact))).collect();
+        let mut total_size: u64 = sizes.values().sum();
+        let mut by_use: Vec<(String, u64, u64)> = index
+            .iter()
+            .filter(|(key, _)| key.as_str() != keep && sizes[key.as_str()] > 0)
+            .map(|(key, artifact)| (key.clone(), artifact.last_used, sizes[key.as_str()]))
+            .collect();
+        by_use.sort_by_key(|(_, last_used, _)| *last_used);
+        for (key, _, size) in by_use {
+            if total_size <= self.max_size {
+                break;
+            }
+            log::info!("Artifact store: removing {} to stay within {} MB", key, self.max_size / 1024 / 1024);
+            let _ = fs::remove_dir_all(self.artifact_dir(&key));
+            index.remove(&key);
+            total_size -= size;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::file_utils::get_sha1_checksum;
+
+    #[test]
+    fn artifacts_are_reused_and_evicted() {
+        let dir = std::env::temp_dir().join("artifact_store_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("download")).unwrap();
+        // Only what the store alone holds counts, one package is over the budget
+        let store = ArtifactStore { dir: dir.join(ARTIFACT_STORE_DIR), max_size: 10 };
+        assert!(ArtifactStore::new(dir.clone(), &ArtifactStoreConfig::default()).is_none());
+
+        let download = |name: &str, content: &str| {
+            let path = dir.join("download").join(name);
+            fs::write(&path, content).unwrap();
+            (path.clone(), get_sha1_checksum(&path).unwrap())
+        };
+        let (core, core_checksum) = download("core_1.0.snap", "core package 1.0");
+        store.store(&core_checksum, &core);
+        fs::remove_file(&core).unwrap();
+        assert!(store.fetch(&core_checksum, &core));
+        assert_eq!(fs::read_to_string(&core).unwrap(), "core package 1.0");
+        assert!(!store.fetch("0000000000000000000000000000000000000000", &dir.join("download/other.snap")));
+
+        // The older core is the least recently used one once the new one is stored
+        fs::remove_file(&core).unwrap();
+        let mut index = store.index();
+        index.get_mut(&core_checksum).unwrap().last_used = now() - 3600;
+        store.save(&index);
+        let (newer, newer_checksum) = download("core_1.1.snap", "core package 1.1");
+        store.store(&newer_checksum, &newer);
+        assert!(!store.fetch(&core_checksum, &core));
+        assert!(store.fetch(&newer_checksum, &dir.join("download/core_1.1_again.snap")));
+
+        // A stored file that was changed is dropped
+        let stored = store.artifact_dir(&newer_checksum).join("core_1.1.snap");
+        fs::remove_file(&stored).unwrap();
+        fs::write(&stored, "tampered").unwrap();
+        assert!(!store.fetch(&newer_checksum, &dir.join("download/core_1.1.snap")));
+        assert!(store.index().is_empty());
+
+        // Still linked from the download, removing it wouldn't free anything
+        let (shared, shared_checksum) = download("core_2.0.snap", "core package 2.0");
+        store.store(&shared_checksum, &shared);
+        let mut index = store.index();
+        index.get_mut(&shared_checksum).unwrap().last_used = now() - 3600;
+        store.save(&index);
+        let (newest, newest_checksum) = download("core_2.1.snap", "core package 2.1");
+        store.store(&newest_checksum, &newest);
+        let restored = store.fetch_into(&shared_checksum, &dir.join("previous")).unwrap();
+        assert_eq!(restored, dir.join("previous/core_2.0.snap"));
+        assert_eq!(fs::read_to_string(&restored).unwrap(), "core package 2.0");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
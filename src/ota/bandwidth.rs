@@ -0,0 +1,200 @@
+use crate::ota::maintenance_window::{MaintenanceConfig, MaintenanceWindow};
+use chrono::{DateTime, Utc};
+use hyper::Uri;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    fmt::{Display, Formatter},
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+//"bandwidth":{
+//       "timezone":"+02:00",
+//       "limit_kb_per_sec":0,
+//       "profiles":[
+//          {
+//             "days":["Mon","Tue","Wed","Thu","Fri"],
+//             "start":"07:00",
+//             "end":"22:00",
+//             "limit_kb_per_sec":1024
+//          }
+//       ]
+//    }
+// The first profile that covers the current time sets the limit, limit_kb_per_sec applies otherwise. 0 is unlimited.
+// Profile times work like the maintenance windows. The limit is shared by all downloads running at once.
+// POST /bandwidth {"limit_kb_per_sec":512} overrides the configured limit until a restart, {"limit_kb_per_sec":null} drops the override.
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct BandwidthProfile {
+    #[serde(flatten)]
+    pub window: MaintenanceWindow,
+    pub limit_kb_per_sec: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BandwidthConfig {
+    #[serde(default)]
+    pub timezone: String,
+    #[serde(default)]
+    pub limit_kb_per_sec: u64,
+    #[serde(default)]
+    pub profiles: Vec<BandwidthProfile>,
+}
+
+impl Display for BandwidthConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let self_serialized = serde_json::to_string_pretty(&self).unwrap();
+        write!(f, "\n{self_serialized}")
+    }
+}
+
+impl BandwidthConfig {
+    fn schedule(&self, profile: &BandwidthProfile) -> MaintenanceConfig {
+        MaintenanceConfig { timezone: self.timezone.clone(), windows: vec![profile.window.clone()] }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for profile in &self.profiles {
+            self.schedule(profile).validate()?;
+        }
+        Ok(())
+    }
+
+    // In KB/s, 0 is unlimited
+    pub fn limit_at(&self, now: DateTime<Utc>) -> u64 {
+        self.profiles
+            .iter()
+            .find(|profile| self.schedule(profile).is_open(now))
+            .map(|profile| profile.limit_kb_per_sec)
+            .unwrap_or(self.limit_kb_per_sec)
+    }
+}
+
+#[derive(Default)]
+pub struct BandwidthLimiter {
+    config: Mutex<BandwidthConfig>,
+    // Set through REST, kept until the agent restarts
+    override_limit: Mutex<Option<u64>>,
+    // When the bytes that were let through so far are paid for
+    next_free: Mutex<Option<Instant>>,
+}
+
+impl BandwidthLimiter {
+    pub fn set_config(&self, config: BandwidthConfig) {
+        *self.config.lock().unwrap() = config;
+    }
+
+    pub fn set_override(&self, limit_kb_per_sec: Option<u64>) {
+        *self.override_limit.lock().unwrap() = limit_kb_per_sec;
+    }
+
+    // In bytes per second, None when unlimited
+    pub fn limit(&self, now: DateTime<Utc>) -> Option<u64> {
+        let limit_kb_per_sec = match *self.override_limit.lock().unwrap() {
+            Some(limit_kb_per_sec) => limit_kb_per_sec,
+            None => self.config.lock().unwrap().limit_at(now),
+        };
+        (limit_kb_per_sec > 0).then_some(limit_kb_per_sec * 1024)
+    }
+
+    // How long to wait before these bytes can go on, the wait of one download delays the others too
+    fn reserve(&self, bytes: u64, limit: u64) -> Duration {
+        let now = Instant::now();
+        let mut next_free = self.next_free.lock().unwrap();
+        let start = next_free.filter(|next_free| *next_free > now).unwrap_or(now);
+        let end = start + Duration::from_secs_f64(bytes as f64 / limit as f64);
+        *next_free = Some(end);
+        end - now
+    }
+
+    pub fn report(&self) -> Value {
+        let override_limit = *self.override_limit.lock().unwrap();
+        json!({
+            "limit_kb_per_sec": self.limit(Utc::now()).map(|limit| limit / 1024).unwrap_or(0),
+            "override": override_limit,
+            "config": *self.config.lock().unwrap(),
+        })
+    }
+}
+
+static BANDWIDTH_LIMITER: OnceLock<BandwidthLimiter> = OnceLock::new();
+
+pub fn bandwidth_limiter() -> &'static BandwidthLimiter {
+    BANDWIDTH_LIMITER.get_or_init(BandwidthLimiter::default)
+}
+
+pub fn set_bandwidth_config(config: BandwidthConfig) {
+    bandwidth_limiter().set_config(config);
+}
+
+// Called with every chunk that was downloaded
+pub async fn throttle(bytes: usize) {
+    let limiter = bandwidth_limiter();
+    if let Some(limit) = limiter.limit(Utc::now()) {
+        let wait = limiter.reserve(bytes as u64, limit);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct BandwidthRequest {
+    limit_kb_per_sec: Option<u64>,
+}
+
+// Without a body only reports the limit
+pub fn bandwidth_route(_: Uri, body: String) -> Result<String, String> {
+    if !body.trim().is_empty() {
+        let request: BandwidthRequest = serde_json::from_str(&body).map_err(|e| format!("Cannot parse bandwidth request: {}", e))?;
+        match request.limit_kb_per_sec {
+            Some(limit_kb_per_sec) => log::info!("Download bandwidth is set to {} KB/s (0 is unlimited)", limit_kb_per_sec),
+            None => log::info!("Download bandwidth is back to the configured limit"),
+        }
+        bandwidth_limiter().set_override(request.limit_kb_per_sec);
+    }
+    serde_json::to_string(&bandwidth_limiter().report()).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(date: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn profile_sets_the_limit() {
+        let config: BandwidthConfig = serde_json::from_str(r#"{
+            "timezone": "UTC",
+            "limit_kb_per_sec": 0,
+            "profiles": [ { "start": "07:00", "end": "22:00", "limit_kb_per_sec": 1024 } ]
+        }"#).unwrap();
+        assert!(config.validate().is_ok());
+        let limiter = BandwidthLimiter::default();
+        limiter.set_config(config);
+        assert_eq!(limiter.limit(utc("2023-06-05T12:00:00Z")), Some(1024 * 1024));
+        assert_eq!(limiter.limit(utc("2023-06-05T23:00:00Z")), None);
+        limiter.set_override(Some(10));
+        assert_eq!(limiter.limit(utc("2023-06-05T23:00:00Z")), Some(10 * 1024));
+        limiter.set_override(Some(0));
+        assert_eq!(limiter.limit(utc("2023-06-05T12:00:00Z")), None);
+
+        // Two downloads of 500 bytes share 1000 bytes per second
+        let first = limiter.reserve(500, 1000);
+        let second = limiter.reserve(500, 1000);
+        assert!(first <= Duration::from_millis(500) && first > Duration::from_millis(450));
+        assert!(second > Duration::from_millis(950));
+
+        let invalid = BandwidthConfig {
+            profiles: vec![BandwidthProfile {
+                window: MaintenanceWindow { days: vec![], start: "7am".to_string(), end: "22:00".to_string() },
+                limit_kb_per_sec: 1,
+            }],
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
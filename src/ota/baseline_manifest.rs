@@ -0,0 +1,206 @@
+use crate::ota::{
+    component_registry::find_component,
+    hardcoded_manifest::get_hardcoded_manifest,
+    manifest::{current_agent_version, Component, ComponentType},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
+};
+
+pub const BASELINE_MANIFEST_FILE: &str = "baseline_manifest";
+const KNOWN_PACKAGE_TYPES: [&str; 4] = ["snap", "deb", "tar", "msi"];
+
+// What a node has before any server manifest, per node type. A file in the common directory replaces the builtin one:
+//{
+//   "version":"customer-1.2",
+//   "operator":[ { "component":"core", "version":"1.0.0", "checksum":"", "package_type":"snap" }, ... ],
+//   "vehicle":[ ... ]
+//}
+// Components must be in the component registry, attributes missing from an entry come from it. An invalid file is rejected as a whole.
+#[derive(Deserialize)]
+struct BaselineFile {
+    version: String,
+    operator: Vec<Component>,
+    vehicle: Vec<Component>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct BaselineStatus {
+    // "builtin" or the file path
+    pub source: String,
+    pub version: String,
+    // Why the baseline file was rejected
+    pub errors: Vec<String>,
+}
+
+struct Baseline {
+    status: BaselineStatus,
+    operator: Vec<Component>,
+    vehicle: Vec<Component>,
+}
+
+static BASELINE: OnceLock<Baseline> = OnceLock::new();
+
+fn builtin_status(errors: Vec<String>) -> BaselineStatus {
+    BaselineStatus { source: "builtin".to_string(), version: format!("builtin-{}", current_agent_version()), errors }
+}
+
+// Registered unique names, known package types and a target path for archives
+pub fn validate_baseline(components: &[Component], operator: bool) -> Result<(), Vec<String>> {
+    let mut errors = vec![];
+    let mut names = HashSet::new();
+    for component in components {
+        let name = &component.component;
+        if name.is_empty() {
+            errors.push("Component without a name".to_string());
+            continue;
+        }
+        if !names.insert(name) {
+            errors.push(format!("{}: duplicate component", name));
+        }
+        let component = match find_component(name) {
+            Some(name) => ComponentType::from_static(name).node_info(operator).apply_to(component.clone()),
+            None => {
+                errors.push(format!("{}: not in the component registry", name));
+                continue;
+            }
+        };
+        if !KNOWN_PACKAGE_TYPES.contains(&component.package_type.as_str()) {
+            errors.push(format!("{}: unknown package type [{}]", name, component.package_type));
+        }
+        if component.package_type == "tar" && component.target_path.map(|path| path.as_os_str().is_empty()).unwrap_or(true) {
+            errors.push(format!("{}: target_path is required for tar packages", name));
+        }
+    }
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+fn read_baseline_file(path: &Path) -> Result<Baseline, Vec<String>> {
+    let text = fs::read_to_string(path).map_err(|e| vec![format!("Cannot read {}: {}", path.to_string_lossy(), e)])?;
+    let file: BaselineFile = serde_json::from_str(&text).map_err(|e| vec![format!("Cannot parse {}: {}", path.to_string_lossy(), e)])?;
+    let mut errors = vec![];
+    for (node, components, operator) in [("operator", &file.operator, true), ("vehicle", &file.vehicle, false)] {
+        if let Err(node_errors) = validate_baseline(components, operator) {
+            errors.extend(node_errors.into_iter().map(|error| format!("{}: {}", node, error)));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(Baseline {
+        status: BaselineStatus { source: path.to_string_lossy().to_string(), version: file.version, errors: vec![] },
+        operator: file.operator,
+        vehicle: file.vehicle,
+    })
+}
+
+// Called once on startup, without a file (or with an invalid one) the builtin baseline is used
+pub fn load_baseline_manifest(path: PathBuf) {
+    if !path.exists() {
+        log::info!("No baseline manifest at {}, using the builtin one", path.to_string_lossy());
+        return;
+    }
+    let baseline = match read_baseline_file(&path) {
+        Ok(baseline) => {
+            log::info!("Using baseline manifest {} from {}", baseline.status.version, path.to_string_lossy());
+            baseline
+        }
+        Err(errors) => {
+            log::error!("Baseline manifest {} is invalid, using the builtin one:", path.to_string_lossy());
+            for error in &errors {
+                log::error!("    {}", error);
+            }
+            Baseline { status: builtin_status(errors), operator: vec![], vehicle: vec![] }
+        }
+    };
+    if BASELINE.set(baseline).is_err() {
+        log::warn!("Baseline manifest was already set");
+    }
+}
+
+pub fn baseline_status() -> BaselineStatus {
+    BASELINE.get().map(|baseline| baseline.status.clone()).unwrap_or_else(|| builtin_status(vec![]))
+}
+
+fn builtin_components(operator: bool) -> Result<Vec<Component>, String> {
+    let components: HashMap<String, Component> = serde_json::from_str(&get_hardcoded_manifest(operator))
+        .map_err(|e| format!("Failed to parse the builtin baseline: {}", e))?;
+    let mut mismatched: Vec<String> = components
+        .iter()
+        .filter(|(name, component)| **name != component.component)
+        .map(|(name, component)| format!("{} is listed as {}", component.component, name))
+        .collect();
+    mismatched.sort();
+    if !mismatched.is_empty() {
+        return Err(format!("Invalid builtin baseline: {}", mismatched.join(", ")));
+    }
+    Ok(components.into_values().collect())
+}
+
+pub fn baseline_components(operator: bool) -> Result<HashMap<ComponentType, Component>, String> {
+    let components = match BASELINE.get().filter(|baseline| baseline.status.errors.is_empty()) {
+        Some(baseline) => if operator { baseline.operator.clone() } else { baseline.vehicle.clone() },
+        None => {
+            let components = builtin_components(operator)?;
+            validate_baseline(&components, operator).map_err(|errors| format!("Invalid builtin baseline: {}", errors.join(", ")))?;
+            components
+        }
+    };
+    components
+        .into_iter()
+        .map(|component| match ComponentType::from_str(&component.component) {
+            Ok(component_type) => Ok((component_type, component)),
+            Err(_) => Err(format!("{} is not in the component registry", component.component)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_baselines_are_valid() {
+        for operator in [true, false] {
+            let components = builtin_components(operator).unwrap();
+            assert_eq!(validate_baseline(&components, operator), Ok(()));
+            // The attributes come from the component registry
+            assert!(components.iter().all(|component| component.package_type.is_empty() && component.target_path.is_none() && component.processes.is_empty()));
+        }
+        let plugin = baseline_components(false).unwrap().remove(&ComponentType::oden_plugin).unwrap();
+        assert_eq!(ComponentType::oden_plugin.node_info(false).apply_to(plugin).target_path, Some(PathBuf::from("/opt/phantom-streamer")));
+    }
+
+    #[test]
+    fn invalid_baseline_file() {
+        let path = std::env::temp_dir().join("baseline_manifest_test");
+        fs::write(&path, r#"{
+            "version":"customer-1",
+            "operator":[
+                { "component":"core", "checksum":"", "package_type":"snap" },
+                { "component":"core", "checksum":"", "package_type":"snap" },
+                { "component":"lidar", "checksum":"", "package_type":"tar" },
+                { "component":"sdk_demo", "checksum":"", "package_type":"tar" }
+            ],
+            "vehicle":[ { "component":"vapp", "checksum":"", "package_type":"rpm" } ]
+        }"#).unwrap();
+        let errors = read_baseline_file(&path).err().unwrap();
+        assert_eq!(errors, vec![
+            "operator: core: duplicate component",
+            "operator: lidar: not in the component registry",
+            "operator: sdk_demo: target_path is required for tar packages",
+            "vehicle: vapp: unknown package type [rpm]",
+        ]);
+
+        fs::write(&path, r#"{ "version":"customer-2", "operator":[ { "component":"core", "checksum":"" } ], "vehicle":[] }"#).unwrap();
+        let baseline = read_baseline_file(&path).ok().unwrap();
+        assert_eq!(baseline.status.version, "customer-2");
+        assert_eq!(baseline.operator.len(), 1);
+        fs::remove_file(path).unwrap();
+    }
+}
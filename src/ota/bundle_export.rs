@@ -0,0 +1,323 @@
+use crate::auth::{
+    auth_manager::fetch_license_manager,
+    license_manager_trait::{AuthError, LicenseManagerTrait},
+};
+use crate::config::is_operator_arch;
+use crate::ota::{
+    component_holds::held_components,
+    manifest::{full_server_name, Component, ComponentType, Manifest, HASH_MANIFEST_PATH, PREVIOUS_INSTALL_PATH},
+    ota_error::OTAError,
+    rest_listener::with_status,
+    sideload::{is_bundle_name, BUNDLE_MANIFEST_FILE, BUNDLE_SIGNATURE_FILE},
+    signature::sign_file,
+    update_journal::{UpdateJournal, UPDATE_JOURNAL_FILE},
+    version_table::{VersionTable, VERSIONS_FILE_PATH},
+};
+use crate::utils::{
+    bash_exec::{BashExec, ExecArgType},
+    file_utils::{file_to_string, get_checksum_like, string_to_file},
+};
+use chrono::Utc;
+use hyper::Uri;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    fs,
+    path::{Component as PathComponent, Path, PathBuf},
+    sync::OnceLock,
+};
+
+pub const EXPORT_DIR: &str = "export";
+const ARTIFACTS_DIR: &str = "artifacts";
+
+// Clones this node: the backups under previous/<server>/<component> go into a sideload bundle,
+// with the hash manifest and the version table next to bundle.json for reference.
+// The agent is always listed, a replacement node must not uninstall it when the backup is missing.
+// Bundles are only written into the export directory, a request can name the file but not where it goes.
+// Nodes only install bundles with a valid bundle.sig. With a signing key (see signature) the export writes it,
+// otherwise the report says signed: false and bundle.json has to be signed offline before the bundle is used.
+
+#[derive(Serialize, Debug)]
+pub struct ExportedComponent {
+    pub component: String,
+    pub version: String,
+    pub checksum: String,
+    pub file: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ExportReport {
+    pub archive: PathBuf,
+    pub server_name: String,
+    pub version: String,
+    pub signed: bool,
+    pub components: Vec<ExportedComponent>,
+}
+
+// Every installed component with its backup, checked against the hash manifest before anything is written
+fn exported_artifacts(manifest: &Manifest) -> Result<Vec<(Component, Option<PathBuf>)>, OTAError> {
+    let mut components: Vec<&Component> = manifest.components.values().filter(|component| component.currently_installed()).collect();
+    components.sort_by(|first, second| first.component.cmp(&second.component));
+    let mut artifacts = vec![];
+    for component in components {
+        let (backup_exists, backup) = component.uninstall_information();
+        if !backup_exists {
+            if component.component == ComponentType::phantom_agent.name() {
+                log::warn!("{} has no backup, the bundle keeps the agent of the target node", component.component);
+                artifacts.push((component.clone(), None));
+                continue;
+            }
+            return Err(OTAError::bundle(format!("{} is installed but has no backup to export", component.component)));
+        }
+        let actual_checksum = get_checksum_like(&backup, &component.checksum).map_err(OTAError::bundle)?;
+        if !actual_checksum.eq_ignore_ascii_case(&component.checksum) {
+            return Err(OTAError::checksum_mismatch(&component.component, &component.checksum, &actual_checksum));
+        }
+        artifacts.push((component.clone(), Some(backup)));
+    }
+    Ok(artifacts)
+}
+
+// Hard links when the staging dir is on the same disk, the backups can be big
+fn stage_file(source: &Path, destination: &Path) -> Result<(), OTAError> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).map_err(|e| OTAError::bundle(format!("Failed to create {}: {}", parent.to_string_lossy(), e)))?;
+    }
+    fs::hard_link(source, destination)
+        .or_else(|_| fs::copy(source, destination).map(|_| ()))
+        .map_err(|e| OTAError::bundle(format!("Failed to stage {}: {}", source.to_string_lossy(), e)))
+}
+
+pub fn export_bundle(manifest: &Manifest, version: &str, version_table: Option<&Path>, archive: &Path, signing_key: Option<&Path>, exec: ExecArgType) -> Result<ExportReport, OTAError> {
+    let artifacts = exported_artifacts(manifest)?;
+    let staging_dir = archive.with_extension("staging");
+    if staging_dir.exists() {
+        fs::remove_dir_all(&staging_dir).map_err(|e| OTAError::bundle(format!("Failed to clear {}: {}", staging_dir.to_string_lossy(), e)))?;
+    }
+    let result = (|| -> Result<Vec<ExportedComponent>, OTAError> {
+        let mut exported = vec![];
+        let mut missing_components = vec![];
+        for (component, backup) in artifacts {
+            let file = match backup {
+                Some(backup) => {
+                    let file = format!("{}/{}/{}", ARTIFACTS_DIR, component.component, backup.file_name().unwrap_or_default().to_string_lossy());
+                    stage_file(&backup, &staging_dir.join(&file))?;
+                    Some(file)
+                }
+                None => None,
+            };
+            // Node specific fields stay behind, the target fills them in like for a server manifest
+            let mut entry = serde_json::to_value(Component { updated: false, path: None, previous_install_path: None, ..component.clone() })
+                .map_err(|e| OTAError::bundle(e.to_string()))?;
+            if let Some(file) = &file {
+                entry["file"] = json!(file);
+            }
+            missing_components.push(entry);
+            exported.push(ExportedComponent { component: component.component, version: component.version, checksum: component.checksum, file });
+        }
+        let bundle_json = json!({ "version": version, "missingComponents": missing_components });
+        fs::create_dir_all(&staging_dir).map_err(|e| OTAError::bundle(format!("Failed to create {}: {}", staging_dir.to_string_lossy(), e)))?;
+        fs::write(staging_dir.join(BUNDLE_MANIFEST_FILE), serde_json::to_string_pretty(&bundle_json).unwrap())
+            .map_err(|e| OTAError::bundle(format!("Failed to write {}: {}", BUNDLE_MANIFEST_FILE, e)))?;
+        if let Some(signing_key) = signing_key {
+            let signature = sign_file(signing_key, &staging_dir.join(BUNDLE_MANIFEST_FILE)).map_err(OTAError::bundle)?;
+            fs::write(staging_dir.join(BUNDLE_SIGNATURE_FILE), signature)
+                .map_err(|e| OTAError::bundle(format!("Failed to write {}: {}", BUNDLE_SIGNATURE_FILE, e)))?;
+        }
+        stage_file(&manifest.hash_manifest.hash_path, &staging_dir.join(HASH_MANIFEST_PATH))?;
+        if let Some(version_table) = version_table.filter(|path| path.exists()) {
+            stage_file(version_table, &staging_dir.join(VERSIONS_FILE_PATH))?;
+        }
+        exec("tar", &["-czf", &archive.to_string_lossy(), "-C", &staging_dir.to_string_lossy(), "."])
+            .map_err(|e| OTAError::bundle(format!("Failed to create {}: {}", archive.to_string_lossy(), e)))?;
+        Ok(exported)
+    })();
+    if let Err(e) = fs::remove_dir_all(&staging_dir) {
+        log::warn!("Failed to remove {}: {}", staging_dir.to_string_lossy(), e);
+    }
+    let components = result?;
+    log::info!("Exported {} components into {}", components.len(), archive.to_string_lossy());
+    if signing_key.is_none() {
+        log::warn!("{} is not signed, sign its {} before installing it", archive.to_string_lossy(), BUNDLE_MANIFEST_FILE);
+    }
+    let signed = signing_key.is_some();
+    Ok(ExportReport { archive: archive.to_path_buf(), server_name: manifest.server_name.clone(), version: version.to_string(), signed, components })
+}
+
+#[derive(Clone)]
+pub struct BundleExporter {
+    pub hash_manifest_path: PathBuf,
+    pub previous_install_path: PathBuf,
+    pub export_dir: PathBuf,
+    pub fetch_license_manager: fn() -> Result<Box<dyn LicenseManagerTrait>, AuthError>,
+    pub read_function: fn(path: &Path) -> Result<String, String>,
+    pub write_function: fn(path: &Path, content: &str) -> Result<(), String>,
+    pub journal: UpdateJournal,
+}
+
+// A plain bundle file name, no directories
+pub fn check_archive_name(name: &str) -> Result<(), String> {
+    let mut components = Path::new(name).components();
+    let plain = matches!((components.next(), components.next()), (Some(PathComponent::Normal(_)), None)) && !name.contains(['/', '\\']);
+    if !plain || !is_bundle_name(name) {
+        return Err(format!("{} is not a bundle file name like clone.tar.gz", name));
+    }
+    Ok(())
+}
+
+impl BundleExporter {
+    // Everything else lives next to the hash manifest
+    pub fn new(hash_manifest_path: PathBuf) -> Self {
+        let common_dir = hash_manifest_path.parent().map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("."));
+        Self {
+            hash_manifest_path,
+            previous_install_path: common_dir.join(PREVIOUS_INSTALL_PATH),
+            export_dir: common_dir.join(EXPORT_DIR),
+            fetch_license_manager,
+            read_function: file_to_string,
+            write_function: string_to_file,
+            journal: UpdateJournal::new(common_dir.join(UPDATE_JOURNAL_FILE)),
+        }
+    }
+
+    // Same node type as the next cycle, an update both stage overrides the architecture
+    pub fn operator(&self) -> bool {
+        self.journal.operator_override().unwrap_or_else(is_operator_arch)
+    }
+
+    // Into the export dir, named after the server and the version unless a name is given
+    pub fn export(&self, name: Option<&str>, signing_key: Option<&Path>) -> Result<ExportReport, OTAError> {
+        if let Some(name) = name {
+            check_archive_name(name).map_err(OTAError::bundle)?;
+        }
+        let operator = self.operator();
+        let license_manager = (self.fetch_license_manager)()
+            .map_err(|e| OTAError::auth(format!("Error occurred during loading the license manager file: {e}")))?;
+        let server_name = license_manager.get_server().unwrap_or_default();
+        let manifest = Manifest::new(
+            operator,
+            self.hash_manifest_path.clone(),
+            self.previous_install_path.clone(),
+            server_name.clone(),
+            self.read_function,
+            self.write_function,
+        )?.with_holds(held_components());
+        let version = VersionTable::new().get_version();
+        fs::create_dir_all(&self.export_dir).map_err(|e| OTAError::bundle(format!("Failed to create {}: {}", self.export_dir.to_string_lossy(), e)))?;
+        let archive = match name {
+            Some(name) => self.export_dir.join(name),
+            None => {
+                let name = format!("{}-{}-{}.tar.gz", full_server_name(&server_name, operator), version, Utc::now().format("%Y%m%d%H%M%S"));
+                self.export_dir.join(name.replace(['/', ':'], "_"))
+            }
+        };
+        export_bundle(&manifest, &version, Some(&VersionTable::get_version_table_path()), &archive, signing_key, BashExec::exec_arg)
+    }
+}
+
+static BUNDLE_EXPORTER: OnceLock<BundleExporter> = OnceLock::new();
+
+pub fn set_bundle_exporter(exporter: BundleExporter) {
+    if BUNDLE_EXPORTER.set(exporter).is_err() {
+        log::warn!("Bundle exporter was already set");
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct ExportRequest {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    key: Option<PathBuf>,
+}
+
+// Body is optional, {"name": "clone.tar.gz", "key": "/etc/phantom/bundle.key"} is written into the export dir
+pub fn export_route(_: Uri, body: String) -> Result<String, String> {
+    let request: ExportRequest = if body.trim().is_empty() {
+        ExportRequest::default()
+    } else {
+        serde_json::from_str(&body).map_err(|e| with_status(400, format!("Cannot parse export request: {}", e)))?
+    };
+    if let Some(name) = &request.name {
+        check_archive_name(name).map_err(|e| with_status(400, e))?;
+    }
+    let exporter = match BUNDLE_EXPORTER.get() {
+        Some(exporter) => exporter.clone(),
+        None => return Err("Bundle exporter is not initialized".to_string()),
+    };
+    // Blocking requests can't run on the listener's runtime
+    match std::thread::spawn(move || -> Result<String, String> {
+        let report = exporter.export(request.name.as_deref(), request.key.as_deref()).map_err(|e| e.report())?;
+        serde_json::to_string_pretty(&report).map_err(|e| e.to_string())
+    }).join() {
+        Ok(result) => result,
+        Err(_) => {
+            log::error!("Crash in bundle export thread");
+            Err("Bundle export thread crashed".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ota::sideload::Bundle;
+    use crate::utils::file_utils::get_sha1_checksum;
+
+    fn read_function(_path: &Path) -> Result<String, String> {
+        Ok(r#"{ "V_": { "core":"core checksum" } }"#.to_string())
+    }
+
+    #[test]
+    fn export_takes_only_a_file_name() {
+        assert!(check_archive_name("clone.tar.gz").is_ok());
+        for name in ["../clone.tar.gz", "/media/usb/clone.tar.gz", "usb/clone.tar.gz", "..\\clone.tar.gz", "..", ".hidden.tar.gz", "clone.txt", ""] {
+            assert!(check_archive_name(name).is_err(), "{}", name);
+        }
+        let dir = std::env::temp_dir().join("bundle_export_name_test");
+        let exporter = BundleExporter::new(dir.join("hash_manifest"));
+        assert_eq!(exporter.export(Some("../../clone.tar.gz"), None).err().unwrap().code(), "OTA-BUNDLE");
+        assert!(export_route(Uri::from_static("/export"), r#"{"name":"/tmp/clone.tar.gz"}"#.to_string()).unwrap_err().starts_with("status 400 "));
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn export_verifies_backups() {
+        let dir = std::env::temp_dir().join("bundle_export_test");
+        let _ = fs::remove_dir_all(&dir);
+        let previous_install_path = dir.join("previous");
+        let backup_dir = previous_install_path.join("V_").join("core");
+        fs::create_dir_all(&backup_dir).unwrap();
+        fs::write(backup_dir.join("core_1.0.snap"), "core package").unwrap();
+        let checksum = get_sha1_checksum(&backup_dir.join("core_1.0.snap")).unwrap();
+
+        let manifest = Manifest::new(false, dir.join("hash_manifest"), previous_install_path, Default::default(), read_function, |_, _| Ok(())).unwrap();
+        assert_eq!(exported_artifacts(&manifest).err().unwrap().code(), "OTA-CHECKSUM");
+
+        let core = ComponentType::core;
+        let mut components = manifest.components;
+        components.get_mut(&core).unwrap().checksum = checksum.clone();
+        let manifest = Manifest { components, ..manifest };
+        let artifacts = exported_artifacts(&manifest).unwrap();
+        let (component, backup) = artifacts.iter().find(|(component, _)| component.component == "core").unwrap();
+        assert_eq!(component.checksum, checksum);
+        assert_eq!(backup.as_ref(), Some(&backup_dir.join("core_1.0.snap")));
+
+        #[cfg(unix)]
+        {
+            fs::write(dir.join("hash_manifest"), "{}").unwrap();
+            let archive = dir.join("clone.tar.gz");
+            let report = export_bundle(&manifest, "3.2.1", None, &archive, None, BashExec::exec_arg).unwrap();
+            assert_eq!(report.version, "3.2.1");
+            assert!(!report.signed);
+            assert!(!archive.with_extension("staging").exists());
+            let extracted = dir.join("extracted");
+            let bundle = Bundle::extract(&archive, &extracted, BashExec::exec_arg).unwrap();
+            assert_eq!(bundle.version, "3.2.1");
+            assert_eq!(bundle.files.get("core"), Some(&extracted.join("artifacts/core/core_1.0.snap")));
+            assert!(bundle.manifest_json.contains("\"updated\": false"));
+            assert!(extracted.join(HASH_MANIFEST_PATH).exists());
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,219 @@
+use crate::ota::{install_manager::MIN_PROCESS_NAME_LENGTH, service_control_trait::SystemControlTrait};
+use crate::rest_comm::core_rest_comm_trait::CoreRestCommTrait;
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    fmt::{Display, Formatter},
+    fs,
+    path::PathBuf,
+    time::Duration,
+};
+use url::Url;
+
+const HTTP_DETECTOR_TIMEOUT: Duration = Duration::from_secs(5);
+
+//"busy":{
+//       "before_download":true,
+//       "detectors":[
+//          { "type":"core_session" },
+//          { "type":"process", "name":"phantom-recorder" },
+//          { "type":"http", "url":"http://localhost:8900/busy" },
+//          { "type":"file", "path":"/var/snap/phantom-core/common/busy" }
+//       ]
+//    }
+// Installs always wait for the node to be free, downloads only with before_download.
+// The http endpoint is busy when it answers true or {"busy":true}, an endpoint that doesn't answer is not.
+
+pub trait BusyDetector {
+    fn name(&self) -> String;
+    // Why the node is busy, None when it's free
+    fn busy(&self) -> Option<String>;
+}
+
+pub struct CoreSessionDetector<'a> {
+    pub core_rest_comm: &'a dyn CoreRestCommTrait,
+}
+
+impl BusyDetector for CoreSessionDetector<'_> {
+    fn name(&self) -> String {
+        "core_session".to_string()
+    }
+
+    fn busy(&self) -> Option<String> {
+        self.core_rest_comm.is_core_has_connected_session().then(|| "core has a connected session".to_string())
+    }
+}
+
+pub struct ProcessDetector<'a, A: SystemControlTrait> {
+    pub name: String,
+    pub system_control: &'a RefCell<A>,
+}
+
+impl<A: SystemControlTrait> BusyDetector for ProcessDetector<'_, A> {
+    fn name(&self) -> String {
+        format!("process {}", self.name)
+    }
+
+    fn busy(&self) -> Option<String> {
+        let processes = self.system_control.borrow_mut().find_process(&self.name);
+        (!processes.is_empty()).then(|| format!("{} is running", self.name))
+    }
+}
+
+pub struct HttpDetector {
+    pub url: Url,
+}
+
+impl BusyDetector for HttpDetector {
+    fn name(&self) -> String {
+        format!("http {}", self.url)
+    }
+
+    fn busy(&self) -> Option<String> {
+        let response = reqwest::blocking::Client::builder()
+            .timeout(HTTP_DETECTOR_TIMEOUT)
+            .build()
+            .and_then(|client| client.get(self.url.as_str()).send())
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text());
+        match response {
+            Ok(text) => {
+                let busy = match serde_json::from_str::<serde_json::Value>(&text) {
+                    Ok(serde_json::Value::Bool(busy)) => busy,
+                    Ok(value) => value["busy"].as_bool().unwrap_or(false),
+                    Err(_) => false,
+                };
+                busy.then(|| format!("{} reports busy", self.url))
+            }
+            Err(e) => {
+                log::warn!("Busy check {} failed ({}), assuming the node is free", self.url, e);
+                None
+            }
+        }
+    }
+}
+
+pub struct FileDetector {
+    pub path: PathBuf,
+}
+
+impl BusyDetector for FileDetector {
+    fn name(&self) -> String {
+        format!("file {}", self.path.to_string_lossy())
+    }
+
+    // The flag file may say why
+    fn busy(&self) -> Option<String> {
+        if !self.path.exists() {
+            return None;
+        }
+        match fs::read_to_string(&self.path).map(|text| text.trim().to_string()) {
+            Ok(text) if !text.is_empty() => Some(format!("{} is set ({})", self.path.to_string_lossy(), text)),
+            _ => Some(format!("{} is set", self.path.to_string_lossy())),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BusyDetectorConfig {
+    CoreSession,
+    Process { name: String },
+    Http { url: Url },
+    File { path: PathBuf },
+}
+
+fn default_before_download() -> bool { true }
+fn default_detectors() -> Vec<BusyDetectorConfig> { vec![BusyDetectorConfig::CoreSession] }
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct BusyConfig {
+    #[serde(default = "default_before_download")]
+    pub before_download: bool,
+    #[serde(default = "default_detectors")]
+    pub detectors: Vec<BusyDetectorConfig>,
+}
+
+impl Default for BusyConfig {
+    fn default() -> Self {
+        Self { before_download: default_before_download(), detectors: default_detectors() }
+    }
+}
+
+impl Display for BusyConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let self_serialized = serde_json::to_string_pretty(&self).unwrap();
+        write!(f, "\n{self_serialized}")
+    }
+}
+
+impl BusyConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for detector in &self.detectors {
+            match detector {
+                BusyDetectorConfig::Process { name } if name.len() < MIN_PROCESS_NAME_LENGTH => {
+                    return Err(format!("process name [{}] is too short", name));
+                }
+                BusyDetectorConfig::File { path } if path.as_os_str().is_empty() => {
+                    return Err("file detector without a path".to_string());
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn detectors<'a, A: SystemControlTrait>(
+        &self,
+        core_rest_comm: &'a dyn CoreRestCommTrait,
+        system_control: &'a RefCell<A>,
+    ) -> Vec<Box<dyn BusyDetector + 'a>> {
+        self.detectors
+            .iter()
+            .map(|detector| -> Box<dyn BusyDetector + 'a> {
+                match detector {
+                    BusyDetectorConfig::CoreSession => Box::new(CoreSessionDetector { core_rest_comm }),
+                    BusyDetectorConfig::Process { name } => Box::new(ProcessDetector { name: name.clone(), system_control }),
+                    BusyDetectorConfig::Http { url } => Box::new(HttpDetector { url: url.clone() }),
+                    BusyDetectorConfig::File { path } => Box::new(FileDetector { path: path.clone() }),
+                }
+            })
+            .collect()
+    }
+}
+
+// The first detector that reports busy, as "name: reason"
+pub fn first_busy(detectors: &[Box<dyn BusyDetector + '_>]) -> Option<String> {
+    detectors.iter().find_map(|detector| detector.busy().map(|reason| format!("{}: {}", detector.name(), reason)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ota::service_control_trait::MockSystemControlTrait;
+    use crate::rest_comm::core_rest_comm_trait::MockCoreRestCommTrait;
+
+    #[test]
+    fn first_busy_detector_blocks() {
+        let config: BusyConfig = serde_json::from_str(r#"{ "detectors":[
+            { "type":"core_session" },
+            { "type":"process", "name":"phantom-recorder" },
+            { "type":"file", "path":"/nonexistent/busy" }
+        ] }"#).unwrap();
+        assert!(config.before_download);
+        assert_eq!(config.validate(), Ok(()));
+
+        let mut core_rest_comm = MockCoreRestCommTrait::new();
+        core_rest_comm.expect_is_core_has_connected_session().returning(|| false);
+        let mut system_control = MockSystemControlTrait::new();
+        system_control.expect_find_process().returning(|name| if name == "phantom-recorder" { vec![42] } else { vec![] });
+        let system_control = RefCell::new(system_control);
+
+        let detectors = config.detectors(&core_rest_comm, &system_control);
+        assert_eq!(first_busy(&detectors), Some("process phantom-recorder: phantom-recorder is running".to_string()));
+        assert_eq!(first_busy(&detectors[2..]), None);
+
+        let invalid = BusyConfig { detectors: vec![BusyDetectorConfig::Process { name: "app".to_string() }], ..BusyConfig::default() };
+        assert!(invalid.validate().is_err());
+    }
+}
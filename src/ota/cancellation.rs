@@ -0,0 +1,84 @@
+use hyper::Uri;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
+
+pub const CANCELLED_MESSAGE: &str = "Update was cancelled by a local request";
+
+// Shared between the OTA thread and the REST listener, which can't reach the OTA thread through the channel mid run
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    running: Arc<AtomicBool>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn begin(&self) {
+        self.cancelled.store(false, Ordering::SeqCst);
+        self.running.store(true, Ordering::SeqCst);
+    }
+
+    pub fn finish(&self) {
+        self.running.store(false, Ordering::SeqCst);
+        self.cancelled.store(false, Ordering::SeqCst);
+    }
+
+    // Returns false if there is nothing to cancel
+    pub fn cancel(&self) -> bool {
+        if !self.running.load(Ordering::SeqCst) {
+            return false;
+        }
+        self.cancelled.store(true, Ordering::SeqCst);
+        true
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub async fn cancelled(&self) {
+        while !self.is_cancelled() {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    }
+}
+
+static CANCELLATION_TOKEN: OnceLock<CancellationToken> = OnceLock::new();
+
+pub fn set_cancellation_token(token: CancellationToken) {
+    if CANCELLATION_TOKEN.set(token).is_err() {
+        log::warn!("Cancellation token was already set");
+    }
+}
+
+pub fn cancel_update(_: Uri, _: String) -> Result<String, String> {
+    match CANCELLATION_TOKEN.get() {
+        Some(token) if token.cancel() => {
+            log::warn!("Cancelling the running update");
+            Ok("Cancelling the running update, the progress is kept for the next run\n".to_string())
+        }
+        Some(_) => Err("No update is running\n".to_string()),
+        None => Err("Cancellation is not initialized\n".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancel_only_while_running() {
+        let token = CancellationToken::default();
+        assert!(!token.cancel());
+        assert!(!token.is_cancelled());
+        token.begin();
+        assert!(token.clone().cancel());
+        assert!(token.is_cancelled());
+        token.finish();
+        assert!(!token.is_cancelled());
+    }
+}
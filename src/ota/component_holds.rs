@@ -0,0 +1,204 @@
+use crate::ota::{component_registry::find_component, rest_listener::with_status};
+use chrono::{DateTime, Utc};
+use hyper::Uri;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+};
+
+pub const COMPONENT_HOLDS_FILE: &str = "component_holds";
+
+// Keeps a component at its installed build, update_with_json neither installs nor uninstalls it
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct ComponentHold {
+    pub component: String,
+    #[serde(default)]
+    pub reason: String,
+    // RFC 3339, a hold without expiry stays until it's released
+    #[serde(default)]
+    pub expires: Option<String>,
+    #[serde(default)]
+    pub created: String,
+}
+
+impl ComponentHold {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        match &self.expires {
+            None => false,
+            Some(expires) => match DateTime::parse_from_rfc3339(expires) {
+                Ok(expires) => expires <= now,
+                Err(e) => {
+                    log::warn!("Invalid expiry {} for the {} hold: {}", expires, self.component, e);
+                    false
+                }
+            },
+        }
+    }
+}
+
+// An empty path keeps the holds in memory only (used by tests)
+#[derive(Default)]
+pub struct ComponentHolds {
+    path: PathBuf,
+    holds: Mutex<Vec<ComponentHold>>,
+}
+
+impl ComponentHolds {
+    pub fn new(path: PathBuf) -> Self {
+        let holds = match fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+                log::error!("Failed to parse component holds {}: {}", path.to_string_lossy(), e);
+                vec![]
+            }),
+            Err(_) => vec![],
+        };
+        Self { path, holds: Mutex::new(holds) }
+    }
+
+    fn save(&self, holds: &[ComponentHold]) -> Result<(), String> {
+        if self.path == PathBuf::default() {
+            return Ok(());
+        }
+        let content = serde_json::to_string_pretty(holds).map_err(|e| e.to_string())?;
+        let temp_path = self.path.with_extension("tmp");
+        File::create(&temp_path)
+            .and_then(|mut file| {
+                file.write_all(content.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&temp_path, &self.path))
+            .map_err(|e| format!("Failed to save component holds: {}", e))
+    }
+
+    // Expired holds are dropped on the way
+    pub fn active(&self) -> Vec<ComponentHold> {
+        let mut holds = self.holds.lock().unwrap();
+        let now = Utc::now();
+        if holds.iter().any(|hold| hold.is_expired(now)) {
+            holds.retain(|hold| {
+                let expired = hold.is_expired(now);
+                if expired {
+                    log::info!("Hold on {} expired", hold.component);
+                }
+                !expired
+            });
+            if let Err(e) = self.save(&holds) {
+                log::error!("{}", e);
+            }
+        }
+        holds.clone()
+    }
+
+    pub fn held_components(&self) -> Vec<String> {
+        self.active().into_iter().map(|hold| hold.component).collect()
+    }
+
+    pub fn hold(&self, hold: ComponentHold) -> Result<(), String> {
+        if hold.component.is_empty() {
+            return Err(with_status(400, "No component to hold"));
+        }
+        if find_component(&hold.component).is_none() {
+            return Err(with_status(400, format!("Unknown component {}", hold.component)));
+        }
+        if hold.is_expired(Utc::now()) {
+            return Err(with_status(400, format!("Hold on {} is already expired", hold.component)));
+        }
+        let hold = ComponentHold {
+            created: Utc::now().to_rfc3339(),
+            ..hold
+        };
+        let mut holds = self.holds.lock().unwrap();
+        holds.retain(|existing| existing.component != hold.component);
+        log::info!("Holding {} ({})", hold.component, hold.reason);
+        holds.push(hold);
+        self.save(&holds)
+    }
+
+    pub fn release(&self, component: &str) -> Result<(), String> {
+        let mut holds = self.holds.lock().unwrap();
+        let count = holds.len();
+        holds.retain(|hold| hold.component != component);
+        if holds.len() == count {
+            return Err(with_status(404, format!("{} is not held", component)));
+        }
+        log::info!("Released the hold on {}", component);
+        self.save(&holds)
+    }
+}
+
+static COMPONENT_HOLDS: OnceLock<ComponentHolds> = OnceLock::new();
+
+pub fn set_component_holds(path: PathBuf) {
+    if COMPONENT_HOLDS.set(ComponentHolds::new(path)).is_err() {
+        log::warn!("Component holds were already set");
+    }
+}
+
+pub fn active_component_holds() -> Vec<ComponentHold> {
+    match COMPONENT_HOLDS.get() {
+        Some(holds) => holds.active(),
+        None => vec![],
+    }
+}
+
+pub fn held_components() -> Vec<String> {
+    active_component_holds().into_iter().map(|hold| hold.component).collect()
+}
+
+fn component_holds() -> Result<&'static ComponentHolds, String> {
+    COMPONENT_HOLDS.get().ok_or("Component holds are not initialized".to_string())
+}
+
+pub fn get_holds(_: Uri, _: String) -> Result<String, String> {
+    serde_json::to_string_pretty(&component_holds()?.active()).map_err(|e| e.to_string())
+}
+
+// Body is {"component": "oden_player", "reason": "...", "expires": "2024-01-01T00:00:00Z"}
+pub fn hold_component(_: Uri, body: String) -> Result<String, String> {
+    let hold: ComponentHold = serde_json::from_str(&body).map_err(|e| with_status(400, format!("Cannot parse hold: {}", e)))?;
+    let component = hold.component.clone();
+    component_holds()?.hold(hold)?;
+    Ok(format!("{} is held\n", component))
+}
+
+// release/<component>
+pub fn release_component(uri: Uri, _: String) -> Result<String, String> {
+    let parts = uri.path().split('/').collect::<Vec<&str>>();
+    let component = match parts.get(2) {
+        Some(component) if !component.is_empty() => *component,
+        _ => return Err(with_status(400, "No component to release")),
+    };
+    component_holds()?.release(component)?;
+    Ok(format!("{} is released\n", component))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn expired_holds_are_dropped() {
+        let holds = ComponentHolds::default();
+        let hold = |component: &str, expires: Option<String>| ComponentHold {
+            component: component.to_string(),
+            reason: "field test".to_string(),
+            expires,
+            created: String::default(),
+        };
+        holds.hold(hold("oden_player", None)).unwrap();
+        holds.hold(hold("core", Some((Utc::now() + Duration::hours(1)).to_rfc3339()))).unwrap();
+        assert!(holds.hold(hold("vapp", Some((Utc::now() - Duration::hours(1)).to_rfc3339()))).is_err());
+        assert_eq!(holds.hold(hold("no_such_component", None)), Err(with_status(400, "Unknown component no_such_component")));
+        assert_eq!(holds.held_components(), vec!["oden_player".to_string(), "core".to_string()]);
+
+        holds.holds.lock().unwrap()[1].expires = Some((Utc::now() - Duration::seconds(1)).to_rfc3339());
+        assert_eq!(holds.held_components(), vec!["oden_player".to_string()]);
+        assert!(holds.release("core").is_err());
+        holds.release("oden_player").unwrap();
+        assert!(holds.active().is_empty());
+    }
+}
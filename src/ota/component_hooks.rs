@@ -0,0 +1,134 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+// Seconds a hook gets before it's killed
+const DEFAULT_HOOK_TIMEOUT: u64 = 120;
+
+fn default_timeout() -> u64 { DEFAULT_HOOK_TIMEOUT }
+
+// Shell command lines, run around the installer of the component:
+//"hooks":{
+//       "pre_install":"systemctl stop phantom-core",
+//       "post_install":"/opt/phantom/migrate_config.sh",
+//       "post_uninstall":"rm -rf /var/cache/phantom",
+//       "timeout":60
+//    }
+// A failing pre-hook aborts the component, a failing post-hook rolls it back.
+
+pub type HookExec = fn(command: &str, timeout: Duration) -> Result<String, String>;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum HookStage {
+    PreInstall,
+    PostInstall,
+    PreUninstall,
+    PostUninstall,
+}
+
+impl HookStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HookStage::PreInstall => "pre_install",
+            HookStage::PostInstall => "post_install",
+            HookStage::PreUninstall => "pre_uninstall",
+            HookStage::PostUninstall => "post_uninstall",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct ComponentHooks {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_install: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_install: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_uninstall: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_uninstall: Option<String>,
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+impl Default for ComponentHooks {
+    fn default() -> Self {
+        Self { pre_install: None, post_install: None, pre_uninstall: None, post_uninstall: None, timeout: DEFAULT_HOOK_TIMEOUT }
+    }
+}
+
+// Kept in the update history
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct HookOutput {
+    pub component: String,
+    pub stage: HookStage,
+    pub command: String,
+    pub success: bool,
+    pub output: String,
+}
+
+impl ComponentHooks {
+    pub fn is_empty(&self) -> bool {
+        self.pre_install.is_none() && self.post_install.is_none() && self.pre_uninstall.is_none() && self.post_uninstall.is_none()
+    }
+
+    pub fn command(&self, stage: HookStage) -> Option<&String> {
+        match stage {
+            HookStage::PreInstall => self.pre_install.as_ref(),
+            HookStage::PostInstall => self.post_install.as_ref(),
+            HookStage::PreUninstall => self.pre_uninstall.as_ref(),
+            HookStage::PostUninstall => self.post_uninstall.as_ref(),
+        }
+    }
+
+    // None when the component has no hook for the stage
+    pub fn run(&self, component: &str, stage: HookStage, exec: HookExec) -> Option<HookOutput> {
+        let command = self.command(stage).filter(|command| !command.trim().is_empty())?;
+        log::info!("Running {} {} hook: {}", component, stage.as_str(), command);
+        let (success, output) = match exec(command, Duration::from_secs(self.timeout)) {
+            Ok(output) => (true, output.trim().to_string()),
+            Err(output) => (false, output.trim().to_string()),
+        };
+        for line in output.lines() {
+            if success {
+                log::info!("[{} {}] {}", component, stage.as_str(), line);
+            } else {
+                log::error!("[{} {}] {}", component, stage.as_str(), line);
+            }
+        }
+        if !success {
+            log::error!("{} {} hook failed", component, stage.as_str());
+        }
+        Some(HookOutput { component: component.to_string(), stage, command: command.clone(), success, output })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hooks_run_by_stage() {
+        let hooks: ComponentHooks = serde_json::from_str(r#"{ "pre_install":"stop", "post_install":"fail" }"#).unwrap();
+        assert_eq!(hooks.timeout, DEFAULT_HOOK_TIMEOUT);
+        let exec: HookExec = |command, _timeout| if command == "fail" { Err("exit status 1: broken\n".to_string()) } else { Ok("stopped\n".to_string()) };
+
+        let output = hooks.run("core", HookStage::PreInstall, exec).unwrap();
+        assert!(output.success);
+        assert_eq!(output.output, "stopped");
+        let output = hooks.run("core", HookStage::PostInstall, exec).unwrap();
+        assert!(!output.success);
+        assert_eq!(output.output, "exit status 1: broken");
+        assert!(hooks.run("core", HookStage::PreUninstall, exec).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hook_timeout() {
+        use crate::utils::bash_exec::BashExec;
+        assert_eq!(BashExec::exec_shell_timeout("echo one; echo two >&2", Duration::from_secs(5)), Ok("one\ntwo\n".to_string()));
+        assert!(BashExec::exec_shell_timeout("exit 3", Duration::from_secs(5)).is_err());
+        let error = BashExec::exec_shell_timeout("sleep 5", Duration::from_millis(300)).unwrap_err();
+        assert!(error.contains("timed out"), "{}", error);
+    }
+}
@@ -0,0 +1,178 @@
+use crate::ota::{install_manager::sort_by_package, manifest::Component, ota_error::OTAError};
+use std::collections::{HashMap, HashSet};
+
+// "oden_plugin": { ..., "depends_on":["oden_player"], "conflicts_with":["oden_legacy_plugin"] }
+// Dependencies are installed before their dependents and uninstalled after them. Names that are not in the
+// manifest are ignored, among independent components the legacy order (agent first, archives last) still holds.
+
+// Before the download only the link is set, see UpdatePlan
+fn installs(component: &Component) -> bool {
+    !component.updated && (component.path.is_some() || component.link.is_some())
+}
+
+fn uninstalls(component: &Component) -> bool {
+    !component.updated && component.path.is_none() && component.link.is_none()
+}
+
+fn installed_after_cycle(component: &Component) -> bool {
+    installs(component) || (component.updated && component.currently_installed())
+}
+
+fn changes(component: &Component) -> bool {
+    !component.updated
+}
+
+// Topological sort, fails on a dependency cycle
+pub fn dependency_order(components: Vec<Component>) -> Result<Vec<Component>, OTAError> {
+    let names: HashSet<String> = components.iter().map(|component| component.component.clone()).collect();
+    let mut waiting_for: HashMap<String, HashSet<String>> = components
+        .iter()
+        .map(|component| {
+            let dependencies = component
+                .depends_on
+                .iter()
+                .filter(|dependency| names.contains(*dependency) && **dependency != component.component)
+                .cloned()
+                .collect();
+            (component.component.clone(), dependencies)
+        })
+        .collect();
+    let mut remaining = components;
+    let mut ordered = Vec::with_capacity(remaining.len());
+    while !remaining.is_empty() {
+        let next = remaining
+            .iter()
+            .enumerate()
+            .filter(|(_, component)| waiting_for[&component.component].is_empty())
+            .min_by(|(_, c1), (_, c2)| sort_by_package(c1, c2).then(c1.component.cmp(&c2.component)))
+            .map(|(index, _)| index);
+        let component = match next {
+            Some(index) => remaining.remove(index),
+            None => {
+                let mut cycle: Vec<&str> = remaining.iter().map(|component| component.component.as_str()).collect();
+                cycle.sort();
+                return Err(OTAError::dependency(format!("Dependency cycle between [{}]", cycle.join(", "))));
+            }
+        };
+        for dependencies in waiting_for.values_mut() {
+            dependencies.remove(&component.component);
+        }
+        ordered.push(component);
+    }
+    Ok(ordered)
+}
+
+// Uninstalls go first, dependents before their dependencies, then the rest in dependency order
+pub fn install_order(components: Vec<Component>) -> Result<Vec<Component>, OTAError> {
+    let (mut uninstalls, others): (Vec<Component>, Vec<Component>) =
+        dependency_order(components)?.into_iter().partition(uninstalls);
+    uninstalls.reverse();
+    uninstalls.extend(others);
+    Ok(uninstalls)
+}
+
+// Dependents are rolled back before what they depend on
+pub fn rollback_order(components: Vec<Component>) -> Vec<Component> {
+    let mut fallback = components.clone();
+    match dependency_order(components) {
+        Ok(mut ordered) => {
+            ordered.reverse();
+            ordered
+        }
+        Err(e) => {
+            log::warn!("{}, rolling back in package order", e.message);
+            fallback.sort_by(sort_by_package);
+            fallback
+        }
+    }
+}
+
+// Only rules touching a component this cycle changes are checked, so an old inconsistency doesn't block every update
+pub fn check_constraints(components: &[Component]) -> Result<(), OTAError> {
+    let by_name: HashMap<&str, &Component> = components.iter().map(|component| (component.component.as_str(), component)).collect();
+    let mut violations = vec![];
+    for component in components.iter().filter(|component| installed_after_cycle(component)) {
+        for dependency in &component.depends_on {
+            match by_name.get(dependency.as_str()) {
+                Some(other) if !installed_after_cycle(other) && (changes(component) || changes(other)) => {
+                    violations.push(format!("{} depends on {} which will not be installed", component.component, dependency));
+                }
+                Some(_) => {}
+                None => log::warn!("{} depends on {} which is not in the manifest", component.component, dependency),
+            }
+        }
+        for conflict in &component.conflicts_with {
+            if let Some(other) = by_name.get(conflict.as_str()) {
+                if installed_after_cycle(other) && (changes(component) || changes(other)) {
+                    violations.push(format!("{} conflicts with {}", component.component, conflict));
+                }
+            }
+        }
+    }
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        violations.sort();
+        violations.dedup();
+        Err(OTAError::dependency(violations.join(", ")))
+    }
+}
+
+// First of the given components that the component depends on
+pub fn depends_on_any<'a>(component: &Component, names: &'a [String]) -> Option<&'a String> {
+    names.iter().find(|name| component.depends_on.contains(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn component(name: &str, package_type: &str, depends_on: &[&str]) -> Component {
+        Component {
+            component: name.to_string(),
+            package_type: package_type.to_string(),
+            path: Some(PathBuf::from(name)),
+            depends_on: depends_on.iter().map(|name| name.to_string()).collect(),
+            ..Component::empty()
+        }
+    }
+
+    fn names(components: &[Component]) -> Vec<&str> {
+        components.iter().map(|component| component.component.as_str()).collect()
+    }
+
+    #[test]
+    fn dependencies_order_the_install() {
+        let components = vec![
+            component("oden_webview", "tar", &["oden_plugin"]),
+            component("oden_plugin", "tar", &["oden_player"]),
+            component("oden_player", "deb", &[]),
+            component("core", "snap", &[]),
+            Component { path: None, ..component("log2jira", "snap", &[]) },
+        ];
+        let ordered = install_order(components.clone()).unwrap();
+        assert_eq!(names(&ordered), vec!["log2jira", "core", "oden_player", "oden_plugin", "oden_webview"]);
+        assert_eq!(names(&rollback_order(components.clone())), vec!["oden_webview", "oden_plugin", "oden_player", "log2jira", "core"]);
+
+        let mut cyclic = components;
+        cyclic[2].depends_on = vec!["oden_webview".to_string()];
+        let error = dependency_order(cyclic).unwrap_err();
+        assert_eq!(error.code(), "OTA-DEPENDENCY");
+        assert_eq!(error.message, "Dependency cycle between [oden_player, oden_plugin, oden_webview]");
+    }
+
+    #[test]
+    fn constraints() {
+        let player = Component { path: None, ..component("oden_player", "deb", &[]) };
+        let plugin = component("oden_plugin", "tar", &["oden_player"]);
+        assert!(check_constraints(&[player.clone(), plugin.clone()]).is_err());
+
+        let installed_player = Component { updated: true, checksum: "abc".to_string(), ..player };
+        assert!(check_constraints(&[installed_player.clone(), plugin.clone()]).is_ok());
+
+        let legacy = Component { conflicts_with: vec!["oden_plugin".to_string()], ..installed_player.clone() };
+        let error = check_constraints(&[legacy, plugin]).unwrap_err();
+        assert_eq!(error.message, "oden_player conflicts with oden_plugin");
+    }
+}
@@ -0,0 +1,226 @@
+use crate::ota::manifest::{Component, ComponentType};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    sync::{OnceLock, RwLock},
+};
+
+pub const COMPONENT_REGISTRY_FILE: &str = "component_registry";
+
+// Shipped with the agent, a component_registry file in the common directory adds to it or overrides it,
+// and the server manifest can add components the agent doesn't know yet:
+// "new_component": { "meta":false, "package_type":"tar", "target_path":"/opt/new", "processes":["new"], "legacy_snap_names":[] }
+// Attributes that differ between node types go under "operator" or "vehicle":
+// "oden_plugin": { "package_type":"tar", "operator":{ "target_path":"/opt/phantom-client" }, "vehicle":{ ... } }
+// This is the only place component attributes come from, the builtin baselines only list components and versions.
+#[cfg(unix)]
+const BUILTIN_REGISTRY: &str = include_str!("component_registry.unix.json");
+#[cfg(windows)]
+const BUILTIN_REGISTRY: &str = include_str!("component_registry.windows.json");
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, Debug)]
+pub struct ComponentInfo {
+    // Meta components are shared by all servers in the hash manifest
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub meta: bool,
+    #[serde(default)]
+    pub package_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub processes: Vec<String>,
+    // Snaps of older packagings, removed on uninstall even when there is no downloaded package to get the name from
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub legacy_snap_names: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operator: Option<NodeComponentInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vehicle: Option<NodeComponentInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, Debug)]
+pub struct NodeComponentInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub processes: Vec<String>,
+}
+
+impl ComponentInfo {
+    // The attributes of the node type win over the common ones
+    pub fn for_node(&self, operator: bool) -> ComponentInfo {
+        let node = if operator { &self.operator } else { &self.vehicle };
+        let Some(node) = node else { return self.clone() };
+        ComponentInfo {
+            target_path: node.target_path.clone().or_else(|| self.target_path.clone()),
+            processes: if node.processes.is_empty() { self.processes.clone() } else { node.processes.clone() },
+            ..self.clone()
+        }
+    }
+
+    // Values the manifest gives win, the registry only fills the blanks
+    pub fn apply_to(&self, component: Component) -> Component {
+        Component {
+            package_type: if component.package_type.is_empty() { self.package_type.clone() } else { component.package_type },
+            target_path: component.target_path.filter(|path| !path.as_os_str().is_empty()).or_else(|| self.target_path.clone()),
+            processes: if component.processes.is_empty() { self.processes.clone() } else { component.processes },
+            ..component
+        }
+    }
+}
+
+// Registry attributes as they come in the server manifest, next to the component fields
+#[derive(Deserialize)]
+pub struct ServerComponentInfo {
+    pub component: String,
+    #[serde(flatten)]
+    pub info: ComponentInfo,
+}
+
+struct ComponentRegistry {
+    // Only registered names are leaked, once each, so ComponentType stays Copy
+    components: HashMap<&'static str, ComponentInfo>,
+    // Not in the builtin registry, these are what the registry file keeps
+    extended: HashSet<&'static str>,
+    path: PathBuf,
+}
+
+impl ComponentRegistry {
+    fn builtin() -> Self {
+        let builtin: HashMap<String, ComponentInfo> = serde_json::from_str(BUILTIN_REGISTRY).unwrap_or_else(|e| {
+            log::error!("Failed to parse the builtin component registry: {}", e);
+            HashMap::new()
+        });
+        let mut registry = Self { components: HashMap::new(), extended: HashSet::new(), path: PathBuf::default() };
+        for (name, info) in builtin {
+            registry.insert(&name, info);
+        }
+        registry
+    }
+
+    fn insert(&mut self, name: &str, info: ComponentInfo) -> &'static str {
+        let name = match self.components.get_key_value(name) {
+            Some((name, _)) => *name,
+            None => Box::leak(name.to_string().into_boxed_str()),
+        };
+        self.components.insert(name, info);
+        name
+    }
+
+    fn save(&self) {
+        if self.path == PathBuf::default() {
+            return;
+        }
+        let extended: HashMap<&str, &ComponentInfo> = self.extended.iter().map(|name| (*name, &self.components[name])).collect();
+        let result = serde_json::to_string_pretty(&extended)
+            .map_err(|e| e.to_string())
+            .and_then(|json| fs::write(&self.path, json).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            log::error!("Failed to save the component registry into {}: {}", self.path.to_string_lossy(), e);
+        }
+    }
+}
+
+static COMPONENT_REGISTRY: OnceLock<RwLock<ComponentRegistry>> = OnceLock::new();
+
+fn registry() -> &'static RwLock<ComponentRegistry> {
+    COMPONENT_REGISTRY.get_or_init(|| RwLock::new(ComponentRegistry::builtin()))
+}
+
+// Called once on startup before any manifest is read, the file is also where components learned from the server are kept
+pub fn load_component_registry(path: PathBuf) {
+    let mut registry = registry().write().unwrap_or_else(|poisoned| poisoned.into_inner());
+    match fs::read_to_string(&path) {
+        Ok(text) => match serde_json::from_str::<HashMap<String, ComponentInfo>>(&text) {
+            Ok(components) => {
+                for (name, info) in components {
+                    log::info!("Component registry: {} from {}", name, path.to_string_lossy());
+                    let name = registry.insert(&name, info);
+                    registry.extended.insert(name);
+                }
+            }
+            Err(e) => log::error!("Failed to parse the component registry {}: {}, ignoring it", path.to_string_lossy(), e),
+        },
+        Err(_) => log::info!("No component registry at {}, using the builtin one", path.to_string_lossy()),
+    }
+    registry.path = path;
+}
+
+// Known components keep their attributes, the server can only add new ones
+pub fn register_server_components(components: Vec<ServerComponentInfo>) {
+    let mut registry = registry().write().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut added = false;
+    for ServerComponentInfo { component, info } in components {
+        if registry.components.contains_key(component.as_str()) || info.package_type.is_empty() {
+            continue;
+        }
+        log::info!("Component registry: {} added by the server manifest", component);
+        let name = registry.insert(&component, info);
+        registry.extended.insert(name);
+        added = true;
+    }
+    if added {
+        registry.save();
+    }
+}
+
+pub fn find_component(name: &str) -> Option<&'static str> {
+    let registry = registry().read().unwrap_or_else(|poisoned| poisoned.into_inner());
+    registry.components.get_key_value(name).map(|(name, _)| *name)
+}
+
+pub fn component_info(component_type: &ComponentType) -> ComponentInfo {
+    let registry = registry().read().unwrap_or_else(|poisoned| poisoned.into_inner());
+    registry.components.get(component_type.name()).cloned().unwrap_or_default()
+}
+
+// Components with registry attributes, in name order
+pub fn registered_components() -> Vec<(ComponentType, ComponentInfo)> {
+    let registry = registry().read().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut components: Vec<(ComponentType, ComponentInfo)> = registry
+        .components
+        .iter()
+        .filter(|(_, info)| **info != ComponentInfo::default())
+        .map(|(name, info)| (ComponentType::from_static(name), info.clone()))
+        .collect();
+    components.sort_by(|(c1, _), (c2, _)| c1.name().cmp(c2.name()));
+    components
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn server_adds_components() {
+        assert!(component_info(&ComponentType::phantom_agent).meta);
+        assert!(ComponentType::from_str("registry_test_component").is_err());
+        assert!(serde_json::from_str::<ComponentType>(r#""registry_test_component""#).is_err());
+        let plugin = component_info(&ComponentType::oden_plugin);
+        assert_ne!(plugin.for_node(true).target_path, plugin.for_node(false).target_path);
+        assert_eq!(plugin.for_node(true).package_type, "tar");
+
+        let json = r#"[
+            { "component":"registry_test_component", "checksum":"abc", "package_type":"tar", "target_path":"/opt/test", "meta":true },
+            { "component":"phantom_agent", "checksum":"abc", "package_type":"tar" }
+        ]"#;
+        register_server_components(serde_json::from_str(json).unwrap());
+
+        let component_type = ComponentType::from_str("registry_test_component").unwrap();
+        let info = component_info(&component_type);
+        assert!(info.meta);
+        assert_eq!(info.target_path, Some(PathBuf::from("/opt/test")));
+        assert_eq!(component_info(&ComponentType::phantom_agent).package_type, "snap");
+
+        let component = info.apply_to(Component { component: "registry_test_component".to_string(), ..Component::empty() });
+        assert_eq!(component.package_type, "tar");
+        assert_eq!(component.target_path, Some(PathBuf::from("/opt/test")));
+    }
+}
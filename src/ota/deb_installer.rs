@@ -79,7 +79,7 @@ impl DebInstaller {
     ) -> Result<String, OTAError> {
         let path = component.path.clone().unwrap_or_default();
         if !path.exists() {
-            return Err(OTAError::nonfatal(format!(
+            return Err(OTAError::installer("deb", format!(
                 "{} not found",
                 path.to_string_lossy()
             )));
@@ -101,7 +101,7 @@ impl DebInstaller {
                     log::warn!("Recovery was not successful: {}", e);
                 }
             } else {
-                return Err(OTAError::nonfatal(e));
+                return Err(OTAError::installer("deb", e));
             }
         }
         match DebInstaller::check_installed_version(&name) {
@@ -110,10 +110,10 @@ impl DebInstaller {
                     Ok("Install completed successfully".to_string())
                 }
                 else {
-                    Err(OTAError::nonfatal(format!("Install did not succeed, expected {}, but version is {}", version, installed_version)))
+                    Err(OTAError::installer("deb", format!("Install did not succeed, expected {}, but version is {}", version, installed_version)))
                 }
             }
-            None => { Err(OTAError::nonfatal("Install did not succeed, could not check version".to_string())) }
+            None => { Err(OTAError::installer("deb", "Install did not succeed, could not check version".to_string())) }
         }
     }
 
@@ -154,7 +154,7 @@ impl DebInstaller {
                     "Failed to remove".red(false),
                     &package_name.red(true)
                 );
-                Err(OTAError::nonfatal(e))
+                Err(OTAError::installer("deb", e))
             }
             Ok(res) => {
                 if DebInstaller::check_installed_version(package_name).is_none() {
@@ -170,7 +170,7 @@ impl DebInstaller {
                         "Failed to remove".red(false),
                         &package_name.red(true)
                     );
-                    Err(OTAError::nonfatal("Could not validate uninstallation".to_string()))
+                    Err(OTAError::installer("deb", "Could not validate uninstallation".to_string()))
                 }
             }
         }
@@ -1,4 +1,4 @@
-use crate::{ota::manifest::Manifest, rest_request::RestServer};
+use crate::{ota::manifest::{Component, Manifest}, rest_request::RestServer};
 use log;
 use sysinfo::{DiskExt, RefreshKind, System, SystemExt};
 use url::Url;
@@ -30,8 +30,12 @@ impl DiskSpaceVerifier {
         bytes / 2_u64.pow(20)
     }
     pub fn verify(&self, manifest: &Manifest) -> Result<bool, String> {
+        Ok(self.has_space_for(self.get_components_total_size(manifest)?))
+    }
+
+    pub fn has_space_for(&self, download_size: u64) -> bool {
         const MIN_DISK_SPACE: u64 = DiskSpaceVerifier::get_min_disk_space();
-        let required_space = MIN_DISK_SPACE + self.get_components_total_size(manifest)?;
+        let required_space = MIN_DISK_SPACE + download_size;
         let available_space = self.disk_space;
         log::info!(
             "Required space for downloading components is {}MB\n Available space is {}MB",
@@ -39,7 +43,7 @@ impl DiskSpaceVerifier {
             Self::bytes_to_megabytes(available_space)
         );
 
-        Ok(available_space > required_space)
+        available_space > required_space
     }
 
     const fn get_min_disk_space() -> u64 {
@@ -54,27 +58,31 @@ impl DiskSpaceVerifier {
             .components
             .iter()
             .try_fold(0, |acc, (_, component)| {
-                if component.updated {
-                    return Ok(acc);
-                }
-                match (&component.link, &component.token) {
-                    (Some(link), Some(token)) => {
-                        let token = format!("Bearer {token}");
-                        let file_size = (self.get_remote_file_size)(link, Some(token))?;
-                        log::info!(
-                            "The size of {} is {}MB",
-                            component.component,
-                            Self::bytes_to_megabytes(file_size)
-                        );
-                        Ok(acc + file_size)
-                    }
-                    (None, None) => {
-                        Ok(acc) // Probably uninstall
-                    }
-                    _ => Err("Can't sum components size, no link or token".to_string()),
-                }
+                Ok(acc + self.get_component_size(component)?)
             })
     }
+
+    pub fn get_component_size(&self, component: &Component) -> Result<u64, String> {
+        if component.updated {
+            return Ok(0);
+        }
+        match (&component.link, &component.token) {
+            (Some(link), Some(token)) => {
+                let token = format!("Bearer {token}");
+                let file_size = (self.get_remote_file_size)(link, Some(token))?;
+                log::info!(
+                    "The size of {} is {}MB",
+                    component.component,
+                    Self::bytes_to_megabytes(file_size)
+                );
+                Ok(file_size)
+            }
+            (None, None) => {
+                Ok(0) // Probably uninstall
+            }
+            _ => Err("Can't sum components size, no link or token".to_string()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -4,26 +4,37 @@ use crate::{config::{get_arch, ArchType}, ota::{
     ota_error::OTAError,
     service_control_trait::SystemControlTrait,
 }, rest_comm::coupling_submit_trait::CouplingRestSubmitter, rest_request::{DownloadStats, RestServer}};
+use crate::ota::artifact_store::ArtifactStore;
+use crate::ota::bandwidth::bandwidth_limiter;
+use crate::ota::cancellation::{CancellationToken, CANCELLED_MESSAGE};
 use crate::ota::ota_status::OTAStatus;
+use crate::ota::peer_cache::{download_from_peers, PeerCacheConfig};
+use crate::ota::update_history::{component_records, ComponentRecord};
+use crate::ota::update_journal::{JournalStep, UpdateJournal};
+use crate::ota::update_plan::UpdatePlan;
+use crate::utils::file_utils::get_checksum_like;
+use crate::utils::log_utils::size_as_string;
 use futures_util::future::join_all;
 use log;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::str::FromStr;
 use std::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
     collections::HashMap,
     path::{Path, PathBuf},
     str,
     sync::{Arc, Mutex},
     thread::sleep,
-    time::Duration,
+    time::{Duration, Instant},
 };
 use url::Url;
 
 
 const DOWNLOAD_ATTEMPTS: i32 = 5;
 
+type DownloadFuture = std::pin::Pin<Box<dyn std::future::Future<Output=Result<(), OTAError>>>>;
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct CheckSums {
     checksums: HashMap<ComponentType, String>,
@@ -36,23 +47,33 @@ pub struct Arch {
 }
 
 pub struct DownloadManager<'a, A: SystemControlTrait> {
-    _system_control: &'a RefCell<A>,
+    system_control: &'a RefCell<A>,
     coupling_rest_submitter: &'a dyn CouplingRestSubmitter,
     dest_path: PathBuf,
     disk_space_verifier: Option<DiskSpaceVerifier>,
     update_ota_status: fn(OTAStatus, Option<String>),
+    journal: UpdateJournal,
+    cancellation: CancellationToken,
+    downloaded_bytes: Cell<u64>,
+    download_started: Cell<Option<Instant>>,
+    // What the server diff asked for, kept for the update history
+    cycle_components: RefCell<Vec<ComponentRecord>>,
+    // Agents on the LAN that are asked for an artifact before its link
+    peers: PeerCacheConfig,
+    // Artifacts downloaded before, by checksum
+    artifacts: Option<ArtifactStore>,
 }
 
 async fn report_eta(url: Url, token: String, update_ota_status:  fn(OTAStatus, Option<String>),
-    stats_ptr: Arc<Mutex<DownloadStats>>
-) -> bool {
-    while stats_ptr.lock().unwrap().download_count != 0 {
+    stats_ptr: Arc<Mutex<DownloadStats>>, cancellation: CancellationToken
+) -> Result<(), OTAError> {
+    while stats_ptr.lock().unwrap().download_count != 0 && !cancellation.is_cancelled() {
         let eta = stats_ptr.lock().unwrap().eta;
         update_ota_status(OTAStatus::DOWNLOADING(eta), None);
         RestServer::report_eta(url.clone(), &token, eta).await;
         tokio::time::sleep(Duration::from_secs(5)).await;
     }
-    true
+    Ok(())
 }
 
 async fn download(
@@ -62,18 +83,26 @@ async fn download(
     token: String,
     stats_ptr: Arc<Mutex<DownloadStats>>,
     callback: fn(&str, u64, u64, Arc<Mutex<DownloadStats>>),
-) -> bool {
+    cancellation: CancellationToken,
+) -> Result<(), OTAError> {
     let mut attempt = 1;
     loop {
-        let result = RestServer::download_file_with_callback(
-            &url,
-            path.clone(),
-            checksum.clone(),
-            &token,
-            stats_ptr.clone(),
-            callback,
-        )
-        .await;
+        let result = tokio::select! {
+            result = RestServer::download_file_with_callback(
+                &url,
+                path.clone(),
+                checksum.clone(),
+                &token,
+                stats_ptr.clone(),
+                callback,
+            ) => result,
+            // Dropping the download leaves the partial file in place, so the next run resumes it
+            _ = cancellation.cancelled() => {
+                log::warn!("Download of {} was cancelled", path.to_string_lossy());
+                stats_ptr.lock().unwrap().dec_download_count();
+                return Err(OTAError::cancelled(CANCELLED_MESSAGE.to_string()));
+            }
+        };
         match result {
             Err(e) => {
                 log::warn!(
@@ -82,10 +111,10 @@ async fn download(
                     path.to_string_lossy(),
                     e
                 );
-                if attempt >= DOWNLOAD_ATTEMPTS {
+                if attempt >= DOWNLOAD_ATTEMPTS || cancellation.is_cancelled() {
                     log::error!("Giving up on the download for {}", path.to_string_lossy());
                     stats_ptr.lock().unwrap().dec_download_count();
-                    return false;
+                    return Err(e);
                 }
                 sleep(Duration::from_secs(5));
                 attempt += 1;
@@ -99,19 +128,50 @@ async fn download(
                     s
                 );
                 stats_ptr.lock().unwrap().dec_download_count();
-                return true;
+                return Ok(());
             }
         }
     }
 }
 
+// The link is only used when no peer has the artifact
+#[allow(clippy::too_many_arguments)]
+async fn download_with_peers(
+    peer_urls: Vec<Url>,
+    url: Url,
+    path: PathBuf,
+    checksum: String,
+    token: String,
+    stats_ptr: Arc<Mutex<DownloadStats>>,
+    callback: fn(&str, u64, u64, Arc<Mutex<DownloadStats>>),
+    cancellation: CancellationToken,
+) -> Result<(), OTAError> {
+    if !peer_urls.is_empty() {
+        let from_peers = tokio::select! {
+            from_peers = download_from_peers(&peer_urls, &path, &checksum, stats_ptr.clone(), callback) => from_peers,
+            _ = cancellation.cancelled() => {
+                log::warn!("Download of {} was cancelled", path.to_string_lossy());
+                stats_ptr.lock().unwrap().dec_download_count();
+                return Err(OTAError::cancelled(CANCELLED_MESSAGE.to_string()));
+            }
+        };
+        if from_peers {
+            stats_ptr.lock().unwrap().dec_download_count();
+            return Ok(());
+        }
+        log::info!("No peer has {}, downloading from {}", path.to_string_lossy(), url);
+    }
+    download(url, path, Some(checksum), token, stats_ptr, callback, cancellation).await
+}
+
 impl<'a, A: SystemControlTrait> DownloadManager<'a, A> {
     pub fn new(
-        _system_control: &'a RefCell<A>,
+        system_control: &'a RefCell<A>,
         coupling_rest_submitter: &'a dyn CouplingRestSubmitter,
         dest_path: PathBuf,
         update_ota_status: fn(OTAStatus, Option<String>),
-
+        journal: UpdateJournal,
+        cancellation: CancellationToken,
     ) -> Result<Self, String> {
         let disk_space_verifier = match DiskSpaceVerifier::new() {
             Ok(verifier) => Some(verifier),
@@ -121,21 +181,36 @@ impl<'a, A: SystemControlTrait> DownloadManager<'a, A> {
             }
         };
         Ok(Self {
-            _system_control,
+            system_control,
             coupling_rest_submitter,
             dest_path,
             disk_space_verifier,
-            update_ota_status
+            update_ota_status,
+            journal,
+            cancellation,
+            downloaded_bytes: Cell::new(0),
+            download_started: Cell::new(None),
+            cycle_components: RefCell::new(vec![]),
+            peers: PeerCacheConfig::default(),
+            artifacts: None,
         })
     }
 
+    pub fn with_peers(self, peers: PeerCacheConfig) -> Self {
+        Self { peers, ..self }
+    }
+
+    pub fn with_artifact_store(self, artifacts: Option<ArtifactStore>) -> Self {
+        Self { artifacts, ..self }
+    }
+
     pub fn run(&self, manifest: Manifest) -> Result<Manifest, OTAError> {
         log::info!("Download Manager running...");
         // post the checksum values of components
-        let server_checksum_response = self.post_empty_checksums()?;
+        let server_checksum_response = self.post_empty_checksums().map_err(OTAError::network)?;
         let json_response: Value = serde_json::from_str(server_checksum_response.as_str()).map_err(|error|{
             log::error!("Failed parsing to JSON with the following error: {error},\n post_checksum response is: {server_checksum_response}");
-            OTAError::nonfatal("Failed parsing post_checksums response".to_string())
+            OTAError::network("Failed parsing post_checksums response".to_string())
         })?;
         let json_response = serde_json::to_string_pretty(&json_response).unwrap();
         log::info!("Response: {json_response}");
@@ -143,6 +218,7 @@ impl<'a, A: SystemControlTrait> DownloadManager<'a, A> {
             log::info!("The response is empty, no download is needed");
             return Ok(manifest);
         }
+        let current_components = manifest.components.clone();
         let manifest_res = manifest.update_with_json(server_checksum_response.as_str());
         if let Err(resp) = manifest_res {
             return Err(OTAError::nonfatal(format!(
@@ -152,12 +228,13 @@ impl<'a, A: SystemControlTrait> DownloadManager<'a, A> {
         let manifest = manifest_res.unwrap();
         #[cfg(test)]
         manifest.display_component_actions();
+        *self.cycle_components.borrow_mut() = component_records(&current_components, &manifest);
         // Check if there is enough disk space for downloading components
         match &self.disk_space_verifier {
             Some(disk_verifier) => match disk_verifier.verify(&manifest) {
                 Ok(result) => {
                     if !result {
-                        return Err(OTAError::fatal(
+                        return Err(OTAError::disk_space(
                             "No available disk space for downloading components".to_string(),
                         ));
                     }
@@ -179,6 +256,42 @@ impl<'a, A: SystemControlTrait> DownloadManager<'a, A> {
         self.download_components(self.dest_path.clone(), manifest)
     }
 
+    pub fn downloaded_bytes(&self) -> u64 {
+        self.downloaded_bytes.get()
+    }
+
+    pub fn cycle_components(&self) -> Vec<ComponentRecord> {
+        self.cycle_components.borrow().clone()
+    }
+
+    // None if the server diff needed no download
+    pub fn download_started(&self) -> Option<Instant> {
+        self.download_started.get()
+    }
+
+    // Same flow as run, stopping before anything is downloaded
+    pub fn plan(&self, manifest: Manifest) -> Result<UpdatePlan, OTAError> {
+        let server_checksum_response = self.post_empty_checksums().map_err(OTAError::network)?;
+        let json_response: Value = serde_json::from_str(server_checksum_response.as_str()).map_err(|error|{
+            log::error!("Failed parsing to JSON with the following error: {error},\n post_checksum response is: {server_checksum_response}");
+            OTAError::network("Failed parsing post_checksums response".to_string())
+        })?;
+        let current_components = manifest.components.clone();
+        let manifest = if matches!(&json_response, Value::Array(components) if components.is_empty()) {
+            manifest
+        } else {
+            manifest.update_with_json(server_checksum_response.as_str()).map_err(|resp| {
+                OTAError::nonfatal(format!("Failed converting server response to json: {resp}"))
+            })?
+        };
+        Ok(UpdatePlan::new(
+            &current_components,
+            &manifest,
+            self.disk_space_verifier.as_ref(),
+            self.system_control,
+        ))
+    }
+
     pub fn _post_checksums(&self, manifest: &Manifest) -> Result<String, String> {
         let check_sums = self._convert_manifest_to_server_manifest(manifest)?;
         let check_sums_json = serde_json::to_value(check_sums).unwrap();
@@ -217,8 +330,13 @@ impl<'a, A: SystemControlTrait> DownloadManager<'a, A> {
         dest_path: PathBuf,
         manifest: Manifest
     ) -> Result<Manifest, OTAError> {
+        self.download_started.set(Some(Instant::now()));
+        match bandwidth_limiter().limit(chrono::Utc::now()) {
+            Some(limit) => log::info!("Downloads are limited to {}/s", size_as_string(limit)),
+            None => log::info!("Downloads are not limited"),
+        }
         let mut components_paths = HashMap::new();
-        let mut futures : Vec<std::pin::Pin<Box<dyn std::future::Future<Output=bool>>>> = vec![];
+        let mut futures : Vec<DownloadFuture> = vec![];
         let mut paths = vec![];
 
         let stats_ptr = Arc::new(Mutex::new(DownloadStats::new()));
@@ -250,43 +368,63 @@ impl<'a, A: SystemControlTrait> DownloadManager<'a, A> {
                         component,
                         paths.get(found_index).expect("Get fail!").0
                     );
-                    return Err(OTAError::fatal(
+                    return Err(OTAError::state_corruption(
                         "Cannot download two components into the same file!".to_string(),
                     ));
                 }
                 paths.push((*component_type, file_full_path.clone()));
+                // Finished at once when a held install already downloaded it, or when the artifact store has it
+                let downloaded = get_checksum_like(&file_full_path, &component.checksum).is_ok_and(|checksum| checksum.eq_ignore_ascii_case(&component.checksum));
+                if downloaded || self.artifacts.as_ref().is_some_and(|artifacts| artifacts.fetch(&component.checksum, &file_full_path)) {
+                    futures.push(Box::pin(async { Ok(()) }));
+                    continue;
+                }
                 stats_ptr.lock().unwrap().update_entry(
                     String::from(file_full_path.to_string_lossy()),
                     0,
                     0
                 );
                 stats_ptr.lock().unwrap().inc_download_count();
+                self.journal.record(&component.component, JournalStep::DownloadStarted, &component.checksum, Some(file_full_path.clone()));
 
-                futures.push(Box::pin(download(link.clone(), file_full_path.clone(), Some(component.checksum.clone()), token, stats_ptr.clone(),
+                futures.push(Box::pin(download_with_peers(self.peers.artifact_urls(&component.checksum), link.clone(), file_full_path.clone(),
+                    component.checksum.clone(), token, stats_ptr.clone(),
                     |file: &str, progress: u64, total: u64, stats_ptr: Arc<Mutex<DownloadStats>>| {
                         stats_ptr.lock().unwrap().update_entry(String::from(file), progress, total);
                     },
+                    self.cancellation.clone(),
                 )));
             }
         }
 
         let (url, token) = self.coupling_rest_submitter.get_url_and_token();
-        futures.push(Box::pin(report_eta(url, token, self.update_ota_status, stats_ptr.clone())));
+        futures.push(Box::pin(report_eta(url, token, self.update_ota_status, stats_ptr.clone(), self.cancellation.clone())));
         let result = join_all(futures).await;
+        self.downloaded_bytes.set(stats_ptr.lock().unwrap().downloaded_bytes);
 
-        let mut successes = 0;
+        let mut errors = vec![];
         for i in 0..paths.len() {
-            if result[i] {
+            if let Err(error) = &result[i] {
+                log::error!("Download was not successful for {:?}: {}", paths[i].0.clone(), error.report());
+                errors.push(error.clone());
+            } else {
                 log::info!("Download was successful for {:?}", paths[i].0.clone());
+                let component = &manifest.components[&paths[i].0];
+                self.journal.record(&component.component, JournalStep::DownloadVerified, &component.checksum, Some(paths[i].1.clone()));
+                if let Some(artifacts) = &self.artifacts {
+                    artifacts.store(&component.checksum, &paths[i].1);
+                }
                 components_paths.insert(paths[i].0, paths[i].1.clone());
-                successes += 1;
-            } else {
-                log::error!("Download was not successful for {:?}", paths[i].0.clone());
             }
         }
 
-        if successes < paths.len() {
-            return Err(OTAError::fatal(format!("{} component(s) failed to download!", paths.len()-successes)));
+        if self.cancellation.is_cancelled() {
+            return Err(OTAError::cancelled(CANCELLED_MESSAGE.to_string()));
+        }
+        // Reported with the kind of the first failure
+        if let Some(error) = errors.first() {
+            let message = format!("{} component(s) failed to download! {}", errors.len(), error.message);
+            return Err(OTAError { message, ..error.clone() }.into_fatal());
         }
 
         manifest.update_components_paths(components_paths)
@@ -295,7 +433,9 @@ impl<'a, A: SystemControlTrait> DownloadManager<'a, A> {
 
 #[cfg(test)]
 mod tests {
-    use crate::ota::download_manager::{download, DownloadManager};
+    use crate::ota::update_journal::UpdateJournal;
+    use crate::ota::cancellation::CancellationToken;
+    use crate::ota::download_manager::{download, DownloadFuture, DownloadManager};
     use crate::ota::manifest::{Component, Manifest};
     use crate::ota::ota_error::OTAErrorSeverity;
     use crate::ota::service_control_trait::MockSystemControlTrait;
@@ -341,7 +481,7 @@ mod tests {
 
         let mock = RefCell::new(mock);
         let update_ota_status = |_status, _message|{};
-        let download_manager = DownloadManager::new(&mock, &rest_mock, Default::default(), update_ota_status);
+        let download_manager = DownloadManager::new(&mock, &rest_mock, Default::default(), update_ota_status, UpdateJournal::default(), CancellationToken::default());
         let _manifest = download_manager.unwrap().run(manifest).unwrap();
     }
 
@@ -359,7 +499,7 @@ mod tests {
         let stats_ptr = Arc::new(Mutex::new(DownloadStats::new()));
 
         println!("================ Start Downloads Block ================");
-        let mut futures : Vec<std::pin::Pin<Box<dyn std::future::Future<Output=bool>>>> = vec![];
+        let mut futures : Vec<DownloadFuture> = vec![];
 
         for i in 0..num {
             let path = String::from(format!("test_download_file{}", i));
@@ -383,11 +523,12 @@ mod tests {
                         .unwrap()
                         .update_entry(String::from(file), progress, size);
                 },
+                CancellationToken::default(),
             )));
         }
         let result = join_all(futures).await;
         for r in result {
-            assert!(r, "Failed to download test file!");
+            assert!(r.is_ok(), "Failed to download test file!");
         }
         println!("================ Close Downloads Block ================");
         remove_dir_all(&test_dir).expect("Failed to remove test dir!");
@@ -490,7 +631,7 @@ mod tests {
         let test_dir = Path::new("./download_with_manifest_test_dir");
         let update_ota_status = |_status, _message|{};
         let download_manager =
-            DownloadManager::new(&mock, &rest_mock, PathBuf::from(test_dir), update_ota_status).unwrap();
+            DownloadManager::new(&mock, &rest_mock, PathBuf::from(test_dir), update_ota_status, UpdateJournal::default(), CancellationToken::default()).unwrap();
         // Setup
         if test_dir.exists() {
             remove_dir_all(&test_dir).expect("Failed to remove old dir!");
@@ -559,7 +700,7 @@ mod tests {
         let mock = RefCell::new(mock);
         let update_ota_status = |_status, _message|{};
         let download_manager =
-            DownloadManager::new(&mock, &rest_mock, PathBuf::from(test_dir), update_ota_status).unwrap();
+            DownloadManager::new(&mock, &rest_mock, PathBuf::from(test_dir), update_ota_status, UpdateJournal::default(), CancellationToken::default()).unwrap();
         match download_manager.run(manifest) {
             Ok(_) => {
                 panic!("Expected error!");
@@ -629,7 +770,7 @@ mod tests {
         let sys_mock = RefCell::new(mock);
         let update_ota_status = |_status, _message|{};
         let download_manager =
-            DownloadManager::new(&sys_mock, &rest_comm, test_dir.clone(), update_ota_status).unwrap();
+            DownloadManager::new(&sys_mock, &rest_comm, test_dir.clone(), update_ota_status, UpdateJournal::default(), CancellationToken::default()).unwrap();
         let reply = download_manager.post_empty_checksums().unwrap();
         log::info!("REPLY IS <<<{}>>>", reply);
 
@@ -684,7 +825,7 @@ mod tests {
         let sys_mock = RefCell::new(mock);
         let update_ota_status = |_status, _message|{};
         let download_manager =
-            DownloadManager::new(&sys_mock, &rest_comm, test_dir.clone(), update_ota_status).unwrap();
+            DownloadManager::new(&sys_mock, &rest_comm, test_dir.clone(), update_ota_status, UpdateJournal::default(), CancellationToken::default()).unwrap();
         let reply = download_manager.post_empty_checksums().unwrap();
         log::info!("REPLY IS <<<{}>>>", reply);
         let write_function = |_: &Path, _: &str| Ok(());
@@ -1,3 +1,5 @@
+// Builtin baselines, used when there is no valid baseline_manifest file (see baseline_manifest).
+// Component attributes come from the component registry
 
 
 #[cfg(unix)]
@@ -19,8 +21,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "component":"core",
     "version":"1.0.0",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap"
+    "installed":true
   },
   "phantom_agent": {
     "token":"token",
@@ -28,8 +29,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "component":"phantom_agent",
     "version":"1.0.0",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap"
+    "installed":true
   },
   "phantom_launcher": {
     "token":"token",
@@ -37,8 +37,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "component":"phantom_launcher",
     "version":"1.0.0",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap"
+    "installed":true
   },
   "oden_player": {
     "token":"token",
@@ -47,9 +46,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":null,
     "checksum":"",
-    "installed":true,
-    "package_type":"deb",
-    "processes":["Phantom Client"]
+    "installed":true
   },
   "oden_plugin": {
     "token":"token",
@@ -59,9 +56,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "link":null,
     "checksum":"",
     "installed":true,
-    "package_type":"tar",
-    "target_path":"/opt/phantom-client",
-    "processes":["Phantom Client"]
+    "depends_on":["oden_player"]
   },
   "oden_webview": {
     "token":"token",
@@ -71,9 +66,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "link":null,
     "checksum":"",
     "installed":true,
-    "package_type":"tar",
-    "target_path":"/opt/phantom-client",
-    "processes":["Phantom Client"]
+    "depends_on":["oden_player"]
   },
   "log2jira": {
     "token":"token",
@@ -82,9 +75,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":"https://something.jfrog.io/ui/packages",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap",
-    "processes":[]
+    "installed":true
   }
 }
             "#,
@@ -101,8 +92,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":"https://something.jfrog.io/ui/packages",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap"
+    "installed":true
   },
   "sim_gps_info":{
     "token":"token",
@@ -111,8 +101,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":"https://something.jfrog.io/ui/packages",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap"
+    "installed":true
   },
   "phantom_agent": {
     "token":"token",
@@ -120,8 +109,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "component":"phantom_agent",
     "version":"1.0.0",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap"
+    "installed":true
   },
   "translator": {
     "token":"token",
@@ -130,8 +118,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":"https://something.jfrog.io/ui/packages",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap"
+    "installed":true
   },
   "vapp": {
     "token":"token",
@@ -140,8 +127,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":"https://something.jfrog.io/ui/packages",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap"
+    "installed":true
   },
   "stream_manager": {
     "token":"token",
@@ -150,8 +136,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":"https://something.jfrog.io/ui/packages",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap"
+    "installed":true
   },
   "sdk_demo": {
     "token":"token",
@@ -160,9 +145,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":"https://something.jfrog.io/ui/packages",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap",
-    "processes":["phantom-sdk-demo"]
+    "installed":true
   },
   "oden_streamer": {
     "token":"token",
@@ -171,9 +154,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":null,
     "checksum":"",
-    "installed":true,
-    "package_type":"deb",
-    "processes":["phantom-streamer","phantom-streame"]
+    "installed":true
   },
   "oden_plugin": {
     "token":"token",
@@ -183,9 +164,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "link":null,
     "checksum":"",
     "installed":true,
-    "package_type":"tar",
-    "target_path":"/opt/phantom-streamer",
-    "processes":["phantom-streamer","phantom-streame"]
+    "depends_on":["oden_streamer"]
   },
   "autonomy_client":{
     "token":"token",
@@ -194,8 +173,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":"https://something.jfrog.io/ui/packages",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap"
+    "installed":true
   },
   "log2jira": {
     "token":"token",
@@ -204,9 +182,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":"https://something.jfrog.io/ui/packages",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap",
-    "processes":[]
+    "installed":true
   }
 }
             "#,
@@ -233,8 +209,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "component":"core",
     "version":"1.0.0",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap"
+    "installed":true
   },
   "phantom_agent": {
     "token":"token",
@@ -243,8 +218,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "path":"C:/Program Files/phantom_agent/bin/download/phantom_agent.exe",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap"
+    "installed":true
   },
   "phantom_launcher": {
     "token":"token",
@@ -253,9 +227,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":null,
     "checksum":"",
-    "installed":true,
-    "package_type":"msi",
-    "processes":["PhantomLauncher.exe"]
+    "installed":true
   },
   "oden_player": {
     "token":"token",
@@ -264,9 +236,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":null,
     "checksum":"",
-    "installed":true,
-    "package_type":"msi",
-    "processes":["PhantomClient.exe", "cef_child_process.exe"]
+    "installed":true
   },
   "oden_plugin": {
     "token":"token",
@@ -276,9 +246,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "link":null,
     "checksum":"",
     "installed":true,
-    "package_type":"tar",
-    "target_path":"C:/Program Files/Phantom Client",
-    "processes":["PhantomClient.exe", "cef_child_process.exe"]
+    "depends_on":["oden_player"]
   },
   "oden_webview": {
     "token":"token",
@@ -288,9 +256,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "link":null,
     "checksum":"",
     "installed":true,
-    "package_type":"tar",
-    "target_path":"C:/Program Files/Phantom Client",
-    "processes":["PhantomClient.exe", "cef_child_process.exe"]
+    "depends_on":["oden_player"]
   },
   "log2jira": {
     "token":"token",
@@ -299,9 +265,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":"https://something.jfrog.io/ui/packages",
     "checksum":"",
-    "installed":true,
-    "package_type":"msi",
-    "processes":[]
+    "installed":true
   }
 }
             "#,
@@ -317,8 +281,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":"https://something.jfrog.io/ui/packages",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap"
+    "installed":true
   },
    "phantom_agent": {
     "token":"token",
@@ -327,8 +290,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "path":"C:/Program Files/phantom_agent/bin/download/windows_service.exe",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap"
+    "installed":true
   },
   "sim_gps_info":{
     "token":"token",
@@ -337,8 +299,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":"https://something.jfrog.io/ui/packages",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap"
+    "installed":true
   },
   "translator": {
     "token":"token",
@@ -347,8 +308,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":"https://something.jfrog.io/ui/packages",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap"
+    "installed":true
   },
   "vapp": {
     "token":"token",
@@ -357,8 +317,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":"https://something.jfrog.io/ui/packages",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap"
+    "installed":true
   },
   "stream_manager": {
     "token":"token",
@@ -367,8 +326,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":"https://something.jfrog.io/ui/packages",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap"
+    "installed":true
   },
   "sdk_demo": {
     "token":"token",
@@ -377,9 +335,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":"https://something.jfrog.io/ui/packages",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap",
-    "processes":["phantom-sdk-demo"]
+    "installed":true
   },
   "oden_streamer": {
     "token":"token",
@@ -388,9 +344,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":null,
     "checksum":"",
-    "installed":true,
-    "package_type":"msi",
-    "processes":["phantom-streamer","phantom-streame"]
+    "installed":true
   },
   "oden_plugin": {
     "token":"token",
@@ -400,9 +354,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "link":null,
     "checksum":"",
     "installed":true,
-    "package_type":"tar",
-    "target_path":"/opt/phantom-streamer",
-    "processes":["phantom-streamer","phantom-streame"]
+    "depends_on":["oden_streamer"]
   },
   "autonomy_client":{
     "token":"token",
@@ -411,8 +363,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":"https://something.jfrog.io/ui/packages",
     "checksum":"",
-    "installed":true,
-    "package_type":"snap"
+    "installed":true
   },
   "log2jira": {
     "token":"token",
@@ -421,9 +372,7 @@ pub fn get_hardcoded_manifest(operator: bool) -> String {
     "version":"1.0.0",
     "link":"https://something.jfrog.io/ui/packages",
     "checksum":"",
-    "installed":true,
-    "package_type":"msi",
-    "processes":[]
+    "installed":true
   }
 }
             "#,
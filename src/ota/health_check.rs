@@ -0,0 +1,120 @@
+use crate::ota::service_control_trait::SystemControlTrait;
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    net::{TcpStream, ToSocketAddrs},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+use url::Url;
+
+// Seconds the software gets to come up after its install
+const DEFAULT_HEALTH_CHECK_TIMEOUT: u64 = 30;
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+fn default_timeout() -> u64 { DEFAULT_HEALTH_CHECK_TIMEOUT }
+fn default_host() -> String { "localhost".to_string() }
+
+///"health_checks":[
+//       { "type":"process", "name":"phantom-core" },
+//       { "type":"port", "port":8700, "timeout":60 },
+//       { "type":"http", "url":"http://localhost:8700/status" }
+//    ]
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum HealthCheckKind {
+    Process { name: String },
+    Port {
+        port: u16,
+        #[serde(default = "default_host")]
+        host: String,
+    },
+    Http { url: Url },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct HealthCheck {
+    #[serde(flatten)]
+    pub kind: HealthCheckKind,
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+impl HealthCheck {
+    fn check_once<A: SystemControlTrait>(&self, system_control: &RefCell<A>) -> Result<(), String> {
+        match &self.kind {
+            HealthCheckKind::Process { name } => {
+                if system_control.borrow_mut().find_process(name).is_empty() {
+                    Err(format!("process {} is not running", name))
+                } else {
+                    Ok(())
+                }
+            }
+            HealthCheckKind::Port { port, host } => {
+                let addresses = (host.as_str(), *port)
+                    .to_socket_addrs()
+                    .map_err(|e| format!("cannot resolve {}:{}: {}", host, port, e))?;
+                for address in addresses {
+                    if TcpStream::connect_timeout(&address, HEALTH_CHECK_INTERVAL).is_ok() {
+                        return Ok(());
+                    }
+                }
+                Err(format!("nothing is listening on {}:{}", host, port))
+            }
+            HealthCheckKind::Http { url } => {
+                let client = reqwest::blocking::Client::builder()
+                    .timeout(Duration::from_secs(self.timeout.max(1)))
+                    .build()
+                    .map_err(|e| e.to_string())?;
+                match client.get(url.as_str()).send() {
+                    Ok(response) if response.status().is_success() => Ok(()),
+                    Ok(response) => Err(format!("{} returned {}", url, response.status())),
+                    Err(e) => Err(format!("{} failed: {}", url, e)),
+                }
+            }
+        }
+    }
+
+    // Polls until the check passes or the timeout runs out
+    pub fn run<A: SystemControlTrait>(&self, system_control: &RefCell<A>) -> Result<(), String> {
+        let start = Instant::now();
+        loop {
+            let result = self.check_once(system_control);
+            if result.is_ok() || start.elapsed() >= Duration::from_secs(self.timeout) {
+                return result;
+            }
+            sleep(HEALTH_CHECK_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ota::service_control_trait::MockSystemControlTrait;
+    use std::net::TcpListener;
+
+    #[test]
+    fn health_checks() {
+        let checks: Vec<HealthCheck> = serde_json::from_str(r#"[
+            { "type": "process", "name": "phantom-core", "timeout": 0 },
+            { "type": "port", "port": 8700 },
+            { "type": "http", "url": "http://localhost:8700/status" }
+        ]"#).unwrap();
+        assert_eq!(checks[0].kind, HealthCheckKind::Process { name: "phantom-core".to_string() });
+        assert_eq!(checks[1].timeout, DEFAULT_HEALTH_CHECK_TIMEOUT);
+        assert_eq!(checks[1].kind, HealthCheckKind::Port { port: 8700, host: "localhost".to_string() });
+
+        let mut system_control = MockSystemControlTrait::new();
+        system_control.expect_find_process().times(1).returning(|_| vec![]);
+        assert!(checks[0].run(&RefCell::new(system_control)).is_err());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let check = HealthCheck { kind: HealthCheckKind::Port { port, host: "127.0.0.1".to_string() }, timeout: 0 };
+        assert!(check.run(&RefCell::new(MockSystemControlTrait::new())).is_ok());
+        drop(listener);
+        assert!(check.run(&RefCell::new(MockSystemControlTrait::new())).is_err());
+    }
+}
@@ -1,28 +1,41 @@
+use crate::ota::artifact_store::ArtifactStore;
 use crate::ota::manifest::{Component, ComponentType, Manifest};
-use crate::ota::ota_error::{OTAError, OTAErrorSeverity};
+use crate::ota::component_hooks::{HookExec, HookOutput, HookStage};
+use crate::ota::agent_boot_guard::AgentBootGuard;
+use crate::ota::component_order::{check_constraints, depends_on_any, install_order, rollback_order};
+use crate::ota::ota_error::{OTAError, OTAErrorKind, OTAErrorSeverity};
 use crate::ota::ota_manager::{as_install_type, PackageType};
 use crate::ota::service_control_trait::SystemControlTrait;
 use crate::rest_comm::coupling_submit_trait::{CouplingRestSubmitter, NodeOtaProgressStatus};
+use crate::BashExec;
 use crate::utils::color::Coloralex;
-use crate::utils::file_utils::get_sha1_checksum;
+use crate::utils::file_utils::{get_checksum_like, link_or_copy};
 use log;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::str::FromStr;
 use std::fs;
+use std::time::Instant;
 use log::info;
 
 #[cfg(windows)]
 use {
     std::thread::sleep,
     std::time::Duration,
-    crate::BashExec,
     crate::utils::tasklist::Tasklist,
 //  crate::ota::manifest::{DOWNLOAD_DIR, WINDOWS_SERVICE_TRIGGER_PATH},
 };
+use crate::ota::cancellation::{CancellationToken, CANCELLED_MESSAGE};
 use crate::ota::ota_status::OTAStatus;
+use crate::ota::update_journal::{JournalStep, UpdateJournal};
 #[cfg(unix)]
-use crate::ota::snap_installer::SnapInstaller;
-use crate::rest_comm::jira_log_submitter::{JIRA_REPORT_TICKET, send_snapshot_to_jira};
+use {
+    crate::ota::snap_installer::SnapInstaller,
+    crate::ota::agent_boot_guard::PendingAgentBoot,
+    crate::ota::manifest::current_agent_version,
+};
+
+// Shorter names are too generic to be killed safely
+pub(crate) const MIN_PROCESS_NAME_LENGTH: usize = 6;
 
 pub struct InstallManager<'c, A: SystemControlTrait> {
     #[allow(dead_code)]
@@ -30,6 +43,16 @@ pub struct InstallManager<'c, A: SystemControlTrait> {
     install_command: fn(component: &Component, installing: bool) -> Result<String, OTAError>,
     status_submitter: &'c dyn CouplingRestSubmitter,
     update_ota_status: fn(OTAStatus, Option<String>),
+    journal: UpdateJournal,
+    cancellation: CancellationToken,
+    rollback_duration: Cell<std::time::Duration>,
+    hook_exec: HookExec,
+    hook_outputs: RefCell<Vec<HookOutput>>,
+    // Self updates are confirmed by the new agent, see agent_boot_guard
+    #[allow(dead_code)]
+    agent_boot: Option<AgentBootGuard>,
+    // Where a roll back finds the previous package when its backup is gone
+    artifacts: Option<ArtifactStore>,
 }
 
 impl<'c, A: SystemControlTrait> InstallManager<'c, A> {
@@ -38,20 +61,87 @@ impl<'c, A: SystemControlTrait> InstallManager<'c, A> {
         install_command: fn(component: &Component, installing: bool) -> Result<String, OTAError>,
         status_submitter: &'c dyn CouplingRestSubmitter,
         update_ota_status: fn(OTAStatus, Option<String>),
+        journal: UpdateJournal,
+        cancellation: CancellationToken,
     ) -> Self {
         Self {
             system_control,
             install_command,
             status_submitter,
             update_ota_status,
+            journal,
+            cancellation,
+            rollback_duration: Cell::new(std::time::Duration::ZERO),
+            hook_exec: BashExec::exec_shell_timeout,
+            hook_outputs: RefCell::new(vec![]),
+            agent_boot: None,
+            artifacts: None,
+        }
+    }
+
+    pub fn with_artifact_store(self, artifacts: Option<ArtifactStore>) -> Self {
+        Self { artifacts, ..self }
+    }
+
+    pub fn with_agent_boot(self, agent_boot: AgentBootGuard) -> Self {
+        Self { agent_boot: Some(agent_boot), ..self }
+    }
+
+    #[cfg(test)]
+    pub fn with_hook_exec(self, hook_exec: HookExec) -> Self {
+        Self { hook_exec, ..self }
+    }
+
+    // What the component hooks printed during install_manifest
+    pub fn hook_outputs(&self) -> Vec<HookOutput> {
+        self.hook_outputs.borrow().clone()
+    }
+
+    fn run_hook(&self, component: &Component, stage: HookStage) -> Result<(), OTAError> {
+        let output = match component.hooks.run(&component.component, stage, self.hook_exec) {
+            Some(output) => output,
+            None => return Ok(()),
+        };
+        let result = if output.success {
+            Ok(())
+        } else {
+            Err(OTAError::hook(
+                &component.component,
+                stage.as_str(),
+                format!("{} {} hook failed: {}", component.component, stage.as_str(), output.output),
+            ))
+        };
+        self.hook_outputs.borrow_mut().push(output);
+        result
+    }
+
+    // A failing pre-hook leaves the component as it is, a failing post-hook rolls it back to installed_checksum
+    fn update_component(&self, component: &Component, installed_checksum: &str) -> Result<(), OTAError> {
+        let (pre_hook, post_hook) = if component.should_install() {
+            (HookStage::PreInstall, HookStage::PostInstall)
+        } else {
+            (HookStage::PreUninstall, HookStage::PostUninstall)
+        };
+        self.run_hook(component, pre_hook)?;
+        self.install_or_uninstall_component(component)?;
+        if let Err(error) = self.run_hook(component, post_hook) {
+            log::warn!("Rolling back {} after its {} hook failed", component.component, post_hook.as_str());
+            let start = Instant::now();
+            let rolled_back = self.roll_back_component(component, installed_checksum);
+            self.rollback_duration.set(self.rollback_duration.get() + start.elapsed());
+            if let Err(e) = rolled_back {
+                return Err(OTAError::rollback(format!("{}, and its roll back failed: {}", error.message, e.message)));
+            }
+            return Err(error);
         }
+        Ok(())
     }
 
     fn kill_component_processes(&self, component: &Component) {
         if !component.processes.is_empty() {
             log::info!("Found {} processes to stop", component.processes.len());
             for process_name in &component.processes {
-                if process_name.len() < 6 {
+                if process_name.len() < MIN_PROCESS_NAME_LENGTH {
                     log::warn!("Sanity check - {} is too short to be a process name!", process_name);
                 } else {
                     let mut system_control = self.system_control.borrow_mut();
@@ -82,10 +172,10 @@ impl<'c, A: SystemControlTrait> InstallManager<'c, A> {
     }
 
     // Install a component if it has a PATH, but if it doesn't have a PATH, uninstall it using prev save
-    fn install_or_uninstall_component(&self, component: &Component) -> bool {
+    fn install_or_uninstall_component(&self, component: &Component) -> Result<(), OTAError> {
         if component.updated {  // Sanity check, if it's not marked for install/uninstall, we shouldn't do anything!
             log::info!("{} - update is not required", component.component);
-            return true;
+            return Ok(());
         }
 
         if component.should_install() || component.should_uninstall() {
@@ -94,7 +184,7 @@ impl<'c, A: SystemControlTrait> InstallManager<'c, A> {
             log::info!("{} {}", updating, component.component);
             if as_install_type(&component.package_type) == PackageType::TAR && component.target_path.is_none() {
                 log::error!("No target path for tar component!");
-                return false;
+                return Err(OTAError::installer(&component.package_type, format!("No target path for {}", component.component)));
             }
 
             self.kill_component_processes(component);
@@ -143,7 +233,14 @@ impl<'c, A: SystemControlTrait> InstallManager<'c, A> {
                     ":".red(false),
                     error.message.red(false)
                 );
-                false
+                // Installers that don't classify their errors are reported as installer failures
+                match error.kind {
+                    OTAErrorKind::Internal => Err(OTAError {
+                        kind: OTAErrorKind::Installer { package_type: component.package_type.clone(), output: error.message.clone() },
+                        ..error
+                    }),
+                    _ => Err(error),
+                }
             } else {
                 if as_install_type(&component.package_type) == PackageType::TAR {
                     self.kill_component_processes(&component);  // Ensure processes run with new files
@@ -160,11 +257,11 @@ impl<'c, A: SystemControlTrait> InstallManager<'c, A> {
                     success_updating,
                     version
                 );
-                true
+                Ok(())
             }
         } else {
             log::warn!("Component {} was marked for install/uninstall, but it's already handled!", component.component);
-            false
+            Err(OTAError::nonfatal(format!("{} was already handled", component.component)))
         }
     }
 
@@ -327,11 +424,56 @@ EXIT /B 0
 "#)
     }
 
-    fn install_agent(&self, manifest: Manifest) -> Manifest {
+    // Copies the new package next to the kept one and records the update, the new agent has to confirm it
+    #[cfg(unix)]
+    fn stage_agent_update(&self, agent_boot: &AgentBootGuard, agent_component: &Component) -> Result<(), OTAError> {
+        if let Some(rejected) = agent_boot.rejected(&agent_component.checksum) {
+            return Err(OTAError::installer(
+                &agent_component.package_type,
+                format!("Phantom agent {} was reverted ({}), waiting for another package", rejected.version, rejected.reason),
+            ));
+        }
+        let previous_dir = agent_component
+            .previous_install_path
+            .clone()
+            .ok_or_else(|| OTAError::state_corruption("No previous directory for phantom agent".to_string()))?;
+        let download_path = agent_component
+            .path
+            .clone()
+            .ok_or_else(|| OTAError::state_corruption("No downloaded package for phantom agent".to_string()))?;
+        let slot = previous_dir.with_extension("next");
+        let new_package = slot.join(download_path.file_name().unwrap_or_default());
+        let _ = fs::remove_dir_all(&slot);
+        fs::create_dir_all(&slot)
+            .and_then(|_| fs::copy(&download_path, &new_package))
+            .map_err(|e| OTAError::fatal(format!("Failed to keep the new agent package in {}: {}", slot.to_string_lossy(), e)))?;
+        let (prev_exists, previous_package) = agent_component.uninstall_information();
+        if !prev_exists {
+            log::warn!("No previous phantom agent package is kept, this update cannot be reverted");
+        }
+        agent_boot.start(PendingAgentBoot {
+            from_version: current_agent_version(),
+            to_version: agent_component.version.clone(),
+            checksum: agent_component.checksum.clone(),
+            previous_checksum: if prev_exists { get_checksum_like(&previous_package, &agent_component.checksum).unwrap_or_default() } else { String::default() },
+            previous_package: prev_exists.then_some(previous_package),
+            previous_dir,
+            new_package,
+            installed_at: chrono::Utc::now().timestamp(),
+            boots: 0,
+            revert_reason: None,
+        })
+    }
+
+    fn install_agent(&self, manifest: Manifest) -> Result<Manifest, OTAError> {
         // Clone required on linux, not on windows
         #[allow(clippy::redundant_clone)]
         if let Some(agent_component) = manifest.components.clone().get(&ComponentType::phantom_agent) {
             if !agent_component.updated {
+                #[cfg(unix)]
+                if let Some(agent_boot) = &self.agent_boot {
+                    self.stage_agent_update(agent_boot, agent_component)?;
+                }
                 // Sending pre-emptive failed status, agent will switch it back to updating (later updated) as soon as it's back up
                 self.status_submitter.put_ota_status(
                     Some("Setting to fail while updating Phantom Agent".to_string()),
@@ -339,10 +481,9 @@ EXIT /B 0
                     NodeOtaProgressStatus::Failed,
                 );
                 // This will make agent panic on next run UNLESS it was installed and the env version was updated correctly
-                manifest
-                    .hash_manifest
-                    .update_version_file(agent_component.version.clone())
-                    .expect("Failed to update version file!");
+                self.journal
+                    .write_agent_version(&manifest.hash_manifest, &agent_component.version)
+                    .map_err(|e| OTAError::state_corruption(format!("Failed to update version file! ({})", e)))?;
                 // Preemptively saving the hash as if the install was successful
                 let new_component: Component = Component {
                     updated: true,
@@ -352,9 +493,9 @@ EXIT /B 0
                 #[allow(unused_variables)]
                     let manifest = manifest
                     .update_single_component(&new_component)
-                    .unwrap()
-                    .write_to_file()
-                    .unwrap();
+                    .and_then(|manifest| self.journal.write_manifest(manifest))
+                    .map_err(OTAError::state_corruption)?;
+                self.journal.record(&agent_component.component, JournalStep::HashCommitted, &agent_component.checksum, None);
 
                 #[cfg(windows)]
                 {
@@ -362,11 +503,11 @@ EXIT /B 0
                     let source = source.to_string_lossy();
                     let target = "C:/Program Files/phantom_agent/bin/phantom_agent.exe";
                     let script = self.create_script(&source, target);
-                    fs::write("agent_install.bat", script).expect("Unable to write file");
+                    fs::write("agent_install.bat", script).map_err(|e| OTAError::installer("msi", format!("Unable to write agent install script: {}", e)))?;
 
                     match BashExec::exec("powershell /c Start-Process agent_install.bat") {
                         Ok(_) => { log::info!("Started phantom agent install script"); }
-                        Err(e) => { panic!("Failed to start phantom agent install script! {}", e); }
+                        Err(e) => { return Err(OTAError::installer("msi", format!("Failed to start phantom agent install script! {}", e))); }
                     }
                     /* We are not triggering the windows_update service. The script will handle it instead
                     let flag_path = PathBuf::from(DOWNLOAD_DIR).join(WINDOWS_SERVICE_TRIGGER_PATH);
@@ -386,7 +527,11 @@ EXIT /B 0
                 #[cfg(unix)]
                 {
                     // If the install component succeeds, this process should be DEAD! If we're still alive, it means we failed
-                    if !self.install_or_uninstall_component(agent_component) {
+                    if let Err(error) = self.install_or_uninstall_component(agent_component) {
+                        log::error!("Agent install failed: {}", error.report());
+                        if let Some(agent_boot) = &self.agent_boot {
+                            agent_boot.cancel();
+                        }
                         // Reverting hash to indicate we failed to complete the installation process correctly
                         let new_component: Component = Component {
                             updated: false,
@@ -395,15 +540,14 @@ EXIT /B 0
                         };
                         return manifest
                             .update_single_component(&new_component)
-                            .unwrap()
-                            .write_to_file()
-                            .unwrap();
+                            .and_then(|manifest| self.journal.write_manifest(manifest))
+                            .map_err(OTAError::state_corruption);
                     }
-                    return manifest;
+                    return Ok(manifest);
                 }
             }
         }
-        manifest
+        Ok(manifest)
     }
 
     pub fn save_prev_components(&self, manifest: Manifest, component_types: &[ComponentType]) -> Result<Manifest, OTAError> {
@@ -426,9 +570,11 @@ EXIT /B 0
                         }
                         if let Some(file) = download_path.file_name() {
                             let prev_path = prev_dir.join(file);
-                            if let Err(e) = fs::copy(download_path, prev_path) {
+                            // Shares the file with the download and the artifact store instead of another copy
+                            if let Err(e) = link_or_copy(&download_path, &prev_path) {
                                 return Err(OTAError::fatal(format!("Failed to save backup: {}", e)));
                             }
+                            self.journal.record(&component.component, JournalStep::BackupSaved, &component.checksum, None);
                         } else {
                             return Err(OTAError::fatal(format!("Failed to extract file name from {}", download_path.to_string_lossy())));
                         }
@@ -446,8 +592,30 @@ EXIT /B 0
         Ok(manifest)
     }
 
-    pub fn roll_back_component(&self, component: &Component) -> Result<Component, OTAError> {
+    // The backup in previous/, put back from the artifact store when it's gone
+    fn previous_package(&self, component: &Component, installed_checksum: &str) -> (bool, std::path::PathBuf) {
         let (prev_exists, file) = component.uninstall_information();
+        if prev_exists || installed_checksum.is_empty() {
+            return (prev_exists, file);
+        }
+        let restored = match (&self.artifacts, &component.previous_install_path) {
+            (Some(artifacts), Some(prev_dir)) => {
+                let _ = fs::remove_dir_all(prev_dir);
+                artifacts.fetch_into(installed_checksum, prev_dir)
+            }
+            _ => None,
+        };
+        match restored {
+            Some(file) => {
+                log::info!("Restored the backup of {} from the artifact store", component.component);
+                (true, file)
+            }
+            None => (false, file),
+        }
+    }
+
+    pub fn roll_back_component(&self, component: &Component, installed_checksum: &str) -> Result<Component, OTAError> {
+        let (prev_exists, file) = self.previous_package(component, installed_checksum);
 
         if component.path.is_some() && (!prev_exists || as_install_type(&component.package_type) == PackageType::MSI) {
             log::info!("Uninstalling {} for roll back", component.component);
@@ -457,22 +625,24 @@ EXIT /B 0
         }
 
         if prev_exists {    // Reinstalling previous component from stored installer
-            let checksum = get_sha1_checksum(&file).unwrap_or_default();
+            let checksum = get_checksum_like(&file, &component.checksum).unwrap_or_default(); // Same algorithm as the declared one
             let component = Component {
                 updated: false,
                 path: Some(file.clone()),
                 ..component.clone()
             };
-            if self.install_or_uninstall_component(&component) {
-                let component = Component {
-                    updated: true,
-                    checksum,
-                    ..component
-                };
-                log::info!("Rolled back {} successfully", component.component);
-                Ok(component)
-            } else {
-                Err(OTAError::nonfatal(format!("Failed to reinstall {:?}", file)))
+            match self.install_or_uninstall_component(&component) {
+                Ok(()) => {
+                    let component = Component {
+                        updated: true,
+                        checksum,
+                        ..component
+                    };
+                    log::info!("Rolled back {} successfully", component.component);
+                    self.journal.record(&component.component, JournalStep::RolledBack, &component.checksum, None);
+                    Ok(component)
+                }
+                Err(e) => Err(OTAError::rollback(format!("Failed to reinstall {:?}: {}", file, e.message))),
             }
         } else {  // No previous component to fall back to
             let component = Component {
@@ -481,6 +651,7 @@ EXIT /B 0
                 ..component.clone()
             };
             log::info!("No previous {} to install, roll back complete", component.component);
+            self.journal.record(&component.component, JournalStep::RolledBack, &component.checksum, None);
             Ok(component)
         }
     }
@@ -494,19 +665,20 @@ EXIT /B 0
             message += &manifest.components[component_type].component;
             if *component_type == ComponentType::phantom_agent {
                 log::error!("Rolling back agent requested (should be impossible)");
-                return Err(OTAError::fatal("Attempted to roll back agent".to_string()));
+                return Err(OTAError::state_corruption("Attempted to roll back agent".to_string()));
             }
         }
         log::info!("Rolling back components: [{}]", message);
+        let hash_manifest = &manifest.hash_manifest;
         let mut roll_back_all = true;
-        let mut vec: Vec<Component> = manifest.components.into_values().collect();
-        vec.sort_by(sort_by_package);
+        let vec = rollback_order(manifest.components.into_values().collect());
         let components = vec
             .into_iter()
             .map(|component| {
                 let component_type = ComponentType::from_str(&component.component).unwrap();
                 if component_types.contains(&component_type) {
-                    match self.roll_back_component(&component) {
+                    let installed_checksum = hash_manifest.installed_checksum(&manifest.server_name, manifest.operator, &component_type);
+                    match self.roll_back_component(&component, &installed_checksum) {
                         Ok(rolled_back_component) => {
                             (component_type, rolled_back_component)
                         }
@@ -528,10 +700,21 @@ EXIT /B 0
             })
         } else {
             log::error!("{}", "Some components were not successfully rolled back!".red(true));
-            Err(OTAError::fatal("Not all components were rolled back!".to_string()))
+            Err(OTAError::rollback("Not all components were rolled back!".to_string()))
         }
     }
 
+    fn timed_roll_back(&self, manifest: Manifest, component_types: &Vec<ComponentType>) -> Result<Manifest, OTAError> {
+        let start = Instant::now();
+        let result = self.roll_back_components(manifest, component_types);
+        self.rollback_duration.set(self.rollback_duration.get() + start.elapsed());
+        result
+    }
+
+    pub fn rollback_duration(&self) -> std::time::Duration {
+        self.rollback_duration.get()
+    }
+
     pub fn restore_archives(&self, manifest: &Manifest) {
         info!("Restoring all archive components (post-install)");
         for (_, component) in manifest.components.clone() {
@@ -543,10 +726,9 @@ EXIT /B 0
                         path: Some(file.clone()),
                         ..component.clone()
                     };
-                    if self.install_or_uninstall_component(&component) {
-                        info!("Restored archive {}", component.component);
-                    } else {
-                        info!("Failed to restore archive {:?}", file);
+                    match self.install_or_uninstall_component(&component) {
+                        Ok(()) => info!("Restored archive {}", component.component),
+                        Err(e) => info!("Failed to restore archive {:?}: {}", file, e.report()),
                     }
                 }
             }
@@ -559,20 +741,27 @@ EXIT /B 0
         if let Some(agent_component) = manifest.components.get(&ComponentType::phantom_agent) {
             if !agent_component.updated {
                 (self.update_ota_status)(OTAStatus::INSTALLING(ComponentType::phantom_agent), None);
-                return Ok(self.install_agent(manifest));
+                return self.install_agent(manifest);
                 // Note that NodeOtaStatus::Updated isn't sent until new run with updated agent, at the other components check
             }
         }
 
-        let mut vec: Vec<Component> = manifest.components.into_values().collect();
-        vec.sort_by(sort_by_package);
-        let mut updated_all = true;
+        let vec: Vec<Component> = manifest.components.into_values().collect();
+        check_constraints(&vec)?;
+        let vec = install_order(vec)?;
+        let mut install_error: Option<OTAError> = None;
+        let mut cancelled = false;
         let mut updated_list: Vec<ComponentType> = Vec::new();
+        let mut failed_list: Vec<String> = Vec::new();
         let components = vec
             .into_iter()
             .map(|component| {
                 let component_type = ComponentType::from_str(&component.component).unwrap();
-                if component.should_install() || component.should_uninstall() {
+                // Checked between components, a running installer is never interrupted
+                cancelled = cancelled || self.cancellation.is_cancelled();
+                if cancelled {
+                    (component_type, component)
+                } else if component.should_install() || component.should_uninstall() {
                     // Update status only one time
                     if updated_list.is_empty() {
                         self.status_submitter.put_ota_status(
@@ -582,11 +771,26 @@ EXIT /B 0
                         );
                     }
                     (self.update_ota_status)(OTAStatus::INSTALLING(component_type), None);
-                    let updated = self.install_or_uninstall_component(&component);
+                    // Installing on top of a failed dependency is pointless, the update is rolled back anyway
+                    let result = match depends_on_any(&component, &failed_list) {
+                        Some(dependency) => Err(OTAError::dependency(format!("{} skipped, its dependency {} failed", component.component, dependency))),
+                        None => self.update_component(&component, &manifest.hash_manifest.installed_checksum(&manifest.server_name, manifest.operator, &component_type)),
+                    };
+                    let updated = result.is_ok();
+                    if !updated {
+                        failed_list.push(component.component.clone());
+                    }
                     if updated {
                         updated_list.push(component_type);
+                        if component.should_install() {
+                            self.journal.record(&component.component, JournalStep::Installed, &component.checksum, component.path.clone());
+                        } else {
+                            self.journal.record(&component.component, JournalStep::Uninstalled, "", None);
+                        }
+                    }
+                    if let Err(error) = result {
+                        install_error = install_error.take().or(Some(error)); // The first failure is reported
                     }
-                    updated_all = updated_all && updated;
                     let checksum = {
                         if updated {
                             if component.should_install() { component.checksum.clone() } // Install success
@@ -595,7 +799,7 @@ EXIT /B 0
                         else {  // Update failed, setting checksum to prev information or none
                             let (prev_exists, file) = component.uninstall_information();
                             if prev_exists {
-                                match get_sha1_checksum(&file) {
+                                match get_checksum_like(&file, &component.checksum) {
                                     Ok(checksum) => { checksum }
                                     Err(_) => { "".to_string() }
                                 }
@@ -619,14 +823,29 @@ EXIT /B 0
             })
             .collect();
 
-        if updated_all {
-            #[cfg(unix)]
-            SnapInstaller::cleanup_deprecated_if_needed(); // This will remove deprecated snap components if it detects them
-            log::info!("{}", "All components were updated successfully".green(true));
+        if cancelled {
+            log::warn!("{}", "Install was cancelled, rolling back the installed components".yellow(true));
             let manifest = Manifest {
                 components,
                 ..manifest
             };
+            return match self.timed_roll_back(manifest, &updated_list) {
+                Ok(manifest) => {
+                    self.restore_archives(&manifest);
+                    Err(OTAError::cancelled(CANCELLED_MESSAGE.to_string()))
+                }
+                Err(e) => { Err(OTAError::rollback(e.message)) }
+            };
+        }
+        let manifest = Manifest {
+            components,
+            ..manifest
+        };
+        let failed_checks = if install_error.is_none() { self.run_health_checks(&manifest, &updated_list) } else { vec![] };
+        if install_error.is_none() && failed_checks.is_empty() {
+            #[cfg(unix)]
+            SnapInstaller::cleanup_deprecated_if_needed(); // This will remove deprecated snap components if it detects them
+            log::info!("{}", "All components were updated successfully".green(true));
             // Update the cloud OTA status only if actual software update occurred
             if !updated_list.is_empty() {
                 info!("The updated_list is not empty, putting OTA status");
@@ -642,34 +861,47 @@ EXIT /B 0
             self.restore_archives(&manifest);
             Ok(manifest)
         } else {
-            let error = "Some components were not successfully updated!".to_string();
-            self.status_submitter.put_ota_status(
-                Some(error.clone()),
-                None,
-                NodeOtaProgressStatus::Failed,
-            );
-            log::error!("{}", error.red(true));
-            if let Err(e) = send_snapshot_to_jira(JIRA_REPORT_TICKET, true) {
-                log::error!("Snapshot error: {e}");
-            }
-            let manifest = Manifest {
-                components,
-                ..manifest
+            let error = match install_error {
+                Some(error) => OTAError {
+                    message: format!("Some components were not successfully updated! {}", error.message),
+                    ..error
+                },
+                None => OTAError::health_check(format!("Health check failed: {}", failed_checks.join(", "))),
             };
-            match self.roll_back_components(manifest, &updated_list) {
+            log::error!("{}", error.report().red(true)); // Reported to the cloud and Jira by OTAManager
+            match self.timed_roll_back(manifest, &updated_list) {
                 Ok(manifest) => {
                     self.restore_archives(&manifest);
-                    Err(OTAError::nonfatal("Failed install caused rollback".to_string()))
+                    Err(OTAError { message: format!("Failed install caused rollback: {}", error.message), ..error })
                 }
-                Err(e) => { Err(OTAError::fatal(e.message)) }
+                Err(e) => { Err(OTAError::rollback(e.message)) }
             }
         }
     }
+
+    // Runs after all the installs, so components that depend on each other are all up. Returns the failures
+    fn run_health_checks(&self, manifest: &Manifest, component_types: &[ComponentType]) -> Vec<String> {
+        let mut failures = vec![];
+        for component_type in component_types {
+            let component = &manifest.components[component_type];
+            if component.path.is_none() { // Uninstalled, nothing to check
+                continue;
+            }
+            for health_check in &component.health_checks {
+                log::info!("Checking {} health: {:?}", component.component, health_check.kind);
+                if let Err(e) = health_check.run(self.system_control) {
+                    log::error!("{} is unhealthy: {}", component.component, e);
+                    failures.push(format!("{} ({})", component.component, e));
+                }
+            }
+        }
+        failures
+    }
 }
 
 // TODO: this should be impl for the num itself
 // A function that sorts components so that phantom_agent is first and all the tar modules are last
-fn sort_by_package(component1: &Component, component2: &Component) -> std::cmp::Ordering {
+pub(crate) fn sort_by_package(component1: &Component, component2: &Component) -> std::cmp::Ordering {
     use std::cmp::Ordering::*;
     if ComponentType::from_str(&component1.component).unwrap() == ComponentType::phantom_agent {
         return Less;
@@ -691,6 +923,7 @@ fn sort_by_package(component1: &Component, component2: &Component) -> std::cmp::
 
 #[cfg(test)]
 mod tests {
+    use crate::ota::update_journal::UpdateJournal;
     use super::*;
     use crate::auth::license_manager::LicenseManager;
     use crate::auth::license_manager_trait::LicenseManagerTrait;
@@ -732,7 +965,7 @@ mod tests {
         let install_command =   // Always returns successful installation no matter what
             |_component: &Component, _installing: bool| -> Result<String, OTAError> { Ok(String::from("ttt")) };
         let install_manager =
-            InstallManager::new(&system_control, install_command, &status_submitter, update_ota_status);
+            InstallManager::new(&system_control, install_command, &status_submitter, update_ota_status, UpdateJournal::default(), CancellationToken::default());
         let component_type = ComponentType::from_str(component_name).unwrap();
         let component: Component = Component {
             component: component_name.to_string(),
@@ -747,6 +980,11 @@ mod tests {
             package_type: "snap".to_string(),
             previous_install_path: None,
             processes: vec![],
+            health_checks: vec![],
+            depends_on: vec![],
+            conflicts_with: vec![],
+            hooks: Default::default(),
+            signature: None,
         };
 
         let manifest = manifest.update_single_component(&component).unwrap();
@@ -774,6 +1012,105 @@ mod tests {
         simple_test_template("sim_gps_info", false)
     }
 
+    #[test]
+    fn failing_post_hook_rolls_back() {
+        use crate::ota::component_hooks::ComponentHooks;
+        use crate::ota::service_control_trait::MockSystemControlTrait;
+        use crate::rest_comm::coupling_submit_trait::MockCouplingRestSubmitter;
+
+        let mut system_control_mock = MockSystemControlTrait::new();
+        system_control_mock.expect_find_process().returning(|_| Vec::new());
+        let system_control = RefCell::new(system_control_mock);
+        let status_submitter = MockCouplingRestSubmitter::new();
+        static UNINSTALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let install_command = |component: &Component, installing: bool| -> Result<String, OTAError> {
+            log::info!("Mock {} {}", if installing { "installing" } else { "uninstalling" }, component.component);
+            if !installing {
+                UNINSTALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            }
+            Ok(String::default())
+        };
+        let hook_exec = |command: &str, _timeout| if command == "migrate" { Err("no config".to_string()) } else { Ok("stopped".to_string()) };
+        let journal_path = std::env::temp_dir().join("failing_post_hook_journal");
+        let _ = std::fs::remove_file(&journal_path);
+        let journal = UpdateJournal::new(journal_path.clone());
+        journal.start_cycle("server", false);
+        let install_manager =
+            InstallManager::new(&system_control, install_command, &status_submitter, |_, _| {}, journal.clone(), CancellationToken::default())
+                .with_hook_exec(hook_exec);
+        let component = Component {
+            component: "sim_gps_info".to_string(),
+            checksum: "abc".to_string(),
+            path: Some(std::env::current_dir().unwrap()),
+            version: "0.1.2".to_string(),
+            package_type: "snap".to_string(),
+            hooks: ComponentHooks {
+                pre_install: Some("stop".to_string()),
+                post_install: Some("migrate".to_string()),
+                ..Default::default()
+            },
+            ..Component::empty()
+        };
+
+        let error = install_manager.update_component(&component, "").err().unwrap();
+        assert_eq!(error.code(), "OTA-HOOK");
+        let outputs = install_manager.hook_outputs();
+        assert_eq!(outputs.len(), 2);
+        assert!(outputs[0].success);
+        assert_eq!((outputs[1].stage, outputs[1].success, outputs[1].output.as_str()), (HookStage::PostInstall, false, "no config"));
+        // Without a previous package the roll back uninstalls what was just installed
+        assert_eq!(UNINSTALLS.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(journal.pending_cycle().unwrap().components["sim_gps_info"].step, JournalStep::RolledBack);
+
+        let component = Component { hooks: ComponentHooks { pre_install: Some("migrate".to_string()), ..Default::default() }, ..component };
+        assert_eq!(install_manager.update_component(&component, "").err().unwrap().code(), "OTA-HOOK");
+        assert_eq!(install_manager.hook_outputs().len(), 3);
+        assert_eq!(UNINSTALLS.load(std::sync::atomic::Ordering::SeqCst), 1); // Nothing was installed to roll back
+        let _ = std::fs::remove_file(journal_path);
+    }
+
+    #[test]
+    fn roll_back_takes_a_missing_backup_from_the_artifact_store() {
+        use crate::ota::artifact_store::{ArtifactStore, ArtifactStoreConfig};
+        use crate::ota::service_control_trait::MockSystemControlTrait;
+        use crate::rest_comm::coupling_submit_trait::MockCouplingRestSubmitter;
+        use crate::utils::file_utils::get_sha1_checksum;
+
+        let dir = std::env::temp_dir().join("roll_back_artifact_store_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let old_package = dir.join("sim_gps_info_0.1.1.snap");
+        fs::write(&old_package, "sim_gps_info 0.1.1").unwrap();
+        let old_checksum = get_sha1_checksum(&old_package).unwrap();
+        let artifacts = ArtifactStore::new(dir.join("artifacts"), &ArtifactStoreConfig { max_size_mb: 1 }).unwrap();
+        artifacts.store(&old_checksum, &old_package);
+        fs::remove_file(&old_package).unwrap();
+
+        let mut system_control_mock = MockSystemControlTrait::new();
+        system_control_mock.expect_find_process().returning(|_| Vec::new());
+        let system_control = RefCell::new(system_control_mock);
+        let status_submitter = MockCouplingRestSubmitter::new();
+        let install_command = |_: &Component, _: bool| -> Result<String, OTAError> { Ok(String::default()) };
+        let install_manager = InstallManager::new(&system_control, install_command, &status_submitter, |_, _| {}, UpdateJournal::default(), CancellationToken::default());
+        let component = Component {
+            component: "sim_gps_info".to_string(),
+            checksum: "new checksum".to_string(),
+            path: Some(dir.join("sim_gps_info_0.1.2.snap")),
+            package_type: "snap".to_string(),
+            previous_install_path: Some(dir.join("previous/V_/sim_gps_info")),
+            ..Component::empty()
+        };
+
+        // Without the store there is nothing to go back to
+        let rolled_back = install_manager.roll_back_component(&component, &old_checksum).unwrap();
+        assert_eq!(rolled_back.checksum, "");
+        let install_manager = install_manager.with_artifact_store(Some(artifacts));
+        let rolled_back = install_manager.roll_back_component(&component, &old_checksum).unwrap();
+        assert_eq!(rolled_back.checksum, old_checksum);
+        assert_eq!(fs::read_to_string(dir.join("previous/V_/sim_gps_info/sim_gps_info_0.1.1.snap")).unwrap(), "sim_gps_info 0.1.1");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
     fn read_function2(_: &Path) -> Result<String, String> {
         let result = r#" {
             "translator": "9685123541"
@@ -808,6 +1145,11 @@ mod tests {
             package_type: "snap".to_string(),
             previous_install_path: None,
             processes: vec![],
+            health_checks: vec![],
+            depends_on: vec![],
+            conflicts_with: vec![],
+            hooks: Default::default(),
+            signature: None,
         };
 
         let manifest = manifest.update_single_component(&component).unwrap();
@@ -825,7 +1167,7 @@ mod tests {
         };
         let update_ota_status = |_ota_status, _message| {};
         let install_manager =
-            InstallManager::new(&system_control, install_command, &status_submitter, update_ota_status);
+            InstallManager::new(&system_control, install_command, &status_submitter, update_ota_status, UpdateJournal::default(), CancellationToken::default());
         let manifest: Manifest = install_manager.install_manifest(manifest).unwrap();
         assert!(
             manifest
@@ -950,7 +1292,7 @@ mod tests {
 
         let update_ota_status = |_status, _message| {};
         let download_manager =
-            DownloadManager::new(&sys_mock, &rest_comm, test_dir.clone(), update_ota_status).unwrap();
+            DownloadManager::new(&sys_mock, &rest_comm, test_dir.clone(), update_ota_status, UpdateJournal::default(), CancellationToken::default()).unwrap();
 
         log::info!("Creating manifest");
         let write_function = |_: &Path, _: &str| Ok(());
@@ -991,7 +1333,7 @@ mod tests {
             #[cfg(windows)] { ota::tar_installer::TarInstaller::install_zip(&component, BashExec::exec_arg) }
         };
         let update_ota_status = |_ota_status, _message| {};
-        let install_manager = InstallManager::new(&sys_mock, install_command, &status_submitter, update_ota_status);
+        let install_manager = InstallManager::new(&sys_mock, install_command, &status_submitter, update_ota_status, UpdateJournal::default(), CancellationToken::default());
         let manifest = install_manager.install_manifest(manifest).unwrap();
         #[cfg(unix)]
         BashExec::sync();
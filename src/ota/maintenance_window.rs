@@ -0,0 +1,216 @@
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, NaiveDateTime, NaiveTime, Offset, TimeZone, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+///"maintenance":{
+//       "timezone":"+02:00",
+//       "windows":[
+//          {
+//             "days":["Sat","Sun"],
+//             "start":"22:00",
+//             "end":"05:00"
+//          }
+//       ]
+//    }
+// timezone is "local" (default), "UTC" or a fixed offset, a window ending before its start ends on the next day
+// and empty days mean every day. Without windows, installing is always allowed.
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+    #[serde(default)]
+    pub days: Vec<String>,
+    pub start: String,
+    pub end: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MaintenanceConfig {
+    #[serde(default)]
+    pub timezone: String,
+    #[serde(default)]
+    pub windows: Vec<MaintenanceWindow>,
+}
+
+impl Display for MaintenanceConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let self_serialized = serde_json::to_string_pretty(&self).unwrap();
+        write!(f, "\n{self_serialized}")
+    }
+}
+
+impl MaintenanceWindow {
+    fn times(&self) -> Result<(NaiveTime, NaiveTime), String> {
+        let start = NaiveTime::parse_from_str(&self.start, "%H:%M")
+            .map_err(|e| format!("Invalid window start {}: {}", self.start, e))?;
+        let end = NaiveTime::parse_from_str(&self.end, "%H:%M")
+            .map_err(|e| format!("Invalid window end {}: {}", self.end, e))?;
+        Ok((start, end))
+    }
+
+    fn applies_to(&self, weekday: Weekday) -> bool {
+        self.days.is_empty() || self.days.iter().any(|day| Weekday::from_str(day).ok() == Some(weekday))
+    }
+
+    // The occurrence of this window that starts on the given local day
+    fn occurrence(&self, day: NaiveDateTime) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        let (start, end) = match self.times() {
+            Ok(times) => times,
+            Err(e) => {
+                log::warn!("Ignoring maintenance window: {}", e);
+                return None;
+            }
+        };
+        if !self.applies_to(day.weekday()) {
+            return None;
+        }
+        let start = day.date().and_time(start);
+        let end = if end <= start.time() { day.date().and_time(end) + Duration::days(1) } else { day.date().and_time(end) };
+        Some((start, end))
+    }
+}
+
+impl MaintenanceConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        self.offset(Utc::now())?;
+        for window in &self.windows {
+            window.times()?;
+            if let Some(day) = window.days.iter().find(|day| Weekday::from_str(day).is_err()) {
+                return Err(format!("Invalid window day {}", day));
+            }
+        }
+        Ok(())
+    }
+
+    fn offset(&self, at: DateTime<Utc>) -> Result<FixedOffset, String> {
+        match self.timezone.as_str() {
+            "" | "local" => Ok(Local.offset_from_utc_datetime(&at.naive_utc()).fix()),
+            "UTC" | "utc" | "Z" => Ok(Utc.fix()),
+            offset => DateTime::parse_from_str(&format!("2000-01-01 00:00 {offset}"), "%Y-%m-%d %H:%M %:z")
+                .map(|date| *date.offset())
+                .map_err(|e| format!("Invalid timezone {}: {}", offset, e)),
+        }
+    }
+
+    fn local_offset(&self, at: DateTime<Utc>) -> FixedOffset {
+        self.offset(at).unwrap_or_else(|e| {
+            log::warn!("{}, using UTC", e);
+            Utc.fix()
+        })
+    }
+
+    pub fn is_open(&self, now: DateTime<Utc>) -> bool {
+        self.windows.is_empty() || self.current_window(now).is_some()
+    }
+
+    fn current_window(&self, now: DateTime<Utc>) -> Option<(DateTime<FixedOffset>, DateTime<FixedOffset>)> {
+        let offset = self.local_offset(now);
+        let local_now = now.with_timezone(&offset).naive_local();
+        // A window that started yesterday may still be open
+        [local_now - Duration::days(1), local_now]
+            .iter()
+            .flat_map(|day| self.windows.iter().filter_map(|window| window.occurrence(*day)))
+            .find(|(start, end)| *start <= local_now && local_now < *end)
+            .map(|(start, end)| Self::localize(offset, start, end))
+    }
+
+    // The window that is open now, or the next one to open within a week
+    pub fn next_window(&self, now: DateTime<Utc>) -> Option<(DateTime<FixedOffset>, DateTime<FixedOffset>)> {
+        if let Some(window) = self.current_window(now) {
+            return Some(window);
+        }
+        let offset = self.local_offset(now);
+        let local_now = now.with_timezone(&offset).naive_local();
+        (0..=7)
+            .map(|days| local_now + Duration::days(days))
+            .flat_map(|day| self.windows.iter().filter_map(move |window| window.occurrence(day)))
+            .filter(|(start, _)| *start > local_now)
+            .min_by_key(|(start, _)| *start)
+            .map(|(start, end)| Self::localize(offset, start, end))
+    }
+
+    fn localize(offset: FixedOffset, start: NaiveDateTime, end: NaiveDateTime) -> (DateTime<FixedOffset>, DateTime<FixedOffset>) {
+        (
+            DateTime::from_naive_utc_and_offset(start - offset, offset),
+            DateTime::from_naive_utc_and_offset(end - offset, offset),
+        )
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct MaintenanceStatus {
+    pub config: MaintenanceConfig,
+    pub install_pending: bool,
+}
+
+impl MaintenanceStatus {
+    pub fn report(&self) -> Value {
+        let now = Utc::now();
+        let next_window = self.config.next_window(now).map(|(start, end)| {
+            json!({"start": start.to_rfc3339(), "end": end.to_rfc3339()})
+        });
+        json!({
+            "install_pending": self.install_pending,
+            "window_open": self.config.is_open(now),
+            "next_window": next_window,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_config() -> MaintenanceConfig {
+        serde_json::from_str(r#"{
+            "timezone": "+02:00",
+            "windows": [
+                { "days": ["Sat"], "start": "22:00", "end": "05:00" },
+                { "days": ["Wed"], "start": "12:00", "end": "13:00" }
+            ]
+        }"#).unwrap()
+    }
+
+    fn utc(date: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn window_open_across_midnight() {
+        let config = get_config();
+        assert!(config.validate().is_ok());
+        // Saturday 2023-06-03 23:00 and Sunday 04:00 local time
+        assert!(config.is_open(utc("2023-06-03T21:00:00Z")));
+        assert!(config.is_open(utc("2023-06-04T02:00:00Z")));
+        assert!(!config.is_open(utc("2023-06-04T03:00:00Z")));
+        assert!(!config.is_open(utc("2023-06-03T19:00:00Z")));
+        assert!(MaintenanceConfig::default().is_open(utc("2023-06-03T19:00:00Z")));
+    }
+
+    #[test]
+    fn next_window_is_earliest() {
+        let config = get_config();
+        // Monday 2023-06-05 10:00 local, the Wednesday window comes first
+        let (start, end) = config.next_window(utc("2023-06-05T08:00:00Z")).unwrap();
+        assert_eq!(start.to_rfc3339(), "2023-06-07T12:00:00+02:00");
+        assert_eq!(end.to_rfc3339(), "2023-06-07T13:00:00+02:00");
+        // Thursday, so the Saturday window is next
+        let (start, _) = config.next_window(utc("2023-06-08T08:00:00Z")).unwrap();
+        assert_eq!(start.to_rfc3339(), "2023-06-10T22:00:00+02:00");
+    }
+
+    #[test]
+    fn invalid_config() {
+        let config = MaintenanceConfig {
+            timezone: "Mars/Olympus".to_string(),
+            windows: vec![],
+        };
+        assert!(config.validate().is_err());
+        let config = MaintenanceConfig {
+            timezone: "UTC".to_string(),
+            windows: vec![MaintenanceWindow { days: vec!["Someday".to_string()], start: "10:00".to_string(), end: "11:00".to_string() }],
+        };
+        assert!(config.validate().is_err());
+    }
+}
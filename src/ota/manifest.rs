@@ -1,8 +1,8 @@
 use crate::ota::ota_error::OTAError;
 use crate::utils::file_utils;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
-use std::{fs, ops};
+use std::{fmt, fs, ops};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use url::Url;
@@ -10,7 +10,10 @@ use std::fs::read_dir;
 use version_compare::Version;
 use crate::utils::color::Coloralex;
 use crate::ota::version_table::VersionTable;
-use super::hardcoded_manifest::get_hardcoded_manifest;
+use crate::ota::health_check::HealthCheck;
+use crate::ota::component_hooks::ComponentHooks;
+use crate::ota::component_registry::{component_info, find_component, registered_components, register_server_components, ComponentInfo, ServerComponentInfo};
+use crate::ota::baseline_manifest::baseline_components;
 
 pub const WINDOWS_PHANTOM_AGENT_PATH: &str = "phantom_agent.exe";
 pub const WINDOWS_SERVICE_TRIGGER_PATH: &str = "phantom_agent.flag";
@@ -21,23 +24,42 @@ pub const PREVIOUS_INSTALL_PATH: &str = "previous";
 pub const FUTURE_VERSION_PATH: &str = "future_version";
 pub const DOWNLOAD_DIR: &str = "download";
 
-#[allow(non_camel_case_types)]
-#[derive(Serialize, Deserialize, Hash, PartialEq, Eq, Clone, Copy, Debug)]
-pub enum ComponentType {
-    core,
-    sim_gps_info,
-    phantom_agent,
-    phantom_launcher,
-    translator,
-    vapp,
-    stream_manager,
-    sdk_demo,
-    oden_player,
-    oden_streamer,
-    oden_plugin,
-    oden_webview,
-    autonomy_client,
-    log2jira,
+// A component name, what a component is comes from the component registry
+#[derive(Hash, PartialEq, Eq, Clone, Copy)]
+pub struct ComponentType(&'static str);
+
+#[allow(non_upper_case_globals)]
+impl ComponentType {
+    pub const core: ComponentType = ComponentType("core");
+    pub const sim_gps_info: ComponentType = ComponentType("sim_gps_info");
+    pub const phantom_agent: ComponentType = ComponentType("phantom_agent");
+    pub const phantom_launcher: ComponentType = ComponentType("phantom_launcher");
+    pub const translator: ComponentType = ComponentType("translator");
+    pub const vapp: ComponentType = ComponentType("vapp");
+    pub const stream_manager: ComponentType = ComponentType("stream_manager");
+    pub const sdk_demo: ComponentType = ComponentType("sdk_demo");
+    pub const oden_player: ComponentType = ComponentType("oden_player");
+    pub const oden_streamer: ComponentType = ComponentType("oden_streamer");
+    pub const oden_plugin: ComponentType = ComponentType("oden_plugin");
+    pub const oden_webview: ComponentType = ComponentType("oden_webview");
+    pub const autonomy_client: ComponentType = ComponentType("autonomy_client");
+    pub const log2jira: ComponentType = ComponentType("log2jira");
+
+    pub(crate) fn from_static(name: &'static str) -> Self {
+        ComponentType(name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.0
+    }
+
+    pub fn info(&self) -> ComponentInfo {
+        component_info(self)
+    }
+
+    pub fn node_info(&self, operator: bool) -> ComponentInfo {
+        component_info(self).for_node(operator)
+    }
 }
 
 pub fn current_agent_version() -> String {
@@ -48,31 +70,40 @@ impl FromStr for ComponentType {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(match s {
-            "core" => ComponentType::core,
-            "sim_gps_info" => ComponentType::sim_gps_info,
-            "phantom_agent" => ComponentType::phantom_agent,
-            "phantom_launcher" => ComponentType::phantom_launcher,
-            "translator" => ComponentType::translator,
-            "vapp" => ComponentType::vapp,
-            "stream_manager" => ComponentType::stream_manager,
-            "sdk_demo" => ComponentType::sdk_demo,
-            "oden_player" => ComponentType::oden_player,
-            "oden_streamer" => ComponentType::oden_streamer,
-            "oden_plugin" => ComponentType::oden_plugin,
-            "oden_webview" => ComponentType::oden_webview,
-            "autonomy_client" => ComponentType::autonomy_client,
-            "log2jira" => ComponentType::log2jira,
-            _ => {
-                return Err("No matching snap component".to_string());
-            }
-        })
+        find_component(s).map(ComponentType).ok_or_else(|| "No matching snap component".to_string())
+    }
+}
+
+impl fmt::Display for ComponentType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Debug for ComponentType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Serialize for ComponentType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0)
+    }
+}
+
+// Only names in the component registry
+impl<'de> Deserialize<'de> for ComponentType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        ComponentType::from_str(&name).map_err(|_| serde::de::Error::custom(format!("Unknown component {}", name)))
     }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Component {
     pub component: String,
+    // A sha1 digest, or one that declares its algorithm like "sha256:<hex>". Stored as is in the hash manifest
     pub checksum: String,
     #[serde(default)]
     pub updated: bool,
@@ -92,6 +123,19 @@ pub struct Component {
     pub previous_install_path: Option<PathBuf>,
     #[serde(default)]
     pub processes: Vec<String>,
+    // Verified after the install, a failing check rolls the update back
+    #[serde(default)]
+    pub health_checks: Vec<HealthCheck>,
+    // Component names, see component_order
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    #[serde(default)]
+    pub conflicts_with: Vec<String>,
+    #[serde(default, skip_serializing_if = "ComponentHooks::is_empty")]
+    pub hooks: ComponentHooks,
+    // Base64 ed25519 signature of the package, see signature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
 }
 
 impl Component {
@@ -108,6 +152,11 @@ impl Component {
             package_type: "".to_string(),
             previous_install_path: None,
             processes: vec![],
+            health_checks: vec![],
+            depends_on: vec![],
+            conflicts_with: vec![],
+            hooks: Default::default(),
+            signature: None,
         }
     }
 
@@ -153,6 +202,7 @@ impl ops::Add<Component> for Component {
                 }
             },
             checksum: second.checksum,
+            signature: second.signature,
             updated: second.updated,
             processes: {
                 if !second.processes.is_empty() {
@@ -161,6 +211,34 @@ impl ops::Add<Component> for Component {
                     self.processes
                 }
             },
+            health_checks: {
+                if !second.health_checks.is_empty() {
+                    second.health_checks
+                } else {
+                    self.health_checks
+                }
+            },
+            depends_on: {
+                if !second.depends_on.is_empty() {
+                    second.depends_on
+                } else {
+                    self.depends_on
+                }
+            },
+            conflicts_with: {
+                if !second.conflicts_with.is_empty() {
+                    second.conflicts_with
+                } else {
+                    self.conflicts_with
+                }
+            },
+            hooks: {
+                if !second.hooks.is_empty() {
+                    second.hooks
+                } else {
+                    self.hooks
+                }
+            },
             previous_install_path: {
                 if self.previous_install_path.is_none() { // Only for tests, probably!
                     second.previous_install_path
@@ -219,6 +297,13 @@ impl HashManifest {
         })
     }
 
+    // Checksum the component is installed with, empty when it isn't installed
+    pub fn installed_checksum(&self, server_name: &str, operator: bool, component_type: &ComponentType) -> String {
+        let full_server_name = full_server_name(server_name, operator);
+        let which_server = if is_meta_component(component_type) { META_SERVER_NAME } else { &full_server_name };
+        self.components.get(which_server).and_then(|server| server.get(component_type)).cloned().unwrap_or_default()
+    }
+
     pub fn standardize(self, server_name: String, operator: bool) -> Self { // Enforce backwards compatibility
         let full_server_name = full_server_name(&server_name, operator);
         let mut new_hash = HashMap::new();
@@ -293,16 +378,19 @@ impl HashManifest {
         result
     }
 
-    pub fn verify_version(&self, my_version_str: String) {
+    // A version file ahead of us means a self update was lost, the hash manifest cannot be trusted
+    pub fn verify_version(&self, my_version_str: String) -> Result<(), OTAError> {
         let zero_version = Version::from("").unwrap();
-        let my_version = Version::from(&my_version_str).unwrap();
+        let my_version = Version::from(&my_version_str)
+            .ok_or_else(|| OTAError::nonfatal(format!("Cannot parse agent version [{}]", my_version_str)))?;
         let mut file_version_str = "".to_string();
         match (file_utils::file_to_string)(&self.future_version_path) {
             Ok(version) => { file_version_str = version; }
             Err(e) => { log::warn!( "Could not read future version file ({}), updating to {}", e, my_version); }
         };
 
-        let mut file_version = Version::from(&file_version_str).unwrap();
+        let mut file_version = Version::from(&file_version_str)
+            .ok_or_else(|| OTAError::state_corruption(format!("Cannot parse version file content [{}]", file_version_str)))?;
 
         if file_version == zero_version || file_version < my_version { // Proper simver compare
             if !(file_version == zero_version) {
@@ -313,9 +401,9 @@ impl HashManifest {
             }
             file_version = match self.update_version_file(my_version.to_string()) {
                 Ok(_) => my_version.clone(),
-                Err(_) => {
+                Err(e) => {
                     log::error!("Failed to update version file!");
-                    panic!("Failed to update version file, version tracking impossible!");
+                    return Err(OTAError::state_corruption(format!("Failed to update version file, version tracking impossible! ({})", e)));
                 }
             }
         }
@@ -328,10 +416,11 @@ impl HashManifest {
                 my_version
             );
             log::error!("{error_msg}");
-            panic!("{error_msg}");
+            return Err(OTAError::state_corruption(error_msg));
         }
 
         log::info!("Successfully verified version as {}", my_version);
+        Ok(())
     }
 }
 
@@ -368,6 +457,11 @@ pub fn get_log2jira_version() -> String {
     current_version
 }
 
+// Meta components are shared by all servers in the hash manifest
+pub fn is_meta_component(component_type: &ComponentType) -> bool {
+    component_type.info().meta
+}
+
 pub fn full_server_name(server_name: &str, operator: bool) -> String {
     let server_name = if let Some(stripped) = server_name.strip_prefix("http://") { stripped } else { server_name };
     let server_name = if let Some(stripped) = server_name.strip_prefix("https://") { stripped } else { server_name };
@@ -385,6 +479,8 @@ pub struct Manifest {
     pub server_name: String,
     pub version: String,
     pub operator: bool,
+    // Components pinned on this node, see ComponentHolds
+    pub held_components: Vec<String>,
 }
 
 impl Manifest {
@@ -406,23 +502,30 @@ impl Manifest {
                     hash_path.to_str().unwrap(),
                     e
                 );
-                panic!("{}", e);
+                return Err(e);
             }
         };
 
         let full_server_name = full_server_name(&server_name, operator);
 
-        let components = match Manifest::parse_json_file(&get_hardcoded_manifest(operator)) {
-            Ok(components) => {
+        let components = match baseline_components(operator) {
+            Ok(mut components) => {
+                // Installed components the baseline doesn't know, e.g. rolled out by a server manifest
+                for (component_type, info) in registered_components() {
+                    let which_server = if info.meta { META_SERVER_NAME } else { &full_server_name };
+                    let installed = hash_manifest.components.get(which_server)
+                        .and_then(|server| server.get(&component_type))
+                        .map(|checksum| !checksum.is_empty())
+                        .unwrap_or(false);
+                    if installed && !components.contains_key(&component_type) {
+                        components.insert(component_type, Component { component: component_type.to_string(), ..Component::empty() });
+                    }
+                }
                 let components: HashMap<ComponentType, Component> = components
                     .into_iter()
                     .map(|(component_type, prev_component)| {
-                        let which_server = match component_type {
-                            ComponentType::phantom_agent |
-                            ComponentType::phantom_launcher |
-                            ComponentType::log2jira => { META_SERVER_NAME }
-                            _ => { &full_server_name }
-                        };
+                        let prev_component = component_type.node_info(operator).apply_to(prev_component);
+                        let which_server = if is_meta_component(&component_type) { META_SERVER_NAME } else { &full_server_name };
                         let version = match component_type {
                             ComponentType::phantom_agent => { // Current agent version from env!
                                 current_agent_version()
@@ -470,8 +573,8 @@ impl Manifest {
                 components
             }
             Err(e) => {
-                log::error!("Failed to parse hard-coded manifest: {}", e);
-                panic!("{}", e);
+                log::error!("Failed to load the baseline manifest: {}", e);
+                return Err(e);
             }
         };
         Ok(Self {
@@ -481,9 +584,14 @@ impl Manifest {
             previous_install_path,
             server_name,
             operator,
+            held_components: vec![],
         })
     }
 
+    pub fn with_holds(self, held_components: Vec<String>) -> Self {
+        Self { held_components, ..self }
+    }
+
     pub fn prepare_for_server_purge(self) -> Manifest {
         let full_server_name = full_server_name(&self.server_name, self.operator);
         if !self.hash_manifest.components.contains_key(&full_server_name) {
@@ -532,6 +640,13 @@ impl Manifest {
             let missing_components: Vec<Component> = serde_json::from_str(json).map_err(|error| error.to_string())?;
             ServerManifestModel { version: "not_supported".to_string(), missing_components }
         };
+        // Components the agent doesn't know yet come with their registry attributes
+        let raw_json: serde_json::Value = serde_json::from_str(json).map_err(|error| error.to_string())?;
+        let raw_components = raw_json.get("missingComponents").unwrap_or(&raw_json).clone();
+        match serde_json::from_value::<Vec<ServerComponentInfo>>(raw_components) {
+            Ok(server_components) => register_server_components(server_components),
+            Err(e) => log::warn!("Could not read registry attributes from the backend manifest: {}", e),
+        }
         let log_version_string = format!("Backend manifest is: {}", parsed_json.version);
         log::info!("{}", log_version_string.yellow(true));
 
@@ -542,7 +657,7 @@ impl Manifest {
 
         if let Some(new_agent) = parsed_json.missing_components
             .iter()
-            .find(|component| component.component == "phantom_agent")
+            .find(|component| component.component == "phantom_agent" && !self.held_components.contains(&component.component))
         {
             // Technically shouldn't be a use case, but just in case we don't have agent in hardcoded manifest
             if let Some((_, old_agent)) = self.components.iter().find(|(old_agent_type, _old_agent)| {
@@ -571,10 +686,15 @@ impl Manifest {
             }
         }
 
+        let held_components = self.held_components.clone();
         let components = self
             .components
             .into_iter()
             .map(|(component_type, prev_component)| {
+                if held_components.contains(&prev_component.component) {
+                    log::info!("update_with_json: {} is held, keeping it as is", prev_component.component);
+                    return (component_type, prev_component);
+                }
                 let found = parsed_json.missing_components
                     .iter()
                     .find(|component| component.component == prev_component.component);
@@ -617,9 +737,38 @@ impl Manifest {
                 (component_type, prev_component)
             })
             .collect();
+        let full_server_name = full_server_name(&self.server_name, self.operator);
+        let components = Self::add_new_components(components, &parsed_json.missing_components, &held_components, &self.previous_install_path, &full_server_name, self.operator);
         Ok(Self { version: parsed_json.version, components, ..self })
     }
 
+    // Server components the baseline doesn't have, known from the registry
+    fn add_new_components(mut components: HashMap<ComponentType, Component>, server_components: &[Component], held_components: &[String],
+                          previous_install_path: &Path, full_server_name: &str, operator: bool) -> HashMap<ComponentType, Component> {
+        for new_component in server_components {
+            if held_components.contains(&new_component.component) ||
+                components.values().any(|component| component.component == new_component.component) {
+                continue;
+            }
+            let component_type = match ComponentType::from_str(&new_component.component) {
+                Ok(component_type) if !component_type.info().package_type.is_empty() => component_type,
+                _ => {
+                    log::warn!("update_with_json: {} is not in the component registry, ignoring it", new_component.component);
+                    continue;
+                }
+            };
+            let info = component_type.node_info(operator);
+            let which_server = if info.meta { META_SERVER_NAME } else { full_server_name };
+            let previous_install_path = if previous_install_path == Path::new("") { None } else {
+                Some(previous_install_path.join(which_server).join(&new_component.component))
+            };
+            log::info!("update_with_json: {} is being added (new component)", new_component.component);
+            let component = Component { component: new_component.component.clone(), previous_install_path, ..Component::empty() };
+            components.insert(component_type, info.apply_to(component + new_component.clone()));
+        }
+        components
+    }
+
     pub fn update_components_paths(
         self,
         paths: HashMap<ComponentType, PathBuf>,
@@ -658,18 +807,15 @@ impl Manifest {
         let mut components = HashMap::new();
         for (component_type, component) in &self.components {
             if component.updated { // Not updating hashmap if the component wasn't installed!
-                match component_type {
-                    ComponentType::phantom_agent |
-                    ComponentType::phantom_launcher |
-                    ComponentType::log2jira => { meta_components.insert(*component_type, component.checksum.clone()); }
-                    _ => { components.insert(*component_type, component.checksum.clone()); }
+                if is_meta_component(component_type) {
+                    meta_components.insert(*component_type, component.checksum.clone());
+                } else {
+                    components.insert(*component_type, component.checksum.clone());
                 }
             }
         }
         let hash_manifest = self.hash_manifest.update_components(meta_components, components, self.server_name.clone(), self.operator);
-        hash_manifest
-            .write_to_file()
-            .expect("Failed to write to file");
+        hash_manifest.write_to_file()?;
 
         VersionTable::new().update_version_file(&self.server_name, &self.version);
         Ok(Self {
@@ -678,16 +824,25 @@ impl Manifest {
         })
     }
 
-
-    pub fn parse_json_array(json: &str) -> Result<Vec<Component>, String> {
-        let components = serde_json::from_str(json);
-        match components {
-            Ok(components) => Ok(components),
-            Err(e) => Err(e.to_string()),
+    // Writes only the given checksums into the hash manifest, leaving the version table untouched
+    pub fn commit_checksums(self, checksums: &HashMap<ComponentType, String>) -> Result<Self, String> {
+        let full_server_name = full_server_name(&self.server_name, self.operator);
+        let mut hash_components = self.hash_manifest.components.clone();
+        let mut components = self.components;
+        for (component_type, checksum) in checksums {
+            let which_server = if is_meta_component(component_type) { META_SERVER_NAME.to_string() } else { full_server_name.clone() };
+            hash_components.entry(which_server).or_default().insert(*component_type, checksum.clone());
+            if let Some(component) = components.get_mut(component_type) {
+                component.checksum = checksum.clone();
+                component.updated = true;
+            }
         }
+        let hash_manifest = HashManifest { components: hash_components, ..self.hash_manifest };
+        hash_manifest.write_to_file()?;
+        Ok(Self { components, hash_manifest, ..self })
     }
 
-    fn parse_json_file(json: &str) -> Result<HashMap<ComponentType, Component>, String> {
+    pub fn parse_json_array(json: &str) -> Result<Vec<Component>, String> {
         let components = serde_json::from_str(json);
         match components {
             Ok(components) => Ok(components),
@@ -695,12 +850,25 @@ impl Manifest {
         }
     }
 
+    // Entries of components the registry doesn't know are dropped, the server installs them again once it registers them
     fn parse_hash_file(json: &str) -> Result<HashMap<String, HashMap<ComponentType, String>>, String> {
-        let components = serde_json::from_str(json);
-        match components {
-            Ok(components) => Ok(components),
-            Err(e) => Err(e.to_string()),
-        }
+        let servers: HashMap<String, HashMap<String, String>> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        Ok(servers
+            .into_iter()
+            .map(|(server, components)| {
+                let components = components
+                    .into_iter()
+                    .filter_map(|(name, checksum)| match ComponentType::from_str(&name) {
+                        Ok(component_type) => Some((component_type, checksum)),
+                        Err(_) => {
+                            log::warn!("Hash manifest: {} ({}) is not in the component registry, dropping it", name, server);
+                            None
+                        }
+                    })
+                    .collect();
+                (server, components)
+            })
+            .collect())
     }
 
     pub fn update_single_component(self, new_component: &Component) -> Result<Self, String> {
@@ -821,6 +989,56 @@ mod tests {
         assert_eq!(core_component.link, Some(Url::parse("https://phantomauto.jfrog.io/artifactory/Phantom.Binary/SDK-Phantom-Agent/0.1.2/amd64/phantom-agent_0.1.2_amd64.snap").unwrap()));
     }
 
+    #[test]
+    fn new_component_from_server() {
+        let write_function = |_: &Path, _: &str| Ok(());
+        let manifest = Manifest::new(true, PathBuf::from("./hash_manifest.json"), PathBuf::from("./previous"), Default::default(),
+                                     read_function, write_function).unwrap();
+        let server_manifest_json = r#"
+        { "version":"2.0", "missingComponents":[
+            { "component":"lidar_driver", "version":"1.0", "checksum":"abc", "link":"https://test.com/lidar.tar",
+              "package_type":"tar", "target_path":"/opt/lidar", "processes":["lidar"] },
+            { "component":"unknown_thing", "version":"1.0", "checksum":"abc", "link":"https://test.com/unknown" }
+        ]}"#;
+        let manifest = manifest.update_with_json(server_manifest_json).unwrap();
+        let lidar = &manifest.components[&ComponentType::from_str("lidar_driver").unwrap()];
+        assert!(!lidar.updated);
+        assert_eq!(lidar.package_type, "tar");
+        assert_eq!(lidar.target_path, Some(PathBuf::from("/opt/lidar")));
+        assert_eq!(lidar.previous_install_path, Some(PathBuf::from("./previous/O_/lidar_driver")));
+        assert!(!manifest.components.values().any(|component| component.component == "unknown_thing"));
+    }
+
+    #[test]
+    fn held_components_are_kept() {
+        let write_function = |_: &Path, _: &str| Ok(());
+        let manifest = Manifest::new(
+            true,
+            PathBuf::from("./hash_manifest.json"),
+            PathBuf::from("./previous"),
+            "test_server".to_string(),
+            read_function,
+            write_function,
+        )
+            .unwrap()
+            .with_holds(vec!["core".to_string(), "oden_plugin".to_string()]);
+        let server_manifest_json = r#"
+        {
+            "version": "1.28",
+            "missingComponents": [
+                { "token": "token", "component": "core", "version": "0.1.3", "link": "https://core_link", "checksum": "new core checksum" },
+                { "token": "token", "component": "oden_webview", "version": "0.1.3", "link": "https://webview_link", "checksum": "new webview checksum" }
+            ]
+        }"#;
+        let manifest = manifest.update_with_json(server_manifest_json).unwrap();
+        let core_component = &manifest.components[&ComponentType::core];
+        assert!(core_component.updated);
+        assert_eq!(core_component.checksum, "core checksum");
+        // Missing from the server list, but held, so not uninstalled
+        assert!(manifest.components[&ComponentType::oden_plugin].updated);
+        assert!(!manifest.components[&ComponentType::oden_webview].updated);
+    }
+
     #[test]
     fn update_from_hash() {
         let write_function = |_: &Path, str: &str| {
@@ -946,10 +1164,10 @@ mod tests {
             file_utils::string_to_file,
         )
             .unwrap();
-        manifest.hash_manifest.verify_version("TEST_VERSION1".to_string()); // Initializing to TEST_VERSION1
-        manifest.hash_manifest.verify_version("TEST_VERSION1".to_string()); // Verifying as TEST_VERSION1 (expecting success)
-        manifest.hash_manifest.verify_version("TEST_VERSION2".to_string()); // TEST_VERSION2 is HIGHER than TEST_VERSION1, so we expect it to PASS and update to TEST_VERSION2
-        let result = std::panic::catch_unwind(|| manifest.hash_manifest.verify_version("TEST_VERSION1".to_string()));
+        assert!(manifest.hash_manifest.verify_version("TEST_VERSION1".to_string()).is_ok()); // Initializing to TEST_VERSION1
+        assert!(manifest.hash_manifest.verify_version("TEST_VERSION1".to_string()).is_ok()); // Verifying as TEST_VERSION1 (expecting success)
+        assert!(manifest.hash_manifest.verify_version("TEST_VERSION2".to_string()).is_ok()); // TEST_VERSION2 is HIGHER than TEST_VERSION1, so we expect it to PASS and update to TEST_VERSION2
+        let result = manifest.hash_manifest.verify_version("TEST_VERSION1".to_string());
         assert!(result.is_err()); // This time we are TEST_VERSION1 but what we should be was updated to TEST_VERSION2 so it should fail!
         fs::remove_dir_all(test_dir).expect("Failed to cleanup!");
     }
@@ -1,8 +1,21 @@
+pub mod agent_boot_guard;
+pub mod artifact_store;
+pub mod bandwidth;
+pub mod baseline_manifest;
+pub mod bundle_export;
+pub mod busy_detector;
+pub mod cancellation;
+pub mod component_holds;
+pub mod component_order;
+pub mod component_registry;
 pub mod deb_installer;
 mod disk_space_verifier;
 mod download_manager;
 pub mod file_system;
+pub mod health_check;
+pub mod component_hooks;
 mod install_manager;
+pub mod maintenance_window;
 pub mod manifest;
 pub mod hardcoded_manifest;
 pub mod system_ctl;
@@ -10,10 +23,17 @@ pub mod system_ctl;
 pub mod msi_installer;
 pub mod ota_error;
 pub mod ota_manager;
+pub mod peer_cache;
 pub mod rest_listener;
+pub mod retry_backoff;
 mod service_control_trait;
+pub mod sideload;
+pub mod signature;
 pub mod snap_installer;
 pub mod tar_installer;
+pub mod update_history;
+pub mod update_journal;
+pub mod update_plan;
 pub mod version_table;
 pub mod ota_status;
 #[cfg(windows)]
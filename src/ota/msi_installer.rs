@@ -67,7 +67,7 @@ impl MsiInstaller {
         let path = component.path.clone().unwrap_or_default();
         let success_str = "Installation succeeded".to_string();
         if !path.exists() {
-            return Err(OTAError::nonfatal(format!(
+            return Err(OTAError::installer("msi", format!(
                 "{} not found",
                 path.to_string_lossy()
             )));
@@ -91,7 +91,7 @@ impl MsiInstaller {
                 while !Self::ensure_msiexec_mutex() {
                     if sec_timeout >= TIMEOUT_SEC {
                         log::error!("Failed to obtain msiexec mutex");
-                        return Err(OTAError::nonfatal("Failed to obtain msiexec mutex".to_string()));
+                        return Err(OTAError::installer("msi", "Failed to obtain msiexec mutex".to_string()));
                     }
                     log::warn!("Another instance of msiexec is running, update will continue when it closes (will wait {} more seconds)", TIMEOUT_SEC - sec_timeout);
                     sleep(Duration::from_secs(5));
@@ -131,7 +131,7 @@ impl MsiInstaller {
                     }
                     else { fs::remove_file(prev_log_file).unwrap_or(()); }
                 }
-                return Err(OTAError::nonfatal(e));
+                return Err(OTAError::installer("msi", e));
             }
 
             let ids = MsiInstaller::get_registry_ids(&product_name);
@@ -172,14 +172,14 @@ impl MsiInstaller {
                 }
                 else { fs::remove_file(prev_log_file).unwrap_or(()); }
             }
-            Err(OTAError::nonfatal(error_str))
+            Err(OTAError::installer("msi", error_str))
         } else {
             let error_str = format!(
                 "Could not extract name from msi file {}",
                 path.to_string_lossy()
             );
             log::error!("{}", error_str);
-            Err(OTAError::nonfatal(error_str))
+            Err(OTAError::installer("msi", error_str))
         }
     }
 
@@ -240,14 +240,14 @@ impl MsiInstaller {
         let path = component.path.clone().unwrap_or_default();
         let success_str = "Uninstallation succeeded".to_string();
         if !path.exists() {
-            return Err(OTAError::nonfatal(format!(
+            return Err(OTAError::installer("msi", format!(
                 "{} not found",
                 path.to_string_lossy()
             )));
         }
         match MsiInstaller::uninstall_from_file(&path, &component.component, exec_command) {
             Ok(()) => { Ok(success_str) }
-            Err(e) => { Err(OTAError::nonfatal(e)) }
+            Err(e) => { Err(OTAError::installer("msi", e)) }
         }
     }
 
@@ -348,6 +348,8 @@ impl MsiInstaller {
 #[cfg(test)]
 #[cfg(windows)]
 mod tests {
+    use crate::ota::update_journal::UpdateJournal;
+    use crate::ota::cancellation::CancellationToken;
     use std::cell::RefCell;
     use std::env::current_dir;
     use crate::ota::msi_installer::MsiInstaller;
@@ -630,7 +632,7 @@ mod tests {
         let sys_mock = RefCell::new(mock);
         let update_status_response = |_ota_status, _message|{};
         let download_manager =
-            DownloadManager::new(&sys_mock, &rest_comm, test_dir.clone(), update_status_response).unwrap();
+            DownloadManager::new(&sys_mock, &rest_comm, test_dir.clone(), update_status_response, UpdateJournal::default(), CancellationToken::default()).unwrap();
 
         println!("Creating manifest");
         let write_function = |_: &Path, _: &str| Ok(());
@@ -654,6 +656,11 @@ mod tests {
             package_type: "snap".to_string(),
             previous_install_path: Some(previous.clone()),
             processes: vec![],
+            health_checks: vec![],
+            depends_on: vec![],
+            conflicts_with: vec![],
+            hooks: Default::default(),
+            signature: None,
         };
 
         let updated_manifest = manifest.update_single_component(&component).unwrap();
@@ -1,4 +1,6 @@
 use crate::utils::color::Coloralex;
+use serde::Serialize;
+use serde_json::{json, Value};
 use std::fmt;
 
 #[derive(PartialEq, Debug, Clone)]
@@ -7,29 +9,166 @@ pub enum OTAErrorSeverity {
     FatalError,
 }
 
+// What went wrong, the code of each kind is stable so the dashboard can group on it
+#[derive(Serialize, PartialEq, Eq, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OTAErrorKind {
+    Network,
+    Auth,
+    ChecksumMismatch { component: String, expected: String, actual: String },
+    DiskSpace,
+    Installer { package_type: String, output: String },
+    Rollback,
+    StateCorruption,
+    HealthCheck,
+    Dependency,
+    Hook { component: String, stage: String },
+    Bundle,
+    Signature { component: String },
+    Cancelled,
+    Internal,
+}
+
+impl OTAErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            OTAErrorKind::Network => "OTA-NETWORK",
+            OTAErrorKind::Auth => "OTA-AUTH",
+            OTAErrorKind::ChecksumMismatch { .. } => "OTA-CHECKSUM",
+            OTAErrorKind::DiskSpace => "OTA-DISK-SPACE",
+            OTAErrorKind::Installer { .. } => "OTA-INSTALLER",
+            OTAErrorKind::Rollback => "OTA-ROLLBACK",
+            OTAErrorKind::StateCorruption => "OTA-STATE",
+            OTAErrorKind::HealthCheck => "OTA-HEALTH-CHECK",
+            OTAErrorKind::Dependency => "OTA-DEPENDENCY",
+            OTAErrorKind::Hook { .. } => "OTA-HOOK",
+            OTAErrorKind::Bundle => "OTA-BUNDLE",
+            OTAErrorKind::Signature { .. } => "OTA-SIGNATURE",
+            OTAErrorKind::Cancelled => "OTA-CANCELLED",
+            OTAErrorKind::Internal => "OTA-INTERNAL",
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct OTAError {
     pub severity: OTAErrorSeverity,
+    pub kind: OTAErrorKind,
     pub message: String,
 }
 
 impl OTAError {
+    fn new(severity: OTAErrorSeverity, kind: OTAErrorKind, message: String) -> OTAError {
+        OTAError { severity, kind, message }
+    }
+
     pub fn nonfatal(message: String) -> OTAError {
-        OTAError {
-            severity: OTAErrorSeverity::NonFatalError,
-            message,
-        }
+        Self::new(OTAErrorSeverity::NonFatalError, OTAErrorKind::Internal, message)
     }
 
     pub fn fatal(message: String) -> OTAError {
-        OTAError {
-            severity: OTAErrorSeverity::FatalError,
+        Self::new(OTAErrorSeverity::FatalError, OTAErrorKind::Internal, message)
+    }
+
+    pub fn network(message: String) -> OTAError {
+        Self::new(OTAErrorSeverity::NonFatalError, OTAErrorKind::Network, message)
+    }
+
+    pub fn auth(message: String) -> OTAError {
+        Self::new(OTAErrorSeverity::NonFatalError, OTAErrorKind::Auth, message)
+    }
+
+    pub fn checksum_mismatch(component: &str, expected: &str, actual: &str) -> OTAError {
+        Self::new(
+            OTAErrorSeverity::NonFatalError,
+            OTAErrorKind::ChecksumMismatch {
+                component: component.to_string(),
+                expected: expected.to_string(),
+                actual: actual.to_string(),
+            },
+            format!("Checksum mismatch for {}: expected {}, got {}", component, expected, actual),
+        )
+    }
+
+    pub fn disk_space(message: String) -> OTAError {
+        Self::new(OTAErrorSeverity::FatalError, OTAErrorKind::DiskSpace, message)
+    }
+
+    // output is what the installer printed or returned
+    pub fn installer(package_type: &str, output: String) -> OTAError {
+        Self::new(
+            OTAErrorSeverity::NonFatalError,
+            OTAErrorKind::Installer { package_type: package_type.to_string(), output: output.clone() },
+            output,
+        )
+    }
+
+    pub fn rollback(message: String) -> OTAError {
+        Self::new(OTAErrorSeverity::FatalError, OTAErrorKind::Rollback, message)
+    }
+
+    pub fn state_corruption(message: String) -> OTAError {
+        Self::new(OTAErrorSeverity::FatalError, OTAErrorKind::StateCorruption, message)
+    }
+
+    pub fn health_check(message: String) -> OTAError {
+        Self::new(OTAErrorSeverity::NonFatalError, OTAErrorKind::HealthCheck, message)
+    }
+
+    // Dependency cycles, conflicts or a missing dependency in the manifest
+    pub fn dependency(message: String) -> OTAError {
+        Self::new(OTAErrorSeverity::NonFatalError, OTAErrorKind::Dependency, message)
+    }
+
+    // A pre/post install or uninstall command of the component failed
+    pub fn hook(component: &str, stage: &str, message: String) -> OTAError {
+        Self::new(
+            OTAErrorSeverity::NonFatalError,
+            OTAErrorKind::Hook { component: component.to_string(), stage: stage.to_string() },
             message,
-        }
+        )
     }
+
+    // An offline bundle that can't be read or doesn't match its manifest
+    pub fn bundle(message: String) -> OTAError {
+        Self::new(OTAErrorSeverity::NonFatalError, OTAErrorKind::Bundle, message)
+    }
+
+    // A package that is not signed while it has to be, or not by a pinned key
+    pub fn signature(component: &str, message: String) -> OTAError {
+        Self::new(OTAErrorSeverity::NonFatalError, OTAErrorKind::Signature { component: component.to_string() }, message)
+    }
+
+    pub fn cancelled(message: String) -> OTAError {
+        Self::new(OTAErrorSeverity::NonFatalError, OTAErrorKind::Cancelled, message)
+    }
+
+    // Same error, escalated
+    pub fn into_fatal(self) -> OTAError {
+        OTAError { severity: OTAErrorSeverity::FatalError, ..self }
+    }
+
     pub fn message(&self) -> String{
         self.message.clone()
     }
+
+    pub fn code(&self) -> &'static str {
+        self.kind.code()
+    }
+
+    // Message with the error code, as sent to the cloud and shown in /status
+    pub fn report(&self) -> String {
+        format!("[{}] {}", self.code(), self.message)
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "code": self.code(),
+            "fatal": self.severity == OTAErrorSeverity::FatalError,
+            "message": self.message,
+            "details": self.kind,
+        })
+    }
 }
 
 impl fmt::Display for OTAError {
@@ -47,6 +186,7 @@ impl fmt::Debug for OTAError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("OTAError")
             .field("severity", &self.severity)
+            .field("kind", &self.kind)
             .field("message", &self.message)
             .finish()
     }
@@ -54,9 +194,24 @@ impl fmt::Debug for OTAError {
 
 impl From<String> for OTAError {
     fn from(message: String) -> OTAError {
-        OTAError {
-            severity: OTAErrorSeverity::NonFatalError,
-            message,
-        }
+        OTAError::nonfatal(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_codes() {
+        let error = OTAError::installer("deb", "dpkg: error processing archive".to_string());
+        assert_eq!(error.report(), "[OTA-INSTALLER] dpkg: error processing archive");
+        assert_eq!(error.to_json()["details"]["package_type"], "deb");
+        assert_eq!(error.into_fatal().severity, OTAErrorSeverity::FatalError);
+
+        let error = OTAError::checksum_mismatch("core", "abc", "def");
+        assert_eq!(error.code(), "OTA-CHECKSUM");
+        assert_eq!(error.to_json()["details"]["kind"], "checksum_mismatch");
+        assert_eq!(OTAError::from("text".to_string()).code(), "OTA-INTERNAL");
     }
 }
@@ -7,41 +7,64 @@ use crate::{auth::{
     file_system::FileSystem,
     install_manager::InstallManager,
     manifest::Manifest,
-    ota_error::{OTAError, OTAErrorSeverity},
+    ota_error::{OTAError, OTAErrorKind, OTAErrorSeverity},
     service_control_trait::SystemControlTrait,
 }, rest_comm::{
     core_rest_comm::CoreRestComm,
     core_rest_comm_trait::CoreRestCommTrait,
     coupling_rest_comm::{CouplingRestComm, RESTRequestFunction},
     coupling_submit_trait::{CouplingRestSubmitter, NodeOtaProgressStatus},
-    jira_log_submitter::{send_snapshot_to_jira, JIRA_REPORT_TICKET},
-}, rest_request::RestServer, service_trait::Action, utils::color::Coloralex, VersionTable};
+    jira_log_submitter::{send_error_snapshot_to_jira, send_snapshot_to_jira, JIRA_REPORT_TICKET},
+}, rest_request::RestServer, service_trait::{Action, RetryReason}, utils::color::Coloralex, VersionTable};
 use crate::{utils, RestMessage, ServiceTrait, config::Config};
 use log;
 use serde::Serialize;
 use std::{
-    cell::RefCell, fs, panic, panic::PanicInfo, path::PathBuf, sync::mpsc, thread::sleep, time::Duration,
-    backtrace::{Backtrace, BacktraceStatus}, ops::Deref
+    cell::{Cell, RefCell}, collections::HashMap, fs, panic, panic::{AssertUnwindSafe, PanicInfo}, path::{Path, PathBuf}, str::FromStr, sync::mpsc,
+    thread::sleep, time::{Duration, Instant}, backtrace::{Backtrace, BacktraceStatus}, ops::Deref
 };
 use hyper::Uri;
+use chrono::Utc;
 use url::Url;
 use serde_json::{json, Value};
 use crate::auth::auth_manager::fetch_license_manager;
-use crate::config::{ArchType, get_arch};
+use crate::config::{get_arch, is_operator_arch};
 
-use crate::ota::manifest::{Component, current_agent_version, PREVIOUS_INSTALL_PATH};
-use crate::utils::file_utils::{create_dir_if_not_exists, file_to_string};
+use crate::ota::manifest::{Component, ComponentType, current_agent_version, FUTURE_VERSION_PATH, PREVIOUS_INSTALL_PATH};
+use crate::ota::artifact_store::{ArtifactStore, ARTIFACT_STORE_DIR};
+use crate::utils::file_utils::{create_dir_if_not_exists, file_to_string, string_to_file};
 
 #[cfg(not(windows))]
 use crate::utils::bash_exec::BashExec;
 pub const UPDATE_BOTH_STATUS_FILE: &str = "update_both_status";
 pub const INCOMPLETE_INSTALL_STATUS_FILE: &str = "incomplete_install";
+// Seconds between looks into the sideload directory
+const SIDELOAD_POLL_INTERVAL: u64 = 10;
+
+thread_local! {
+    // Panics inside run_once are caught, the panic hook must not claim OTA has terminated
+    static CYCLE_RUNNING: Cell<bool> = const { Cell::new(false) };
+}
 #[cfg(not(windows))]
 pub const LOG_STRING: &str ="Phantom Agent is checking for updates, run\n\njournalctl -u snap.phantom-agent.phantom-agent-daemon.service -fo cat\n\nto follow the progress.\n";
 #[cfg(windows)]
 pub const LOG_STRING: &str ="Phantom Agent is checking for updates, run\n\npowershell Get-Content 'C:\\Program Files\\phantom_agent\\log\\phantom_agent.log' -Wait -Tail 30\n\nto follow the progress.\n";
 
 use crate::ota::ota_status::{OTAStatus, OTAStatusRestResponse};
+use crate::ota::cancellation::CancellationToken;
+use crate::ota::component_holds::{held_components, COMPONENT_HOLDS_FILE};
+use crate::ota::component_registry::{load_component_registry, COMPONENT_REGISTRY_FILE};
+use crate::ota::baseline_manifest::BASELINE_MANIFEST_FILE;
+use crate::ota::bundle_export::{BundleExporter, EXPORT_DIR};
+use crate::ota::peer_cache::ArtifactIndex;
+use crate::ota::busy_detector::first_busy;
+use crate::ota::agent_boot_guard::{check_boot, AgentBootGuard, BootCheck, AGENT_BOOT_FILE};
+use crate::ota::retry_backoff::RetryStatus;
+use crate::ota::signature::SignaturePolicy;
+use crate::ota::sideload::{Bundle, BundleFile, OfflineSubmitter, Sideload, BUNDLE_MANIFEST_FILE, SIDELOAD_DIR, SIDELOAD_STATE_FILE};
+use crate::ota::update_history::{component_records, CycleRecorder, UpdateHistory, UPDATE_HISTORY_FILE};
+use crate::ota::update_journal::{JournalStep, PendingCycle, UpdateJournal, UPDATE_JOURNAL_FILE};
+use crate::ota::update_plan::{UpdatePlan, UpdatePlanner};
 use crate::rest_comm::coupling_rest_comm::fetch_coupling_rest_comm;
 
 #[derive(Debug, Serialize)]
@@ -72,6 +95,21 @@ pub struct OTAManager<A: SystemControlTrait> {
     rest_channel_receiver: mpsc::Receiver<RestMessage>,
     update_ota_status: fn(OTAStatus, Option<String>),
     override_operator: RefCell<Option<bool>>,
+    journal: UpdateJournal,
+    update_install_pending: fn(bool),
+    // Downloaded, waiting for a maintenance window
+    pending_install: RefCell<Option<Manifest>>,
+    install_override: RefCell<bool>,
+    cancellation: CancellationToken,
+    history: UpdateHistory,
+    // The run_once cycle in progress
+    cycle_record: RefCell<CycleRecorder>,
+    update_retry_status: fn(RetryStatus),
+    retry_status: RefCell<RetryStatus>,
+    agent_boot: AgentBootGuard,
+    sideload: Sideload,
+    // Set while pending_install came from an offline bundle
+    pending_sideload: RefCell<Option<BundleFile>>,
 }
 
 #[derive(PartialEq, Eq)]
@@ -109,6 +147,7 @@ impl<A: SystemControlTrait> ServiceTrait for OTAManager<A> {
 }
 
 impl<A: SystemControlTrait> OTAManager<A> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         system_control: RefCell<A>,
         hash_manifest_path: PathBuf,
@@ -116,7 +155,10 @@ impl<A: SystemControlTrait> OTAManager<A> {
         dest_path: PathBuf,
         install_command: fn(component: &Component, installing: bool) -> Result<String, OTAError>,
         update_ota_status: fn(OTAStatus, Option<String>),
+        update_install_pending: fn(bool),
+        update_retry_status: fn(RetryStatus),
     ) -> Self {
+        load_component_registry(Self::common_file_path(&hash_manifest_path, COMPONENT_REGISTRY_FILE)); // Before any manifest is read
         let core_rest_comm = Box::new(CoreRestComm {
             url: config.core_uri.clone(),
             get: RestServer::get,
@@ -137,6 +179,15 @@ impl<A: SystemControlTrait> OTAManager<A> {
             Some(parent) => parent.join(PREVIOUS_INSTALL_PATH),
         };
         create_dir_if_not_exists(&previous_install_path);
+        let journal = UpdateJournal::new(Self::common_file_path(&hash_manifest_path, UPDATE_JOURNAL_FILE));
+        Self::migrate_legacy_status_files(&hash_manifest_path, &journal);
+        Self::restore_version_files(&hash_manifest_path, &journal);
+        let history = UpdateHistory::new(Self::common_file_path(&hash_manifest_path, UPDATE_HISTORY_FILE));
+        let agent_boot = AgentBootGuard::new(Self::common_file_path(&hash_manifest_path, AGENT_BOOT_FILE));
+        let sideload = Sideload::new(
+            config.sideload.watch_dir.clone().unwrap_or_else(|| Self::common_file_path(&hash_manifest_path, SIDELOAD_DIR)),
+            Self::common_file_path(&hash_manifest_path, SIDELOAD_STATE_FILE),
+        );
         Self {
             system_control,
             hash_manifest_path,
@@ -152,6 +203,18 @@ impl<A: SystemControlTrait> OTAManager<A> {
             rest_channel_receiver,
             update_ota_status,
             override_operator: RefCell::new(None),
+            journal,
+            update_install_pending,
+            pending_install: RefCell::new(None),
+            install_override: RefCell::new(false),
+            cancellation: CancellationToken::default(),
+            history,
+            cycle_record: RefCell::new(CycleRecorder::new()),
+            update_retry_status,
+            retry_status: RefCell::new(RetryStatus::default()),
+            agent_boot,
+            sideload,
+            pending_sideload: RefCell::new(None),
         }
     }
     pub fn get_operator(&self) -> bool {
@@ -161,9 +224,8 @@ impl<A: SystemControlTrait> OTAManager<A> {
                 operator
             },
             None => {
-                let arch = get_arch();
-                let is_operator = arch == ArchType::WIN || arch == ArchType::AMD64;
-                let arch_type = arch.to_string().green(true);
+                let is_operator = is_operator_arch();
+                let arch_type = get_arch().to_string().green(true);
                 let node_type = if is_operator {"operator".green(true)} else {"vehicle".green(true)};
                 log::info!("Architecture is {}, so our node type is {}", arch_type, node_type);
                 is_operator
@@ -171,13 +233,13 @@ impl<A: SystemControlTrait> OTAManager<A> {
         }
     }
 
-    pub fn get_manifest(&self, operator: bool) -> Manifest {
+    pub fn get_manifest(&self, operator: bool) -> Result<Manifest, OTAError> {
         let server_name = match (self.fetch_license_manager)() {
             Ok(manager) => { manager.get_server().unwrap_or_default() }
             Err(e) => {
-                let error = format!("Error occurred during loading the license manager file: {e}, OTA disabled");
-                log::error!("{error}");
-                (self.update_ota_status)(OTAStatus::ERROR, Some(error));
+                let error = OTAError::auth(format!("Error occurred during loading the license manager file: {e}, OTA disabled"));
+                log::error!("{}", error.message);
+                self.set_status(OTAStatus::ERROR, Some(error.report()));
                 "".to_string()
             }
         };
@@ -188,11 +250,172 @@ impl<A: SystemControlTrait> OTAManager<A> {
             server_name,
             self.file_system.read_function,
             self.file_system.write_function,
-        ).unwrap()
+        )
+            .map(|manifest| manifest.with_holds(held_components()))
+            .map_err(|e| OTAError::state_corruption(format!("Failed to load the hash manifest: {e}")))
+    }
+
+    // Where bundles are picked up, /sideload uploads land there too
+    pub fn get_sideload_dir(&self) -> PathBuf {
+        self.sideload.watch_dir()
+    }
+
+    pub fn get_sideload_max_upload(&self) -> u64 {
+        self.config.sideload.max_upload_mb * 1024 * 1024
+    }
+
+    // Bundles don't come from the coupling server, they're only installed when their signature is checked
+    pub fn sideload_allowed(&self) -> bool {
+        self.config.signatures.default == SignaturePolicy::Required
+    }
+
+    pub fn get_component_holds_path(&self) -> PathBuf {
+        Self::common_file_path(&self.hash_manifest_path, COMPONENT_HOLDS_FILE)
+    }
+
+    pub fn get_baseline_manifest_path(&self) -> PathBuf {
+        Self::common_file_path(&self.hash_manifest_path, BASELINE_MANIFEST_FILE)
+    }
+
+    pub fn get_bundle_exporter(&self) -> BundleExporter {
+        BundleExporter {
+            hash_manifest_path: self.hash_manifest_path.clone(),
+            previous_install_path: self.previous_install_path.clone(),
+            export_dir: Self::common_file_path(&self.hash_manifest_path, EXPORT_DIR),
+            fetch_license_manager: self.fetch_license_manager,
+            read_function: self.file_system.read_function,
+            write_function: self.file_system.write_function,
+            journal: self.journal.clone(),
+        }
+    }
+
+    // Downloads and kept packages, None when they're not shared with peers
+    pub fn get_artifact_index(&self) -> Option<ArtifactIndex> {
+        let mut dirs = vec![self.dest_path.clone(), self.previous_install_path.clone()];
+        dirs.extend(self.get_artifact_store().map(|artifacts| artifacts.dir()));
+        self.config.peer_cache.serve.then(|| ArtifactIndex::new(dirs))
+    }
+
+    pub fn get_artifact_store(&self) -> Option<ArtifactStore> {
+        ArtifactStore::new(Self::common_file_path(&self.hash_manifest_path, ARTIFACT_STORE_DIR), &self.config.artifact_store)
+    }
+
+    pub fn get_update_planner(&self) -> UpdatePlanner {
+        UpdatePlanner {
+            hash_manifest_path: self.hash_manifest_path.clone(),
+            previous_install_path: self.previous_install_path.clone(),
+            fetch_license_manager: self.fetch_license_manager,
+            send_json: self.send_json,
+            read_function: self.file_system.read_function,
+            write_function: self.file_system.write_function,
+            journal: self.journal.clone(),
+        }
+    }
+
+    // Dry run of the next cycle, nothing is killed, downloaded or installed
+    pub fn plan(&self) -> Result<UpdatePlan, OTAError> {
+        self.get_update_planner().plan(&self.system_control, self.get_operator())
+    }
+
+    fn set_status(&self, status: OTAStatus, message: Option<String>) {
+        {
+            let mut cycle_record = self.cycle_record.borrow_mut();
+            cycle_record.status = status.clone();
+            cycle_record.message = message.clone().unwrap_or_default();
+        }
+        (self.update_ota_status)(status, message);
+    }
+
+    // The cloud, /status, the update history and Jira all get the error code
+    // A failed install always gets a snapshot, other fatal errors at most one a day. Each failure is put once
+    fn report_error(&self, error: &OTAError, coupling_rest_comm: &dyn CouplingRestSubmitter) {
+        self.set_status(OTAStatus::ERROR, Some(error.report()));
+        self.cycle_record.borrow_mut().record.error_code = Some(error.code().to_string());
+        let failed_install = matches!(
+            error.kind,
+            OTAErrorKind::Installer { .. } | OTAErrorKind::Rollback | OTAErrorKind::HealthCheck | OTAErrorKind::Dependency | OTAErrorKind::Hook { .. }
+        );
+        if error.severity == OTAErrorSeverity::FatalError && !failed_install {
+            // Puts the failed status too
+            if let Err(e) = send_error_snapshot_to_jira(JIRA_REPORT_TICKET, false, error) {
+                log::error!("Snapshot error: {e}");
+            }
+            return;
+        }
+        coupling_rest_comm.put_ota_status(
+            Some(error.report()),
+            None,
+            NodeOtaProgressStatus::Failed
+        );
+        if failed_install {
+            if let Err(e) = send_error_snapshot_to_jira(JIRA_REPORT_TICKET, true, error) {
+                log::error!("Snapshot error: {e}");
+            }
+        }
+    }
+
+    // Errors that used to panic and stop OTA, the cycle is abandoned and the next interval retries it
+    fn degrade(&self, error: OTAError, coupling_rest_comm: Option<&dyn CouplingRestSubmitter>) -> Action {
+        log::error!("OTA is degraded until the next interval: {}", error.report());
+        match coupling_rest_comm {
+            Some(coupling_rest_comm) => self.report_error(&error, coupling_rest_comm),
+            None => self.cycle_record.borrow_mut().record.error_code = Some(error.code().to_string()),
+        }
+        self.set_status(OTAStatus::DEGRADED, Some(error.report()));
+        Action::CONTINUE
     }
 
+    pub fn get_update_history(&self) -> UpdateHistory {
+        self.history.clone()
+    }
+
+    // Every cycle that got to the server ends up in the update history
     pub fn run_once(&self) -> Action {
-        (self.update_ota_status)(OTAStatus::CHECKING, None);
+        let outer_record = self.cycle_record.replace(CycleRecorder::new()); // Update both runs a cycle inside a cycle
+        let outer_cycle = CYCLE_RUNNING.with(|running| running.replace(true));
+        // Last resort for panics we didn't turn into errors, the panic hook already reported it
+        let action = match panic::catch_unwind(AssertUnwindSafe(|| self.run_cycle())) {
+            Ok(action) => action,
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .or_else(|| payload.downcast_ref::<&str>().map(|message| message.to_string()))
+                    .unwrap_or_default();
+                self.degrade(OTAError::fatal(format!("OTA cycle panicked: {message}")), None)
+            }
+        };
+        CYCLE_RUNNING.with(|running| running.set(outer_cycle));
+        let mut cycle_record = self.cycle_record.replace(outer_record);
+        if cycle_record.checked {
+            if cycle_record.closed_cycle.is_none() {
+                cycle_record.closed_cycle = self.journal.take_closed_cycle();
+            }
+            // A self update is healthy once a cycle went through to the end, a pending or failed install doesn't count
+            let succeeded = matches!(cycle_record.status, OTAStatus::UPDATED) && cycle_record.record.error_code.is_none();
+            self.history.append(&cycle_record.finish());
+            if succeeded {
+                self.agent_boot.confirm(&current_agent_version());
+            }
+        }
+        action
+    }
+
+    fn run_cycle(&self) -> Action {
+        // A pending install is planned again, the server diff, the holds and the window may have changed since
+        if self.pending_install.borrow_mut().take().is_some() {
+            (self.update_install_pending)(false);
+            if self.journal.pending_cycle().is_some() {
+                self.journal.complete_cycle(); // Nothing was installed, the downloads are kept
+            }
+            if let Some(bundle_file) = self.pending_sideload.borrow_mut().take() {
+                return self.apply_sideload(bundle_file);
+            }
+        }
+        if let Some(bundle_file) = self.sideload.next_bundle() {
+            return self.apply_sideload(bundle_file);
+        }
+        self.set_status(OTAStatus::CHECKING, None);
 
         let license_manager = match (self.fetch_license_manager)() {
             Ok(license_manager) => { license_manager }
@@ -200,7 +423,7 @@ impl<A: SystemControlTrait> OTAManager<A> {
                 return match error {
                     AuthError::NetworkError(error) => {
                         log::error!("Network error occurred while getting license token {error}, retrying...");
-                        Action::RETRY
+                        Action::RETRY(RetryReason::Network)
                     }
                     AuthError::LicenseError(error) | AuthError::DecodingError(error) => {
                         log::error!("Error occurred while getting license token: {error}");
@@ -216,110 +439,252 @@ impl<A: SystemControlTrait> OTAManager<A> {
 
         let coupling_rest_comm = CouplingRestComm::new(license_manager.deref(), self.send_json);
 
-        match self.get_update_both_status() {
-            UpdateBothStatus::None => {}
-            UpdateBothStatus::Operator => { *self.override_operator.borrow_mut() = Some(true); }
-            UpdateBothStatus::Vehicle => { *self.override_operator.borrow_mut() = Some(false); }
+        if let Some(operator) = self.journal.operator_override() {
+            *self.override_operator.borrow_mut() = Some(operator);
         }
 
         let operator = self.get_operator();
 
-        let manifest = self.get_manifest(operator);
-        manifest.hash_manifest.verify_version(current_agent_version()); // This WILL panic if version is wrong!
+        let manifest = match self.get_manifest(operator) {
+            Ok(manifest) => manifest,
+            Err(error) => return self.degrade(error, Some(&coupling_rest_comm)),
+        };
+        let manifest = match self.restore_reverted_agent(manifest) {
+            Ok(manifest) => manifest,
+            Err(error) => return self.degrade(error, Some(&coupling_rest_comm)),
+        };
+        if let Err(error) = manifest.hash_manifest.verify_version(current_agent_version()) {
+            return self.degrade(error, Some(&coupling_rest_comm));
+        }
         manifest.standardize_prev_dir();
-        // Run the download logic only if core has not a connected session
-        if self.core_rest_comm.is_core_has_connected_session() {
-            log::info!("Core has connected session");
-            return Action::RETRY
-        }
-
-        let manifest = match self.get_incomplete_install_status() {
-            None => { manifest }
-            Some(server) => {
-                if manifest.server_name != server {
-                    log::warn!("Incomplete install detected (for {}) but we are in ({}), doing full factory reset",
-                        server, manifest.server_name);
-                    if let Err(e) = self.purge_hash_manifest() {
-                        log::error!("Error in full factory reset! ({})", e);
-                        return Action::RETRY
-                    }
-                    self.get_manifest(operator) // replacing the manifest with an empty one and continuing
-                }
-                else {
-                    log::warn!("Incomplete install detected (for {}), doing partial factory reset", server);
-                    match self.purge_server_manifest(manifest, server) {
-                        Err(e) => {
-                            log::error!("Error in partial factory reset ({}), doing full factory reset!", e);
-                            if let Err(e) = self.purge_hash_manifest() {
-                                log::error!("Error in full factory reset! ({})", e);
-                                return Action::RETRY
-                            }
-                            self.get_manifest(operator) // replacing the manifest with an empty one and continuing
-                        }
-                        Ok(manifest) => {
-                            let manifest = manifest.write_to_file().expect("Failed to save to file!");
-                            manifest
-                        }
+        if self.config.busy.before_download {
+            if let Some(action) = self.defer_if_busy("Download") {
+                return action;
+            }
+        }
+
+        let manifest = match (self.journal.pending_cycle(), self.take_legacy_incomplete_install()) {
+            (Some(cycle), _) => match self.resume_from_journal(&cycle, &coupling_rest_comm) {
+                Ok(()) => match self.get_manifest(operator) { // The hash manifest changed, reloading
+                    Ok(manifest) => manifest,
+                    Err(error) => return self.degrade(error, Some(&coupling_rest_comm)),
+                },
+                Err(e) => {
+                    log::error!("Failed to resume from the update journal ({})", e);
+                    self.journal.complete_cycle();
+                    match self.factory_reset(manifest, cycle.server, operator) {
+                        Some(manifest) => manifest,
+                        None => return Action::RETRY(RetryReason::Network), // Failed resets back off like network errors
                     }
                 }
-
-            }
+            },
+            (None, Some(server)) => match self.factory_reset(manifest, server, operator) {
+                Some(manifest) => manifest,
+                None => return Action::RETRY(RetryReason::Network),
+            },
+            (None, None) => manifest,
         };
+        self.journal.start_cycle(&manifest.server_name, manifest.operator);
+        self.journal.take_closed_cycle(); // Dropping the resumed cycle, if any
+        {
+            let mut cycle_record = self.cycle_record.borrow_mut();
+            cycle_record.checked = true;
+            cycle_record.record.server = manifest.server_name.clone();
+            cycle_record.record.operator = manifest.operator;
+        }
 
         let download_manager = DownloadManager::new(
             &self.system_control,
             &coupling_rest_comm,
             self.dest_path.clone(),
-            self.update_ota_status
-        ).unwrap();
+            self.update_ota_status,
+            self.journal.clone(),
+            self.cancellation.clone(),
+        ).unwrap().with_peers(self.config.peer_cache.clone()).with_artifact_store(self.get_artifact_store());
 
-        let manifest = match download_manager.run(manifest) {
+        let download_result = download_manager.run(manifest);
+        {
+            let mut cycle_record = self.cycle_record.borrow_mut();
+            cycle_record.downloaded(download_manager.download_started(), download_manager.downloaded_bytes());
+            cycle_record.record.components = download_manager.cycle_components();
+        }
+        let manifest = match download_result {
             Ok(manifest) => manifest,
             Err(error) => {
                 log::error!("Download manager error: {error}");
-                coupling_rest_comm.put_ota_status(
-                    Some(error.message()),
-                    None,
-                    NodeOtaProgressStatus::Failed
-                );
-                (self.update_ota_status)(OTAStatus::ERROR, Some(error.message()));
-                if error.severity == OTAErrorSeverity::FatalError {
-                    if let Err(e) = send_snapshot_to_jira(JIRA_REPORT_TICKET, false) {
-                        log::error!("Snapshot error: {e}");
-                    }
+                self.report_error(&error, &coupling_rest_comm);
+                self.journal.complete_cycle(); // Nothing was installed, verified downloads are resumed next time
+                return Action::CONTINUE;
+            }
+        };
+
+        if !manifest.is_fully_installed() && !self.install_allowed() {
+            self.hold_install(manifest);
+            return Action::CONTINUE;
+        }
+        if !manifest.is_fully_installed() {
+            if let Some(action) = self.defer_if_busy("Install") {
+                // Checked again by the next cycle once the node is free
+                *self.pending_install.borrow_mut() = Some(manifest);
+                (self.update_install_pending)(true);
+                return action;
+            }
+        }
+        self.install_and_commit(manifest, &coupling_rest_comm)
+    }
+
+    // Offline update, the bundle stands in for the coupling server and the downloads
+    fn apply_sideload(&self, bundle_file: BundleFile) -> Action {
+        let message = format!("Applying the offline bundle {}", bundle_file.name());
+        log::info!("{}", message.clone().yellow(true));
+        self.set_status(OTAStatus::CHECKING, Some(message));
+        let manifest = match self.sideload_manifest(&bundle_file) {
+            Ok(manifest) => manifest,
+            Err(error) => {
+                log::error!("Sideload error: {error}");
+                self.report_error(&error, &OfflineSubmitter);
+                if self.journal.pending_cycle().is_some() {
+                    self.journal.complete_cycle(); // Nothing was installed
                 }
+                self.finish_sideload(&bundle_file, false);
                 return Action::CONTINUE;
             }
         };
+        if !manifest.is_fully_installed() && !self.install_allowed() {
+            *self.pending_sideload.borrow_mut() = Some(bundle_file);
+            self.hold_install(manifest);
+            return Action::CONTINUE;
+        }
+        if !manifest.is_fully_installed() {
+            if let Some(action) = self.defer_if_busy("Install") {
+                *self.pending_sideload.borrow_mut() = Some(bundle_file);
+                *self.pending_install.borrow_mut() = Some(manifest);
+                (self.update_install_pending)(true);
+                return action;
+            }
+        }
+        let updates_agent = self.start_sideload_install(&bundle_file, &manifest);
+        let action = self.install_and_commit(manifest, &OfflineSubmitter);
+        self.finish_sideload(&bundle_file, updates_agent);
+        action
+    }
+
+    // Same checks as an online cycle, with the bundle manifest instead of the server diff
+    fn sideload_manifest(&self, bundle_file: &BundleFile) -> Result<Manifest, OTAError> {
+        if !self.sideload_allowed() {
+            return Err(OTAError::bundle(format!("{} is not installed, offline bundles need signatures set to required", bundle_file.name())));
+        }
+        let bundle = Bundle::extract(&bundle_file.path, &self.dest_path.join(SIDELOAD_DIR), utils::bash_exec::BashExec::exec_arg)?;
+        let signature = match &bundle.signature {
+            Some(path) => Some(fs::read_to_string(path).map_err(|e| OTAError::bundle(format!("Cannot read the signature of {}: {e}", bundle_file.name())))?),
+            None => None,
+        };
+        self.config.signatures.verify_file(&bundle_file.name(), &bundle.dir.join(BUNDLE_MANIFEST_FILE), signature.as_deref(), SignaturePolicy::Required)?;
+        let operator = self.get_operator();
+        if let Some(cycle) = self.journal.pending_cycle() {
+            if let Err(e) = self.resume_from_journal(&cycle, &OfflineSubmitter) {
+                log::error!("Failed to resume from the update journal ({})", e);
+                self.journal.complete_cycle();
+            }
+        }
+        let manifest = self.restore_reverted_agent(self.get_manifest(operator)?)?;
+        manifest.hash_manifest.verify_version(current_agent_version())?;
+        manifest.standardize_prev_dir();
+        let current_components = manifest.components.clone();
+        let manifest = manifest
+            .update_with_json(&bundle.manifest_json)
+            .map_err(|e| OTAError::bundle(format!("Invalid bundle manifest: {e}")))?;
+        self.journal.start_cycle(&manifest.server_name, manifest.operator);
+        self.journal.take_closed_cycle();
+        {
+            let mut cycle_record = self.cycle_record.borrow_mut();
+            cycle_record.checked = true;
+            cycle_record.record.server = manifest.server_name.clone();
+            cycle_record.record.operator = manifest.operator;
+            cycle_record.record.components = component_records(&current_components, &manifest);
+        }
+        bundle.attach_artifacts(manifest)
+    }
+
+    // An agent update is installed alone, the updated agent applies the rest of the bundle
+    fn start_sideload_install(&self, bundle_file: &BundleFile, manifest: &Manifest) -> bool {
+        let updates_agent = manifest.components.get(&ComponentType::phantom_agent).map(Component::should_install).unwrap_or(false);
+        if !updates_agent {
+            self.sideload.record(bundle_file, "installing"); // An agent restart mid-install doesn't apply it again
+        }
+        updates_agent
+    }
+
+    fn finish_sideload(&self, bundle_file: &BundleFile, updates_agent: bool) {
+        let result = match &self.cycle_record.borrow().record.error_code {
+            Some(code) => format!("failed ({})", code),
+            None if updates_agent => {
+                log::info!("Offline bundle {} updated the agent, the rest is applied after the restart", bundle_file.name());
+                self.sideload.forget(bundle_file);
+                return;
+            }
+            None => "applied".to_string(),
+        };
+        log::info!("Offline bundle {} {}", bundle_file.name(), result);
+        self.sideload.record(bundle_file, &result);
+    }
+
+    // Reports the first detector that finds the node busy, the retry policy decides when to check again
+    fn defer_if_busy(&self, phase: &str) -> Option<Action> {
+        let detectors = self.config.busy.detectors(self.core_rest_comm.as_ref(), &self.system_control);
+        let reason = first_busy(&detectors)?;
+        let message = format!("{} is deferred, the node is busy ({})", phase, reason);
+        log::info!("{}", message.clone().yellow(true));
+        self.set_status(OTAStatus::BUSY, Some(message));
+        Some(Action::RETRY(RetryReason::Busy))
+    }
+
+    fn install_allowed(&self) -> bool {
+        *self.install_override.borrow() || self.config.maintenance.is_open(Utc::now())
+    }
+
+    // The journal cycle stays open until the next cycle, which polls, applies the holds and checks the downloads again before installing
+    fn hold_install(&self, manifest: Manifest) {
+        let message = match self.config.maintenance.next_window(Utc::now()) {
+            Some((start, _)) => format!("Install is pending until the maintenance window at {}", start.to_rfc3339()),
+            None => "Install is pending until the next maintenance window".to_string(),
+        };
+        log::info!("{}", message.clone().yellow(true));
+        *self.pending_install.borrow_mut() = Some(manifest);
+        (self.update_install_pending)(true);
+        self.set_status(OTAStatus::PENDING, Some(message));
+    }
 
+    fn install_and_commit(&self, manifest: Manifest, coupling_rest_comm: &dyn CouplingRestSubmitter) -> Action {
+        self.cycle_record.borrow_mut().record.manifest_version = manifest.version.clone();
         let manifest = if !manifest.is_fully_installed() {
+            if let Err(error) = self.config.signatures.verify_manifest(&manifest) {
+                log::error!("Signature check failed, nothing is installed: {error}");
+                self.report_error(&error, coupling_rest_comm);
+                self.journal.complete_cycle();
+                return Action::CONTINUE;
+            }
             let install_manager = InstallManager::new(
                 &self.system_control,
                 self.install_command,
-                &coupling_rest_comm,
-                self.update_ota_status
-            );
-            self.set_incomplete_install_status(Some(manifest.server_name.clone())); // Detects if install did not finish
-            match install_manager.install_manifest(manifest) {
+                coupling_rest_comm,
+                self.update_ota_status,
+                self.journal.clone(),
+                self.cancellation.clone(),
+            ).with_agent_boot(self.agent_boot.clone()).with_artifact_store(self.get_artifact_store());
+            let install_started = Instant::now();
+            let install_result = install_manager.install_manifest(manifest);
+            self.cycle_record.borrow_mut().installed(install_started, install_manager.rollback_duration());
+            self.cycle_record.borrow_mut().record.hooks = install_manager.hook_outputs();
+            match install_result {
                 Ok(manifest) => {
                     self.core_rest_comm.update_manifest_version(&manifest.version);
                     manifest
                 },
                 Err(error) => {
-                    coupling_rest_comm.put_ota_status(
-                        Some(error.message()),
-                        None,
-                        NodeOtaProgressStatus::Failed
-                    );
-                    (self.update_ota_status)(OTAStatus::ERROR, Some(error.message()));
                     log::error!("Install manifest error: {error}");
-
-                    if error.severity == OTAErrorSeverity::FatalError {
-                        if let Err(e) = send_snapshot_to_jira(JIRA_REPORT_TICKET, false) {
-                            log::error!("Snapshot error: {e}");
-                        }
-                    }
-                    self.set_incomplete_install_status(None); // Install finished (error)
+                    self.report_error(&error, coupling_rest_comm);
+                    self.journal.complete_cycle(); // Install finished (error), the failed components were rolled back
                     return Action::CONTINUE;
                 }
             }
@@ -337,19 +702,24 @@ impl<A: SystemControlTrait> OTAManager<A> {
         };
         // If we got here the install completed successfully so we can safely clear the dest folder
         log::info!("Clearing download folder: {}", self.dest_path.to_string_lossy());
-        (self.file_system.empty_folder)(self.dest_path.as_path())
-            .expect("Download cleanup failed!");
+        if let Err(e) = (self.file_system.empty_folder)(self.dest_path.as_path()) {
+            log::warn!("Download cleanup failed: {e}"); // Leftovers are overwritten by the next download
+        }
         #[cfg(unix)]
         BashExec::sync(); // Making sure all the installed files are synced before we save the hash
-        manifest.write_to_file().expect("Failed to save to file!");
-        self.set_incomplete_install_status(None);   // Install finished (success)
-        (self.update_ota_status)(OTAStatus::UPDATED, None);
+        if let Err(e) = self.journal.write_manifest(manifest) {
+            self.journal.complete_cycle(); // The journal still has the installed steps, resuming commits them
+            return self.degrade(OTAError::state_corruption(format!("Failed to save the hash manifest: {e}")), Some(coupling_rest_comm));
+        }
+        self.journal.commit_cycle();   // Install finished (success)
+        self.set_status(OTAStatus::UPDATED, None);
         // If we're in the update both mode, we go to the next stage
         match self.get_update_both_status() {
             UpdateBothStatus::None => {}
             UpdateBothStatus::Operator => {
                 self.set_update_both_status(UpdateBothStatus::Vehicle);
                 log::info!("{}", "UPDATE BOTH: Install Operator complete. Switching to Vehicle...".blue(true));
+                self.cycle_record.borrow_mut().closed_cycle = self.journal.take_closed_cycle();
                 return self.run_once();
             }
             UpdateBothStatus::Vehicle => {
@@ -366,84 +736,242 @@ impl<A: SystemControlTrait> OTAManager<A> {
     }
 
     pub fn get_update_both_status(&self) -> UpdateBothStatus {
-        let update_both_status_file = match self.hash_manifest_path.parent() {
-            None => PathBuf::from(format!("./{}", UPDATE_BOTH_STATUS_FILE)),
-            Some(parent) => parent.join(UPDATE_BOTH_STATUS_FILE),
+        match self.journal.update_both_stage().as_deref() {
+            Some("operator") => UpdateBothStatus::Operator,
+            Some("vehicle") => UpdateBothStatus::Vehicle,
+            _ => UpdateBothStatus::None,
+        }
+    }
+
+    pub fn set_update_both_status(&self, status: UpdateBothStatus) {
+        let stage = match status {
+            UpdateBothStatus::None => { "none" }
+            UpdateBothStatus::Operator => { "operator" }
+            UpdateBothStatus::Vehicle => { "vehicle" }
         };
+        self.journal.set_update_both_stage(stage);
+    }
+
+    pub(crate) fn common_file_path(hash_manifest_path: &Path, file_name: &str) -> PathBuf {
+        match hash_manifest_path.parent() {
+            None => PathBuf::from(format!("./{}", file_name)),
+            Some(parent) => parent.join(file_name),
+        }
+    }
+
+    // Status files written by agents older than the update journal
+    fn migrate_legacy_status_files(hash_manifest_path: &Path, journal: &UpdateJournal) {
+        let update_both_status_file = Self::common_file_path(hash_manifest_path, UPDATE_BOTH_STATUS_FILE);
         if update_both_status_file.exists() {
             if let Ok(text) = file_to_string(&update_both_status_file) {
-                if text == "operator" { return UpdateBothStatus::Operator; }
-                else if text == "vehicle" { return UpdateBothStatus::Vehicle; }
+                log::info!("Moving update both status ({}) into the update journal", text);
+                journal.set_update_both_stage(&text);
+            }
+            if fs::remove_file(update_both_status_file).is_err() {
+                log::warn!("Failed to remove update both status file");
             }
         }
-        UpdateBothStatus::None
     }
 
-    pub fn set_update_both_status(&self, status: UpdateBothStatus) {
-        let update_both_status_file = match self.hash_manifest_path.parent() {
-            None => PathBuf::from(format!("./{}", UPDATE_BOTH_STATUS_FILE)),
-            Some(parent) => parent.join(UPDATE_BOTH_STATUS_FILE),
-        };
-        let text = match status {
-            UpdateBothStatus::None => {
-                if update_both_status_file.exists() && fs::remove_file(update_both_status_file).is_err() {
-                    log::warn!("Failed to remove update both status file");
+    // The version files of an interrupted cycle are rewritten from the journal, a crash may have cut their write short
+    fn restore_version_files(hash_manifest_path: &Path, journal: &UpdateJournal) {
+        let Some(cycle) = journal.pending_cycle() else { return };
+        if let Some(version) = cycle.agent_version {
+            let future_version_file = Self::common_file_path(hash_manifest_path, FUTURE_VERSION_PATH);
+            if file_to_string(&future_version_file).ok().as_deref() != Some(version.as_str()) {
+                log::warn!("Restoring the future version ({}) from the update journal", version);
+                if let Err(e) = string_to_file(&future_version_file, &version) {
+                    log::error!("Failed to restore the future version file: {}", e);
                 }
-                return;
             }
-            UpdateBothStatus::Operator => { "operator" }
-            UpdateBothStatus::Vehicle => { "vehicle" }
-        };
+        }
+        if let Some((server, version)) = cycle.manifest_version {
+            log::info!("Restoring the version table entry of {} ({}) from the update journal", server, version);
+            VersionTable::new().update_version_file(&server, &version);
+        }
+    }
 
-        if utils::file_utils::string_to_file(&update_both_status_file, text).is_err() {
-            log::warn!("Failed to set update both status file");
+    // The legacy marker does not say which components were installed, so it can only be handled with a factory reset
+    fn take_legacy_incomplete_install(&self) -> Option<String> {
+        let incomplete_install_status_file = Self::common_file_path(&self.hash_manifest_path, INCOMPLETE_INSTALL_STATUS_FILE);
+        if !incomplete_install_status_file.exists() {
+            return None;
+        }
+        let server = file_to_string(&incomplete_install_status_file).ok();
+        if fs::remove_file(incomplete_install_status_file).is_err() {
+            log::error!("Failed to remove incomplete install status file!");
         }
+        server
     }
 
-    pub fn get_incomplete_install_status(&self) -> Option<String> {
-        let incomplete_install_status_file = match self.hash_manifest_path.parent() {
-            None => PathBuf::from(format!("./{}", INCOMPLETE_INSTALL_STATUS_FILE)),
-            Some(parent) => parent.join(INCOMPLETE_INSTALL_STATUS_FILE),
-        };
-        if incomplete_install_status_file.exists() {
-            if let Ok(text) = file_to_string(&incomplete_install_status_file) {
-                return Some(text);
+    // Returns None if even the full factory reset failed
+    fn factory_reset(&self, manifest: Manifest, server: String, operator: bool) -> Option<Manifest> {
+        if manifest.server_name != server {
+            log::warn!("Incomplete install detected (for {}) but we are in ({}), doing full factory reset",
+                server, manifest.server_name);
+            if let Err(e) = self.purge_hash_manifest() {
+                log::error!("Error in full factory reset! ({})", e);
+                return None;
+            }
+            return self.reload_manifest(operator); // replacing the manifest with an empty one and continuing
+        }
+        log::warn!("Incomplete install detected (for {}), doing partial factory reset", server);
+        match self.purge_server_manifest(manifest, server) {
+            Err(e) => {
+                log::error!("Error in partial factory reset ({}), doing full factory reset!", e);
+                if let Err(e) = self.purge_hash_manifest() {
+                    log::error!("Error in full factory reset! ({})", e);
+                    return None;
+                }
+                self.reload_manifest(operator) // replacing the manifest with an empty one and continuing
             }
+            Ok(manifest) => match manifest.write_to_file() {
+                Ok(manifest) => Some(manifest),
+                Err(e) => {
+                    log::error!("Failed to save the partially reset manifest ({})", e);
+                    None
+                }
+            },
         }
-        None
     }
 
-    pub fn set_incomplete_install_status(&self, status: Option<String>) {
-        let incomplete_install_status_file = match self.hash_manifest_path.parent() {
-            None => PathBuf::from(format!("./{}", INCOMPLETE_INSTALL_STATUS_FILE)),
-            Some(parent) => parent.join(INCOMPLETE_INSTALL_STATUS_FILE),
+    // Counts the start of an updated agent, which is reverted when it doesn't confirm in time
+    // The previous agent runs again, the version file and the hash manifest go back to it
+    fn restore_reverted_agent(&self, manifest: Manifest) -> Result<Manifest, OTAError> {
+        let pending = match self.agent_boot.pending() {
+            Some(pending) => pending,
+            None => return Ok(manifest),
         };
-        let text = match status {
-            None => {
-                if incomplete_install_status_file.exists() && fs::remove_file(incomplete_install_status_file).is_err() {
-                    log::error!("Failed to remove incomplete install status file!");
-                }
-                return;
+        if !matches!(check_boot(&pending, &current_agent_version(), &self.config.self_update, Utc::now().timestamp()), BootCheck::Reverted(_)) {
+            return Ok(manifest);
+        }
+        log::warn!("Phantom agent {} was reverted to {}", pending.to_version, pending.from_version);
+        self.journal
+            .write_agent_version(&manifest.hash_manifest, &pending.from_version)
+            .map_err(|e| OTAError::state_corruption(format!("Failed to update version file! ({})", e)))?;
+        let manifest = match manifest.components.get(&ComponentType::phantom_agent).cloned() {
+            Some(agent) => {
+                let agent = Component { updated: true, checksum: pending.previous_checksum.clone(), version: pending.from_version.clone(), ..agent };
+                manifest
+                    .update_single_component(&agent)
+                    .and_then(|manifest| self.journal.write_manifest(manifest))
+                    .map_err(OTAError::state_corruption)?
             }
-            Some(text) => { text }
+            None => manifest,
         };
+        self.agent_boot.reject(&pending);
+        Ok(manifest)
+    }
 
-        if utils::file_utils::string_to_file(&incomplete_install_status_file, &text).is_err() {
-            log::error!("Failed to set update incomplete install file!");
+    fn reload_manifest(&self, operator: bool) -> Option<Manifest> {
+        match self.get_manifest(operator) {
+            Ok(manifest) => Some(manifest),
+            Err(error) => {
+                log::error!("{}", error.report());
+                None
+            }
+        }
+    }
+
+    // Commits whatever the interrupted cycle finished installing, the next diff redoes the rest
+    fn resume_from_journal(&self, cycle: &PendingCycle, coupling_rest_comm: &dyn CouplingRestSubmitter) -> Result<(), String> {
+        log::warn!("Interrupted update detected (for {}), resuming from the update journal", cycle.server);
+        let manifest = Manifest::new(
+            cycle.operator,
+            self.hash_manifest_path.clone(),
+            self.previous_install_path.clone(),
+            cycle.server.clone(),
+            self.file_system.read_function,
+            self.file_system.write_function,
+        )?;
+        let mut checksums = HashMap::new();
+        let mut paths = HashMap::new();
+        let mut backups = vec![];
+        for (component, journaled) in &cycle.components {
+            let component_type = match ComponentType::from_str(component) {
+                Ok(component_type) => component_type,
+                Err(_) => {
+                    log::warn!("Unknown component {} in the update journal", component);
+                    continue;
+                }
+            };
+            match journaled.step {
+                JournalStep::Installed => {
+                    log::info!("{} was installed before the interruption, committing it", component);
+                    checksums.insert(component_type, journaled.checksum.clone());
+                    match journaled.path.clone().filter(|path| path.exists()) {
+                        Some(path) => {
+                            paths.insert(component_type, path);
+                            backups.push(component_type);
+                        }
+                        None => log::warn!("Download of {} is gone, keeping the previous backup", component),
+                    }
+                }
+                JournalStep::Uninstalled => {
+                    log::info!("{} was uninstalled before the interruption, committing it", component);
+                    checksums.insert(component_type, String::default());
+                    backups.push(component_type);
+                }
+                JournalStep::BackupSaved => {
+                    checksums.insert(component_type, journaled.checksum.clone());
+                }
+                step => log::info!("{} stopped at {:?}, it will be redone", component, step),
+            }
         }
+        let manifest = manifest.update_components_paths(paths).map_err(|e| e.message())?;
+        let install_manager = InstallManager::new(
+            &self.system_control,
+            self.install_command,
+            coupling_rest_comm,
+            self.update_ota_status,
+            self.journal.clone(),
+            CancellationToken::default(),
+        );
+        let manifest = install_manager.save_prev_components(manifest, &backups).map_err(|e| e.message())?;
+        manifest.commit_checksums(&checksums)?;
+        self.journal.commit_cycle();
+        Ok(())
+    }
+
+    fn set_retry_status(&self, retry_status: RetryStatus) {
+        (self.update_retry_status)(retry_status.clone());
+        *self.retry_status.borrow_mut() = retry_status;
+    }
+
+    pub fn get_cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
     }
 
     fn run_until_complete(&self) {
+        self.cancellation.begin();
         loop {
             match self.run_once() {
-                Action::RETRY => {
-                    let ota_poll_frequency = self.config.ota_poll_frequency;
-                    log::info!("OTA will retry, in {ota_poll_frequency} seconds");
-                    sleep(Duration::new(u64::from(ota_poll_frequency), 0));
+                Action::RETRY(_) if self.cancellation.is_cancelled() => {
+                    log::warn!("Update was cancelled, not retrying");
+                    break;
                 }
-                Action::CONTINUE => return,
+                Action::RETRY(reason) => {
+                    let retry_status = self.retry_status.borrow().next(&self.config.retry, reason, self.config.ota_interval, Utc::now());
+                    log::info!("OTA will retry ({:?}, attempt {}), in {} seconds", reason, retry_status.attempt + 1, retry_status.delay);
+                    let delay = retry_status.delay;
+                    self.set_retry_status(retry_status);
+                    // Sleeping in steps, so a cancel doesn't wait for the whole backoff
+                    for _ in 0..delay {
+                        if self.cancellation.is_cancelled() {
+                            break;
+                        }
+                        sleep(Duration::from_secs(1));
+                    }
+                    if self.cancellation.is_cancelled() {
+                        log::warn!("Update was cancelled during the retry delay, not retrying");
+                        break;
+                    }
+                }
+                Action::CONTINUE => break,
             }
         }
+        self.set_retry_status(RetryStatus::default());
+        self.cancellation.finish();
     }
 
     fn log_and_error(text: &str) {
@@ -472,9 +1000,14 @@ impl<A: SystemControlTrait> OTAManager<A> {
         if let Err(e) = send_snapshot_to_jira(JIRA_REPORT_TICKET, false) {
             Self::log_and_error(&format!("Could not send snapshot: {e}"));
         }
+        if CYCLE_RUNNING.with(|running| running.get()) {
+            Self::log_and_error(&"OTA cycle was aborted, it will be retried on the next interval.".red(true));
+            return;
+        }
         Self::log_and_error(&"Due to unrecoverable error, OTA process has terminated. Fix the error and restart the service.".red(true));
     }
 
+    #[allow(clippy::manual_is_multiple_of)] // is_multiple_of needs a newer toolchain than the agent is built with
     pub fn run(&self) {
         // This hook will intercept the panic and write it into log before the process ends
         panic::set_hook(Box::new(|info| {
@@ -486,6 +1019,16 @@ impl<A: SystemControlTrait> OTAManager<A> {
             let mut count_seconds = self.config.ota_interval;
             while count_seconds > 0 {
                 count_seconds -= 1;
+                if self.pending_install.borrow().is_some() && self.install_allowed() {
+                    log::info!("Maintenance window is open");
+                    self.run_until_complete();
+                    count_seconds = self.config.ota_interval;
+                }
+                if count_seconds % SIDELOAD_POLL_INTERVAL == 0 && self.pending_install.borrow().is_none() && self.sideload.has_new_bundle() {
+                    log::info!("Found an offline bundle in {}", self.sideload.watch_dir().to_string_lossy());
+                    self.run_until_complete();
+                    count_seconds = self.config.ota_interval;
+                }
                 if let Ok(message) = self.rest_channel_receiver.recv_timeout(Duration::new(1, 0)) {
             
                     match message {
@@ -499,11 +1042,13 @@ impl<A: SystemControlTrait> OTAManager<A> {
                             log::info!("Received request for forcing a version");
                             #[cfg(windows)]
                             crate::ui::progress_ui::ProgressUI::show();
-                            let manifest = self.get_manifest(self.get_operator());
-                            let server_name = manifest.server_name.clone();
-                            if let Ok(manifest) = self.purge_server_manifest(manifest, server_name) {
-                                manifest.write_to_file().expect("Failed to save to file!");
-                                self.run_until_complete();
+                            let purged = self.get_manifest(self.get_operator()).map_err(|e| e.report()).and_then(|manifest| {
+                                let server_name = manifest.server_name.clone();
+                                self.purge_server_manifest(manifest, server_name)
+                            });
+                            match purged.and_then(|manifest| manifest.write_to_file()) {
+                                Ok(_) => self.run_until_complete(),
+                                Err(e) => log::error!("Failed to force a version: {e}"),
                             }
                         }
                         RestMessage::UpdateBothSides => {
@@ -511,6 +1056,12 @@ impl<A: SystemControlTrait> OTAManager<A> {
                             self.set_update_both_status(UpdateBothStatus::Operator);
                             self.run_until_complete();
                         }
+                        RestMessage::InstallNow => {
+                            log::info!("{}", "Received request to install now, ignoring maintenance windows".yellow(true));
+                            *self.install_override.borrow_mut() = true;
+                            self.run_until_complete();
+                            *self.install_override.borrow_mut() = false;
+                        }
                         _ => {
                             log::error!("Got the following value {:?}", message)
                         }
@@ -626,7 +1177,9 @@ impl<A: SystemControlTrait> OTAManager<A> {
             &self.system_control,
             self.install_command,
             &coupling_rest_comm,
-            self.update_ota_status
+            self.update_ota_status,
+            self.journal.clone(),
+            CancellationToken::default(),
         );
         for (_, component) in &manifest.components {
             if component.should_uninstall() {
@@ -704,6 +1257,12 @@ mod test {
     use crate::ota::ota_error::OTAError;
     use crate::ota::ota_manager::OTAManager;
     use crate::ota::service_control_trait::MockSystemControlTrait;
+    use crate::ota::retry_backoff::RetryStatus;
+    use crate::ota::update_history::{CycleRecorder, UpdateHistory};
+    use crate::ota::update_journal::UpdateJournal;
+    use crate::ota::cancellation::CancellationToken;
+    use crate::ota::agent_boot_guard::AgentBootGuard;
+    use crate::ota::sideload::Sideload;
     use crate::ota::manifest::Component;
     use crate::rest_comm::core_rest_comm_trait::MockCoreRestCommTrait;
     use crate::rest_request::SendType;
@@ -823,6 +1382,18 @@ mod test {
             rest_channel_receiver,
             update_ota_status,
             override_operator: RefCell::new(None),
+            journal: UpdateJournal::default(),
+            update_install_pending: |_| {},
+            pending_install: RefCell::new(None),
+            install_override: RefCell::new(false),
+            cancellation: CancellationToken::default(),
+            history: UpdateHistory::default(),
+            cycle_record: RefCell::new(CycleRecorder::new()),
+            update_retry_status: |_| {},
+            retry_status: RefCell::new(RetryStatus::default()),
+            agent_boot: AgentBootGuard::new(Default::default()),
+            sideload: Sideload::new(Default::default(), Default::default()),
+            pending_sideload: RefCell::new(None),
         };
 
         manager.run_once();
@@ -876,11 +1447,96 @@ mod test {
             rest_channel_receiver,
             update_ota_status,
             override_operator: RefCell::new(None),
+            journal: UpdateJournal::default(),
+            update_install_pending: |_| {},
+            pending_install: RefCell::new(None),
+            install_override: RefCell::new(false),
+            cancellation: CancellationToken::default(),
+            history: UpdateHistory::default(),
+            cycle_record: RefCell::new(CycleRecorder::new()),
+            update_retry_status: |_| {},
+            retry_status: RefCell::new(RetryStatus::default()),
+            agent_boot: AgentBootGuard::new(Default::default()),
+            sideload: Sideload::new(Default::default(), Default::default()),
+            pending_sideload: RefCell::new(None),
         };
 
         manager.run_once();
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn exported_bundle_is_sideloaded() {
+        use crate::config::Config;
+        use crate::ota::bundle_export::export_bundle;
+        use crate::ota::manifest::{ComponentType, Manifest};
+        use crate::ota::signature::{SignatureConfig, SignaturePolicy};
+        use crate::ota::sideload::BundleFile;
+        use crate::utils::{bash_exec::BashExec, file_utils::{file_to_string, get_sha1_checksum, string_to_file}};
+        use base64::{engine::general_purpose, Engine as _};
+        use std::fs;
+
+        let dir = std::env::temp_dir().join("exported_bundle_sideload_test");
+        let _ = fs::remove_dir_all(&dir);
+        let backup_dir = dir.join("previous/V_/core");
+        fs::create_dir_all(&backup_dir).unwrap();
+        fs::write(backup_dir.join("core_1.0.snap"), "core package").unwrap();
+        let checksum = get_sha1_checksum(&backup_dir.join("core_1.0.snap")).unwrap();
+        fs::write(dir.join("hash_manifest"), format!(r#"{{ "V_": {{ "core":"{}" }} }}"#, checksum)).unwrap();
+        let manifest = Manifest::new(false, dir.join("hash_manifest"), dir.join("previous"), Default::default(), file_to_string, string_to_file).unwrap();
+        let seed = [9; 32];
+        fs::write(dir.join("bundle.key"), general_purpose::STANDARD.encode(seed)).unwrap();
+        let signed = dir.join("signed.tar.gz");
+        assert!(export_bundle(&manifest, "3.2.1", None, &signed, Some(&dir.join("bundle.key")), BashExec::exec_arg).unwrap().signed);
+        let unsigned = dir.join("unsigned.tar.gz");
+        export_bundle(&manifest, "3.2.1", None, &unsigned, None, BashExec::exec_arg).unwrap();
+
+        let (rest_channel_sender, rest_channel_receiver) = mpsc::channel::<RestMessage>();
+        let (_, public_key) = crypto::ed25519::keypair(&seed);
+        let config = Config {
+            signatures: SignatureConfig { keys: vec![general_purpose::STANDARD.encode(public_key)], default: SignaturePolicy::Required, components: Default::default() },
+            ..Default::default()
+        };
+        let manager = OTAManager {
+            system_control: RefCell::new(MockSystemControlTrait::new()),
+            core_rest_comm: Box::new(MockCoreRestCommTrait::new()),
+            fetch_license_manager: fetch_for_ota_manager_test,
+            hash_manifest_path: dir.join("target/hash_manifest"),
+            previous_install_path: dir.join("target/previous"),
+            config,
+            dest_path: dir.join("target"),
+            install_command: |_, _| Ok(String::default()),
+            file_system: FileSystem { write_function: string_to_file, read_function: file_to_string, empty_folder: |_| Ok(()), remove_file: |_| Ok(()) },
+            send_json,
+            rest_channel_sender,
+            rest_channel_receiver,
+            update_ota_status: |_, _| {},
+            override_operator: RefCell::new(Some(false)),
+            journal: UpdateJournal::default(),
+            update_install_pending: |_| {},
+            pending_install: RefCell::new(None),
+            install_override: RefCell::new(false),
+            cancellation: CancellationToken::default(),
+            history: UpdateHistory::default(),
+            cycle_record: RefCell::new(CycleRecorder::new()),
+            update_retry_status: |_| {},
+            retry_status: RefCell::new(RetryStatus::default()),
+            agent_boot: AgentBootGuard::new(Default::default()),
+            sideload: Sideload::new(Default::default(), Default::default()),
+            pending_sideload: RefCell::new(None),
+        };
+        fs::create_dir_all(dir.join("target")).unwrap();
+        fs::write(dir.join("target/hash_manifest"), "{}").unwrap();
+        let bundle_file = |path: &Path| BundleFile { path: path.to_path_buf(), size: 0, modified: 0, checksum: Default::default() };
+
+        let manifest = manager.sideload_manifest(&bundle_file(&signed)).unwrap();
+        let core = &manifest.components[&ComponentType::core];
+        assert_eq!(core.checksum, checksum);
+        assert_eq!(fs::read_to_string(core.path.as_ref().unwrap()).unwrap(), "core package");
+        assert_eq!(manager.sideload_manifest(&bundle_file(&unsigned)).err().unwrap().code(), "OTA-SIGNATURE");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
     pub fn string_func() -> Result<String, String> {
         Err("We return a string error".to_string())
     }
@@ -18,6 +18,11 @@ pub enum OTAStatus {
     CHECKING,
     INSTALLING(ComponentType),
     UPDATED,
+    PENDING,
+    // A busy detector defers the update
+    BUSY,
+    // The last cycle hit an error that used to stop OTA, the next interval retries
+    DEGRADED,
 }
 
 impl Serialize for OTAStatusRestResponse {
@@ -0,0 +1,291 @@
+use crate::ota::{ota_error::OTAError, rest_listener::with_status};
+use crate::rest_request::{DownloadStats, RestServer};
+use crate::utils::file_utils::{get_checksum_like, is_checksum, ChecksumAlgorithm};
+use hyper::Uri;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    fs,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime},
+};
+use url::Url;
+
+pub const ARTIFACTS_ROUTE: &str = "artifacts";
+const PEER_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_SCAN_DEPTH: usize = 4;
+// Per peer address, a cycle asks for every component twice (HEAD, then GET)
+const PEER_REQUESTS_PER_MINUTE: u32 = 60;
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+//"peer_cache":{
+//       "serve":true,
+//       "peers":["http://192.168.10.20:30000"]
+//    }
+// With serve, GET /artifacts/<checksum> returns any downloaded or kept package with that checksum.
+// Peers are other agents' REST listeners, they're tried in order before the component link.
+// Whatever a peer sends is checked against the manifest checksum, the link is used when it doesn't match.
+// Lookups hash the served directories, so each address gets at most 60 requests a minute.
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct PeerCacheConfig {
+    #[serde(default)]
+    pub serve: bool,
+    #[serde(default)]
+    pub peers: Vec<Url>,
+}
+
+impl Display for PeerCacheConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let self_serialized = serde_json::to_string_pretty(&self).unwrap();
+        write!(f, "\n{self_serialized}")
+    }
+}
+
+impl PeerCacheConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for peer in &self.peers {
+            if !matches!(peer.scheme(), "http" | "https") || peer.host().is_none() {
+                return Err(format!("peer [{}] is not an http address", peer));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn artifact_urls(&self, checksum: &str) -> Vec<Url> {
+        self.peers.iter().filter_map(|peer| peer.join(&format!("/{}/{}", ARTIFACTS_ROUTE, checksum)).ok()).collect()
+    }
+}
+
+// Size and modification time of the file when its checksum was computed
+type KnownChecksum = (u64, SystemTime, String);
+
+pub struct ArtifactIndex {
+    dirs: Vec<PathBuf>,
+    // Per algorithm, recomputed when the size or the modification time changes
+    checksums: Mutex<HashMap<(PathBuf, ChecksumAlgorithm), KnownChecksum>>,
+    // Start of the current window and the requests in it
+    requests: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl ArtifactIndex {
+    pub fn new(dirs: Vec<PathBuf>) -> Self {
+        Self { dirs, checksums: Mutex::new(HashMap::new()), requests: Mutex::new(HashMap::new()) }
+    }
+
+    fn allow(&self, peer: IpAddr, now: Instant) -> bool {
+        let mut requests = self.requests.lock().unwrap();
+        requests.retain(|_, (start, _)| now.duration_since(*start) < RATE_WINDOW);
+        let (_, count) = requests.entry(peer).or_insert((now, 0));
+        *count += 1;
+        *count <= PEER_REQUESTS_PER_MINUTE
+    }
+
+    fn collect_files(dir: &Path, depth: usize, files: &mut Vec<PathBuf>) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') || name.ends_with(".part") || name.ends_with(".part.json") {
+                continue;
+            }
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() && depth < MAX_SCAN_DEPTH => Self::collect_files(&path, depth + 1, files),
+                Ok(file_type) if file_type.is_file() => files.push(path),
+                _ => {}
+            }
+        }
+    }
+
+    pub fn find(&self, checksum: &str) -> Option<PathBuf> {
+        let algorithm = ChecksumAlgorithm::of(checksum).ok()?;
+        let mut files = vec![];
+        for dir in &self.dirs {
+            Self::collect_files(dir, 0, &mut files);
+        }
+        self.checksums.lock().unwrap().retain(|(path, _), _| files.contains(path));
+        for path in files {
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            let (size, modified) = (metadata.len(), metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH));
+            let key = (path.clone(), algorithm);
+            let known = self.checksums.lock().unwrap()
+                .get(&key)
+                .filter(|(known_size, known_modified, _)| *known_size == size && *known_modified == modified)
+                .map(|(_, _, file_checksum)| file_checksum.clone());
+            // Hashed without the lock, other lookups don't wait for a big file
+            let file_checksum = match known {
+                Some(file_checksum) => file_checksum,
+                None => match get_checksum_like(&path, checksum) {
+                    Ok(file_checksum) => {
+                        self.checksums.lock().unwrap().insert(key, (size, modified, file_checksum.clone()));
+                        file_checksum
+                    }
+                    Err(e) => {
+                        log::warn!("Peer cache: {}", e);
+                        continue;
+                    }
+                },
+            };
+            if file_checksum.eq_ignore_ascii_case(checksum) {
+                return Some(path);
+            }
+        }
+        None
+    }
+}
+
+static ARTIFACT_INDEX: OnceLock<ArtifactIndex> = OnceLock::new();
+
+pub fn set_artifact_index(index: ArtifactIndex) {
+    if ARTIFACT_INDEX.set(index).is_err() {
+        log::warn!("Artifact index was already set");
+    }
+}
+
+// /artifacts/<checksum>
+pub fn artifact_route(uri: &Uri, peer: IpAddr) -> Result<PathBuf, String> {
+    let index = ARTIFACT_INDEX.get().ok_or_else(|| "Artifact index is not initialized".to_string())?;
+    if !index.allow(peer, Instant::now()) {
+        log::warn!("Peer cache: too many requests from {}", peer);
+        return Err(with_status(429, format!("More than {} requests a minute", PEER_REQUESTS_PER_MINUTE)));
+    }
+    let checksum = uri.path().split('/').nth(2).unwrap_or_default().to_lowercase();
+    if !is_checksum(&checksum) {
+        return Err(format!("[{}] is not a checksum", checksum));
+    }
+    let path = index.find(&checksum).ok_or_else(|| format!("No artifact with checksum {}", checksum))?;
+    log::info!("Peer cache: serving {} for {}", path.to_string_lossy(), checksum);
+    Ok(path)
+}
+
+async fn peer_has(url: &Url) -> bool {
+    let response = match reqwest::Client::builder().timeout(PEER_PROBE_TIMEOUT).build() {
+        Ok(client) => client.head(url.as_str()).send().await,
+        Err(_) => return false,
+    };
+    matches!(response, Ok(response) if response.status().is_success())
+}
+
+// True once one of the peers sent exactly the expected file, the bearer token never goes to a peer
+pub async fn download_from_peers<F: Fn(&str, u64, u64, Arc<Mutex<DownloadStats>>) + Copy>(
+    peer_urls: &[Url],
+    path: &Path,
+    checksum: &str,
+    stats: Arc<Mutex<DownloadStats>>,
+    callback: F,
+) -> bool {
+    for url in peer_urls {
+        if !peer_has(url).await {
+            log::debug!("Peer cache: {} is not available", url);
+            continue;
+        }
+        let result = RestServer::download_file_with_callback(url, path.to_path_buf(), Some(checksum.to_string()), &String::new(), stats.clone(), callback)
+            .await
+            .and_then(|_| {
+                // A peer that cuts the transfer short isn't caught by the download itself
+                let actual_checksum = get_checksum_like(path, checksum).unwrap_or_default();
+                match actual_checksum.eq_ignore_ascii_case(checksum) {
+                    true => Ok(()),
+                    false => Err(OTAError::checksum_mismatch(&path.to_string_lossy(), checksum, &actual_checksum)),
+                }
+            });
+        match result {
+            Ok(_) => {
+                log::info!("Peer cache: got {} from {}", path.to_string_lossy(), url);
+                return true;
+            }
+            Err(e) => {
+                log::warn!("Peer cache: download from {} failed ({}), trying the next source", url, e.report());
+                let _ = fs::remove_file(path);
+                callback(&path.to_string_lossy(), 0, 0, stats.clone());
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::file_utils::get_sha1_checksum;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("previous/V_/core")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn artifacts_are_found_by_checksum() {
+        let dir = dir("peer_cache_index_test");
+        let package = dir.join("previous/V_/core/core_1.0.snap");
+        fs::write(&package, "core package").unwrap();
+        fs::write(dir.join("previous/V_/core/core_1.1.snap.part"), "core package").unwrap();
+        let checksum = get_sha1_checksum(&package).unwrap();
+
+        let index = ArtifactIndex::new(vec![dir.join("downloads"), dir.join("previous")]);
+        assert_eq!(index.find(&checksum), Some(package.clone()));
+        assert_eq!(index.find("0000000000000000000000000000000000000000"), None);
+        let sha256 = crate::utils::file_utils::get_checksum(&package, ChecksumAlgorithm::Sha256, true).unwrap();
+        assert_eq!(index.find(&sha256), Some(package.clone()));
+        fs::write(&package, "another package").unwrap();
+        assert_eq!(index.find(&checksum), None);
+
+        let (peer, other_peer, now) = ("10.0.0.2".parse().unwrap(), "10.0.0.3".parse().unwrap(), Instant::now());
+        assert!((0..PEER_REQUESTS_PER_MINUTE).all(|_| index.allow(peer, now)));
+        assert!(!index.allow(peer, now));
+        assert!(index.allow(other_peer, now));
+        assert!(index.allow(peer, now + RATE_WINDOW));
+
+        assert!(is_checksum(&checksum));
+        assert!(!is_checksum("../../license"));
+        let config: PeerCacheConfig = serde_json::from_str(r#"{ "peers":["http://10.0.0.2:30000"] }"#).unwrap();
+        assert_eq!(config.validate(), Ok(()));
+        assert_eq!(config.artifact_urls(&checksum)[0].as_str(), format!("http://10.0.0.2:30000/artifacts/{}", checksum));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn peer_content_is_verified() {
+        let dir = dir("peer_cache_download_test");
+        let checksum = {
+            fs::write(dir.join("expected"), "core package").unwrap();
+            get_sha1_checksum(&dir.join("expected")).unwrap()
+        };
+        let good = MockServer::start().await;
+        let bad = MockServer::start().await;
+        for (server, body) in [(&good, "core package"), (&bad, "tampered package")] {
+            Mock::given(method("HEAD")).and(path(format!("/artifacts/{}", checksum)))
+                .respond_with(ResponseTemplate::new(200)).mount(server).await;
+            Mock::given(method("GET")).and(path(format!("/artifacts/{}", checksum)))
+                .respond_with(ResponseTemplate::new(200).set_body_string(body)).mount(server).await;
+        }
+        let peer_urls = |servers: &[&MockServer]| {
+            let config = PeerCacheConfig { serve: false, peers: servers.iter().map(|server| Url::parse(&server.uri()).unwrap()).collect() };
+            config.artifact_urls(&checksum)
+        };
+        let stats = Arc::new(Mutex::new(DownloadStats::new()));
+        let callback = |_: &str, _: u64, _: u64, _: Arc<Mutex<DownloadStats>>| {};
+        let target = dir.join("core.snap");
+
+        assert!(!download_from_peers(&peer_urls(&[&bad]), &target, &checksum, stats.clone(), callback).await);
+        assert!(!target.exists());
+        assert!(download_from_peers(&peer_urls(&[&bad, &good]), &target, &checksum, stats, callback).await);
+        assert_eq!(fs::read_to_string(&target).unwrap(), "core package");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
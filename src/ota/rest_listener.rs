@@ -3,14 +3,23 @@ use std::{
     collections::HashMap,
     convert::Infallible,
     mem::MaybeUninit,
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
     sync::mpsc,
     sync::{Mutex, Once},
     thread,
 };
 
 use crate::{OTAStatus, OTAStatusRestResponse, RestMessage};
-use hyper::{service::{make_service_fn, service_fn}, Body, Request, Response, Server, Uri};
+use crate::ota::maintenance_window::{MaintenanceConfig, MaintenanceStatus};
+use crate::ota::retry_backoff::RetryStatus;
+use hyper::{
+    body::{Bytes, HttpBody},
+    header::{CONTENT_LENGTH, CONTENT_TYPE},
+    server::conn::AddrStream,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, Uri,
+};
 
 use spdlog::info;
 
@@ -24,10 +33,39 @@ type CallbacksContainer = Mutex<
     >,
 >;
 
+// Gets the address of the requester
+type FileLookup = fn(&Uri, IpAddr) -> Result<PathBuf, String>;
+
+const FILE_CHUNK_SIZE: usize = 128 * 1024;
+const STATUS_PREFIX: &str = "status ";
+
+// Callback errors are answered with 500 unless they carry their own status
+pub fn with_status(status: u16, message: impl std::fmt::Display) -> String {
+    format!("{}{} {}", STATUS_PREFIX, status, message)
+}
+
+fn split_status(response: String) -> (u16, String) {
+    split_status_or(response, 500)
+}
+
+fn split_status_or(response: String, default_status: u16) -> (u16, String) {
+    let status = response
+        .strip_prefix(STATUS_PREFIX)
+        .and_then(|rest| rest.split_once(' '))
+        .and_then(|(status, message)| status.parse::<u16>().ok().map(|status| (status, message.to_string())));
+    status.unwrap_or((default_status, response))
+}
+
 pub struct RestListener {
     port: u16,
     callbacks: CallbacksContainer,
+    // Request key to the directory the body is saved into and the largest body that is taken
+    uploads: Mutex<HashMap<String, (PathBuf, u64)>>,
+    // Request key to the lookup of the file that is sent back
+    files: Mutex<HashMap<String, FileLookup>>,
     ota_status: Mutex<OTAStatusRestResponse>,
+    maintenance_status: Mutex<MaintenanceStatus>,
+    retry_status: Mutex<RetryStatus>,
 }
 
 impl RestListener {
@@ -36,11 +74,15 @@ impl RestListener {
         Self {
             port,
             callbacks: Mutex::new(HashMap::new()),
+            uploads: Mutex::new(HashMap::new()),
+            files: Mutex::new(HashMap::new()),
             ota_status: Mutex::new(OTAStatusRestResponse{
                 ota_status: OTAStatus::ERROR,
                 message: "".to_string(),
                 manifest_version: "".to_string()
             } ),
+            maintenance_status: Mutex::new(MaintenanceStatus::default()),
+            retry_status: Mutex::new(RetryStatus::default()),
         }
     }
 
@@ -56,10 +98,128 @@ impl RestListener {
             log::info!("{}", message);
         }
     }
-    async fn response_function(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    // The body is streamed into <dir>/<file name>.part, which is renamed once it's complete
+    async fn receive_upload(uri: &Uri, mut body: Body, dir: &Path, max_size: u64) -> Result<String, String> {
+        use std::io::Write;
+        let parts = uri.path().split('/').collect::<Vec<&str>>();
+        let name = match parts.get(2) {
+            Some(name) if !name.is_empty() && !name.starts_with('.') && !name.contains('\\') => *name,
+            _ => return Err(format!("Upload to /{}/<file name>\n", parts.get(1).unwrap_or(&""))),
+        };
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}\n", dir.to_string_lossy(), e))?;
+        let path = dir.join(name);
+        let part_path = dir.join(format!("{}.part", name));
+        let mut file = std::fs::File::create(&part_path).map_err(|e| format!("Failed to create {}: {}\n", part_path.to_string_lossy(), e))?;
+        let mut size: u64 = 0;
+        while let Some(chunk) = body.data().await {
+            let written = chunk
+                .map_err(|e| e.to_string())
+                .and_then(|chunk| file.write_all(&chunk).map(|_| chunk.len() as u64).map_err(|e| e.to_string()));
+            match written {
+                Ok(length) if size + length <= max_size => size += length,
+                Ok(_) => {
+                    let _ = std::fs::remove_file(&part_path);
+                    return Err(with_status(413, format!("{} is larger than {} bytes\n", name, max_size)));
+                }
+                Err(e) => {
+                    let _ = std::fs::remove_file(&part_path);
+                    return Err(format!("Upload of {} failed: {}\n", name, e));
+                }
+            }
+        }
+        file.sync_all()
+            .and_then(|_| std::fs::rename(&part_path, &path))
+            .map_err(|e| format!("Failed to save {}: {}\n", path.to_string_lossy(), e))?;
+        log::info!("Received {} ({} bytes)", path.to_string_lossy(), size);
+        Ok(format!("Received {} ({} bytes)\n", name, size))
+    }
+
+    // Streamed in chunks, packages don't fit in memory. HEAD only tells whether the file is there
+    async fn send_file(uri: &Uri, remote: IpAddr, head: bool, lookup: FileLookup) -> Response<Body> {
+        use tokio::io::AsyncReadExt;
+        let lookup_uri = uri.clone();
+        let found = match tokio::task::spawn_blocking(move || lookup(&lookup_uri, remote)).await {
+            Ok(found) => found,
+            Err(e) => Err(format!("File lookup crashed: {}", e)),
+        };
+        let opened = match found {
+            Ok(path) => tokio::fs::File::open(&path).await.map_err(|e| format!("Failed to open {}: {}", path.to_string_lossy(), e)),
+            Err(e) => Err(e),
+        };
+        let (mut file, size) = match opened {
+            Ok(file) => match file.metadata().await {
+                Ok(metadata) => (file, metadata.len()),
+                Err(e) => return Response::builder().status(500).body(Body::from(format!("{}\n", e))).unwrap(),
+            },
+            Err(e) => {
+                let (status, e) = split_status_or(e, 404);
+                Self::log(uri, &format!("RESPONDING WITH STATUS {} BODY {}", status, e));
+                return Response::builder().status(status).body(Body::from(format!("{}\n", e))).unwrap();
+            }
+        };
+        Self::log(uri, &format!("RESPONDING WITH STATUS 200 FILE OF {} BYTES", size));
+        let body = if head { Body::empty() } else {
+            let (mut sender, body) = Body::channel();
+            tokio::spawn(async move {
+                let mut buffer = vec![0; FILE_CHUNK_SIZE];
+                loop {
+                    match file.read(&mut buffer).await {
+                        Ok(0) => break,
+                        Ok(length) => {
+                            if sender.send_data(Bytes::copy_from_slice(&buffer[..length])).await.is_err() {
+                                break; // The client went away
+                            }
+                        }
+                        Err(e) => {
+                            log::warn!("Failed to read the file being sent: {}", e);
+                            sender.abort();
+                            break;
+                        }
+                    }
+                }
+            });
+            body
+        };
+        Response::builder()
+            .status(200)
+            .header(CONTENT_LENGTH, size)
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .body(body)
+            .unwrap()
+    }
+
+    fn content_length(request: &Request<Body>) -> Option<u64> {
+        request.headers().get(CONTENT_LENGTH)?.to_str().ok()?.parse().ok()
+    }
+
+    async fn response_function(remote: SocketAddr, request: Request<Body>) -> Result<Response<Body>, Infallible> {
         use std::ops::Deref;
         let uri = request.uri().clone();
-        Self::log(&uri, format!("Got request: {:?}", request).as_str());
+        Self::log(&uri, format!("Got request from {}: {:?}", remote, request).as_str());
+        let upload_key = uri.path().split('/').nth(1).unwrap_or_default().to_string();
+        let upload = rest_listener().uploads.lock().unwrap().get(&upload_key).cloned();
+        if let Some((dir, max_size)) = upload {
+            // Files are pushed by a tool running on the node, not over the network
+            let (status, body) = if !remote.ip().is_loopback() {
+                (403, format!("Uploads to /{} are only taken from this node\n", upload_key))
+            } else if matches!(Self::content_length(&request), Some(length) if length > max_size) {
+                (413, format!("Uploads to /{} are limited to {} bytes\n", upload_key, max_size))
+            } else {
+                match Self::receive_upload(&uri, request.into_body(), &dir, max_size).await {
+                    Ok(response) => (200, response),
+                    Err(response) => split_status(response),
+                }
+            };
+            Self::log(&uri, &format!("RESPONDING WITH STATUS {} BODY {}", status, body));
+            return Ok(Response::builder()
+                .status(status).header("Access-Control-Allow-Origin", "*")
+                .body(Body::from(body))
+                .unwrap());
+        }
+        let file_lookup = rest_listener().files.lock().unwrap().get(&upload_key).copied();
+        if let Some(lookup) = file_lookup {
+            return Ok(Self::send_file(&uri, remote.ip(), request.method() == Method::HEAD, lookup).await);
+        }
         let body_bytes = hyper::body::to_bytes(request.into_body()).await.unwrap();
         let body_str = String::from_utf8_lossy(&body_bytes).to_string();
         let body_str = if !body_str.is_empty() && body_str.starts_with('\"') {
@@ -83,7 +243,7 @@ impl RestListener {
                 let (trigger, callback) = map.get(key).unwrap();
                 let (code, response) = match callback(uri.clone(), body_str) {
                     Ok(response) => { (200, response) }
-                    Err(response) => { (500, response) }
+                    Err(response) => { split_status(response) }
                 };
                 match trigger {
                     None => { (code, response) } // Non-channel callback returns response immediately
@@ -115,8 +275,9 @@ impl RestListener {
     #[tokio::main]
     async fn serving(port: u16) {
         let addr = SocketAddr::from(([0, 0, 0, 0], port));
-        let make_service = make_service_fn(|_conn| async {
-            Ok::<_, Infallible>(service_fn(RestListener::response_function))
+        let make_service = make_service_fn(|conn: &AddrStream| {
+            let remote = conn.remote_addr();
+            async move { Ok::<_, Infallible>(service_fn(move |request| RestListener::response_function(remote, request))) }
         });
 
         let server = Server::bind(&addr).serve(make_service);
@@ -154,6 +315,19 @@ impl RestListener {
             .borrow_mut()
             .insert(request, (trigger, callback));
     }
+
+    // Bodies of these requests are files, they're saved without going through a callback.
+    // Only requests from this node are taken, up to max_size bytes
+    pub fn add_upload(&self, request: String, dir: PathBuf, max_size: u64) {
+        info!("Adding the following upload: {} (into {}, up to {} bytes)", request, dir.to_string_lossy(), max_size);
+        self.uploads.lock().unwrap().insert(request, (dir, max_size));
+    }
+
+    // The response of these requests is the file the lookup finds
+    pub fn add_file_route(&self, request: String, lookup: FileLookup) {
+        info!("Adding the following file route: {}", request);
+        self.files.lock().unwrap().insert(request, lookup);
+    }
 }
 
 pub fn rest_listener() -> &'static RestListener {
@@ -185,10 +359,30 @@ pub fn get_ota_status() -> OTAStatusRestResponse {
     rest_listener().ota_status.lock().unwrap().clone()
 }
 
+pub fn set_maintenance_config(config: MaintenanceConfig) {
+    rest_listener().maintenance_status.lock().unwrap().config = config;
+}
+
+pub fn set_install_pending(install_pending: bool) {
+    rest_listener().maintenance_status.lock().unwrap().install_pending = install_pending;
+}
+
+pub fn get_maintenance_status() -> MaintenanceStatus {
+    rest_listener().maintenance_status.lock().unwrap().clone()
+}
+
+pub fn set_retry_status(status: RetryStatus) {
+    *rest_listener().retry_status.lock().unwrap() = status;
+}
+
+pub fn get_retry_status() -> RetryStatus {
+    rest_listener().retry_status.lock().unwrap().clone()
+}
+
 #[cfg(test)]
 mod test {
     use crate::ota::rest_listener::rest_listener;
-    use crate::ota::rest_listener::{create_rest_listener, RestListener};
+    use crate::ota::rest_listener::{create_rest_listener, split_status, split_status_or, with_status, RestListener};
     use crate::utils::log_utils::set_logging_for_tests;
     use crate::{get_ota_status, OTAManager, OTAStatus, OTAStatusRestResponse, RestMessage, set_ota_status};
     use crate::RestMessage::UpdateVersion;
@@ -282,14 +476,16 @@ mod test {
     #[tokio::main]
     async fn hyper_serve(port: u16) {
         use hyper::{
+            server::conn::AddrStream,
             service::{make_service_fn, service_fn},
             Server,
         };
         use std::{convert::Infallible, net::SocketAddr};
 
         let addr = SocketAddr::from(([0, 0, 0, 0], port));
-        let make_service = make_service_fn(|_conn| async {
-            Ok::<_, Infallible>(service_fn(RestListener::response_function))
+        let make_service = make_service_fn(|conn: &AddrStream| {
+            let remote = conn.remote_addr();
+            async move { Ok::<_, Infallible>(service_fn(move |request| RestListener::response_function(remote, request))) }
         });
         let server = Server::bind(&addr).serve(make_service);
         if let Err(e) = server.await {
@@ -329,4 +525,12 @@ mod test {
         let status = get_ota_status();
         log::info!("STATUS IS {:?}", status);
     }
+
+    #[test]
+    fn callback_errors_carry_their_status() {
+        assert_eq!(split_status(with_status(400, "Unknown component foo\n")), (400, "Unknown component foo\n".to_string()));
+        assert_eq!(split_status("Failed\n".to_string()), (500, "Failed\n".to_string()));
+        assert_eq!(split_status("status unknown".to_string()), (500, "status unknown".to_string()));
+        assert_eq!(split_status_or("No artifact\n".to_string(), 404), (404, "No artifact\n".to_string()));
+    }
 }
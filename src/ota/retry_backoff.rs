@@ -0,0 +1,165 @@
+use crate::service_trait::RetryReason;
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+//"retry":{
+//       "network":{ "initial":5, "multiplier":2.0, "max":600, "jitter":true },
+//       "busy":{ "initial":5, "multiplier":1.5, "max":60, "jitter":false }
+//    }
+// Delays are in seconds. With jitter the delay is drawn uniformly between 1 second and the exponential delay,
+// so the fleet doesn't retry in lockstep after a server outage. A busy node waits the ota_interval before the busy delay.
+
+fn default_initial() -> u64 { 5 }
+fn default_multiplier() -> f64 { 2.0 }
+fn default_max() -> u64 { 600 }
+fn default_jitter() -> bool { true }
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct BackoffPolicy {
+    #[serde(default = "default_initial")]
+    pub initial: u64,
+    #[serde(default = "default_multiplier")]
+    pub multiplier: f64,
+    #[serde(default = "default_max")]
+    pub max: u64,
+    #[serde(default = "default_jitter")]
+    pub jitter: bool,
+}
+
+impl Default for BackoffPolicy {
+    fn default() -> Self {
+        Self { initial: default_initial(), multiplier: default_multiplier(), max: default_max(), jitter: default_jitter() }
+    }
+}
+
+impl BackoffPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.initial == 0 {
+            return Err("initial delay must be positive".to_string());
+        }
+        if !self.multiplier.is_finite() || self.multiplier < 1.0 {
+            return Err(format!("multiplier {} must be at least 1", self.multiplier));
+        }
+        if self.max < self.initial {
+            return Err(format!("max delay {} is smaller than the initial delay {}", self.max, self.initial));
+        }
+        Ok(())
+    }
+
+    // Upper bound of the delay before the given retry, counting from 0
+    pub fn ceiling(&self, attempt: u32) -> u64 {
+        let delay = self.initial as f64 * self.multiplier.powi(attempt.min(i32::MAX as u32) as i32);
+        delay.min(self.max as f64) as u64
+    }
+
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let ceiling = self.ceiling(attempt);
+        let delay = if self.jitter { rand::thread_rng().gen_range(1..=ceiling.max(1)) } else { ceiling };
+        Duration::from_secs(delay)
+    }
+}
+
+// Busy detectors are local, polling them doesn't load the server, so they back off slower and without jitter
+fn default_busy() -> BackoffPolicy {
+    BackoffPolicy { initial: 5, multiplier: 1.5, max: 60, jitter: false }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RetryConfig {
+    #[serde(default)]
+    pub network: BackoffPolicy,
+    // How often a busy node is checked again
+    #[serde(default = "default_busy")]
+    pub busy: BackoffPolicy,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self { network: BackoffPolicy::default(), busy: default_busy() }
+    }
+}
+
+impl Display for RetryConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let self_serialized = serde_json::to_string_pretty(&self).unwrap();
+        write!(f, "\n{self_serialized}")
+    }
+}
+
+impl RetryConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        self.network.validate().map_err(|e| format!("network: {}", e))?;
+        self.busy.validate().map_err(|e| format!("busy: {}", e))
+    }
+
+    pub fn policy(&self, reason: RetryReason) -> &BackoffPolicy {
+        match reason {
+            RetryReason::Network => &self.network,
+            RetryReason::Busy => &self.busy,
+        }
+    }
+}
+
+// Backoff state of the current run, reported in /status
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct RetryStatus {
+    pub reason: Option<RetryReason>,
+    pub attempt: u32,
+    pub delay: u64,
+    pub next_attempt: Option<String>,
+}
+
+impl RetryStatus {
+    // Attempts count per reason, switching from one reason to the other starts over
+    pub fn next(&self, config: &RetryConfig, reason: RetryReason, poll_interval: u64, now: DateTime<Utc>) -> RetryStatus {
+        let attempt = if self.reason == Some(reason) { self.attempt + 1 } else { 0 };
+        let delay = match reason {
+            RetryReason::Network => config.policy(reason).delay(attempt),
+            RetryReason::Busy => Duration::from_secs(poll_interval) + config.policy(reason).delay(attempt),
+        };
+        let next_attempt = now + chrono::Duration::from_std(delay).unwrap_or_else(|_| chrono::Duration::zero());
+        RetryStatus { reason: Some(reason), attempt, delay: delay.as_secs(), next_attempt: Some(next_attempt.to_rfc3339()) }
+    }
+
+    pub fn report(&self) -> Value {
+        json!({
+            "retrying": self.reason.is_some(),
+            "reason": self.reason,
+            "attempt": self.attempt,
+            "delay": self.delay,
+            "next_attempt": self.next_attempt,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_up_to_the_cap() {
+        let config = RetryConfig {
+            network: BackoffPolicy { initial: 5, multiplier: 2.0, max: 30, jitter: false },
+            ..RetryConfig::default()
+        };
+        config.validate().unwrap();
+        assert_eq!((0..5).map(|attempt| config.network.ceiling(attempt)).collect::<Vec<u64>>(), vec![5, 10, 20, 30, 30]);
+
+        let now = Utc::now();
+        let status = RetryStatus::default().next(&config, RetryReason::Network, 3600, now);
+        let status = status.next(&config, RetryReason::Network, 3600, now);
+        assert_eq!((status.attempt, status.delay), (1, 10));
+        let status = status.next(&config, RetryReason::Busy, 3600, now);
+        assert_eq!((status.attempt, status.delay), (0, 3605));
+        let status = status.next(&config, RetryReason::Busy, 3600, now);
+        assert_eq!((status.attempt, status.delay), (1, 3607));
+
+        let jittered = BackoffPolicy { jitter: true, ..config.network.clone() };
+        assert!((0..20).all(|_| (1..=20).contains(&jittered.delay(2).as_secs())));
+        assert!(BackoffPolicy { multiplier: 0.5, ..BackoffPolicy::default() }.validate().is_err());
+    }
+}
@@ -0,0 +1,377 @@
+use crate::ota::{manifest::Manifest, ota_error::OTAError};
+use crate::rest_comm::coupling_submit_trait::{CouplingRestSubmitter, NodeOtaProgressStatus};
+use crate::utils::{bash_exec::ExecArgType, file_utils::{get_checksum_like, get_sha1_checksum}};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    fs::{self, File},
+    io::Write,
+    path::{Component as PathComponent, Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use url::Url;
+
+pub const SIDELOAD_DIR: &str = "sideload";
+pub const SIDELOAD_STATE_FILE: &str = "sideload_state";
+pub const BUNDLE_MANIFEST_FILE: &str = "bundle.json";
+pub const BUNDLE_SIGNATURE_FILE: &str = "bundle.sig";
+const BUNDLE_EXTENSIONS: [&str; 3] = ["tar", "tgz", "gz"];
+// A bundle that is still being copied into the watched directory is left alone
+const SETTLE_TIME: Duration = Duration::from_secs(30);
+const MAX_RECORDS: usize = 50;
+
+// Offline bundle, a tar archive (optionally gzipped) with:
+//   bundle.json  - the coupling server manifest, each component points at its artifact with "file"
+//                  {"version":"3.2.1","missingComponents":[{"component":"core","checksum":"<sha1>","version":"3.2.1",
+//                   "package_type":"snap","file":"artifacts/phantom-core_3.2.1_amd64.snap"}, ...]}
+//   artifacts/   - the packages
+//   bundle.sig   - base64 ed25519 signature of bundle.json, checked against the pinned keys (see signature)
+// Like the server manifest, components the bundle doesn't list are uninstalled.
+// Bundles are only installed when the default signature policy is required.
+//"sideload":{
+//       "watch_dir":"/media/usb/phantom",
+//       "max_upload_mb":4096
+//    }
+// Without a watch_dir bundles are picked up from the sideload directory next to the hash manifest.
+// Uploads to /sideload/<file name> from this node are saved into the watched directory, up to max_upload_mb.
+
+fn default_max_upload_mb() -> u64 { 4096 }
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct SideloadConfig {
+    #[serde(default)]
+    pub watch_dir: Option<PathBuf>,
+    #[serde(default = "default_max_upload_mb")]
+    pub max_upload_mb: u64,
+}
+
+impl Default for SideloadConfig {
+    fn default() -> Self {
+        Self { watch_dir: None, max_upload_mb: default_max_upload_mb() }
+    }
+}
+
+impl Display for SideloadConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let self_serialized = serde_json::to_string_pretty(&self).unwrap();
+        write!(f, "\n{self_serialized}")
+    }
+}
+
+pub fn is_bundle_name(name: &str) -> bool {
+    !name.starts_with('.')
+        && Path::new(name)
+            .extension()
+            .map(|extension| BUNDLE_EXTENSIONS.contains(&extension.to_string_lossy().as_ref()))
+            .unwrap_or(false)
+}
+
+#[derive(Deserialize)]
+struct BundleManifest {
+    version: String,
+    #[serde(rename = "missingComponents")]
+    missing_components: Vec<BundleComponent>,
+}
+
+#[derive(Deserialize)]
+struct BundleComponent {
+    component: String,
+    #[serde(default)]
+    file: Option<PathBuf>,
+}
+
+pub struct Bundle {
+    pub dir: PathBuf,
+    pub version: String,
+    // In the coupling server format, for Manifest::update_with_json
+    pub manifest_json: String,
+    pub components: Vec<String>,
+    pub files: HashMap<String, PathBuf>,
+    pub signature: Option<PathBuf>,
+}
+
+impl Bundle {
+    // Whatever was extracted into dir before is removed
+    pub fn extract(archive: &Path, dir: &Path, exec: ExecArgType) -> Result<Bundle, OTAError> {
+        if dir.exists() {
+            fs::remove_dir_all(dir).map_err(|e| OTAError::bundle(format!("Failed to clear {}: {}", dir.to_string_lossy(), e)))?;
+        }
+        fs::create_dir_all(dir).map_err(|e| OTAError::bundle(format!("Failed to create {}: {}", dir.to_string_lossy(), e)))?;
+        exec("tar", &["-xf", &archive.to_string_lossy(), "-C", &dir.to_string_lossy()])
+            .map_err(|e| OTAError::bundle(format!("Failed to extract {}: {}", archive.to_string_lossy(), e)))?;
+        Self::open(dir)
+    }
+
+    pub fn open(dir: &Path) -> Result<Bundle, OTAError> {
+        let manifest_json = fs::read_to_string(dir.join(BUNDLE_MANIFEST_FILE))
+            .map_err(|e| OTAError::bundle(format!("Bundle has no {}: {}", BUNDLE_MANIFEST_FILE, e)))?;
+        let manifest: BundleManifest = serde_json::from_str(&manifest_json)
+            .map_err(|e| OTAError::bundle(format!("Invalid {}: {}", BUNDLE_MANIFEST_FILE, e)))?;
+        let mut components = vec![];
+        let mut files = HashMap::new();
+        for component in manifest.missing_components {
+            if let Some(file) = component.file {
+                if file.is_absolute() || file.components().any(|part| part == PathComponent::ParentDir) {
+                    return Err(OTAError::bundle(format!("Artifact {} of {} is outside the bundle", file.to_string_lossy(), component.component)));
+                }
+                files.insert(component.component.clone(), dir.join(file));
+            }
+            components.push(component.component);
+        }
+        let signature = Some(dir.join(BUNDLE_SIGNATURE_FILE)).filter(|path| path.exists());
+        Ok(Bundle { dir: dir.to_path_buf(), version: manifest.version, manifest_json, components, files, signature })
+    }
+
+    // Components the bundle updates get its artifacts, checked like a download
+    pub fn attach_artifacts(&self, manifest: Manifest) -> Result<Manifest, OTAError> {
+        let mut paths = HashMap::new();
+        for (component_type, component) in &manifest.components {
+            if component.updated || !self.components.contains(&component.component) {
+                continue; // Unlisted components are uninstalled
+            }
+            let path = self
+                .files
+                .get(&component.component)
+                .ok_or_else(|| OTAError::bundle(format!("Bundle has no artifact for {}", component.component)))?;
+            let actual_checksum = get_checksum_like(path, &component.checksum).map_err(OTAError::bundle)?;
+            if !actual_checksum.eq_ignore_ascii_case(&component.checksum) {
+                return Err(OTAError::checksum_mismatch(&component.component, &component.checksum, &actual_checksum));
+            }
+            log::info!("{} {} is taken from the bundle ({})", component.component, component.version, path.to_string_lossy());
+            paths.insert(*component_type, path.clone());
+        }
+        manifest.update_components_paths(paths)
+    }
+}
+
+// A bundle file in the watched directory, the checksum tells copies of the same bundle apart from new ones
+#[derive(Clone, Debug)]
+pub struct BundleFile {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: u64,
+    pub checksum: String,
+}
+
+impl BundleFile {
+    pub fn name(&self) -> String {
+        self.path.file_name().unwrap_or_default().to_string_lossy().to_string()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct SideloadRecord {
+    pub file: String,
+    pub size: u64,
+    pub modified: u64,
+    pub checksum: String,
+    pub result: String,
+    pub time: String,
+}
+
+// Every bundle is applied once whatever the result, copies of it are skipped
+pub struct Sideload {
+    watch_dir: PathBuf,
+    state_path: PathBuf,
+}
+
+impl Sideload {
+    pub fn new(watch_dir: PathBuf, state_path: PathBuf) -> Self {
+        Self { watch_dir, state_path }
+    }
+
+    pub fn watch_dir(&self) -> PathBuf {
+        self.watch_dir.clone()
+    }
+
+    pub fn records(&self) -> Vec<SideloadRecord> {
+        match fs::read_to_string(&self.state_path) {
+            Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+                log::error!("Failed to parse the sideload state {}: {}", self.state_path.to_string_lossy(), e);
+                vec![]
+            }),
+            Err(_) => vec![],
+        }
+    }
+
+    fn save(&self, records: &[SideloadRecord]) {
+        if self.state_path == PathBuf::default() {
+            return;
+        }
+        let content = serde_json::to_string_pretty(records).unwrap();
+        let temp_path = self.state_path.with_extension("tmp");
+        let result = File::create(&temp_path)
+            .and_then(|mut file| {
+                file.write_all(content.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&temp_path, &self.state_path));
+        if let Err(e) = result {
+            log::error!("Failed to save the sideload state: {}", e);
+        }
+    }
+
+    // Settled bundle files as (path, size, modified), by name
+    fn bundle_files(&self) -> Vec<(PathBuf, u64, u64)> {
+        let entries = match fs::read_dir(&self.watch_dir) {
+            Ok(entries) => entries,
+            Err(_) => return vec![], // Nothing plugged in
+        };
+        let mut files: Vec<(PathBuf, u64, u64)> = entries
+            .flatten()
+            .filter(|entry| is_bundle_name(&entry.file_name().to_string_lossy()))
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok().filter(|metadata| metadata.is_file())?;
+                let modified = metadata.modified().ok()?;
+                if SystemTime::now().duration_since(modified).unwrap_or_default() < SETTLE_TIME {
+                    return None;
+                }
+                let modified = modified.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                Some((entry.path(), metadata.len(), modified))
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn is_known(records: &[SideloadRecord], path: &Path, size: u64, modified: u64) -> bool {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        records.iter().any(|record| record.file == name && record.size == size && record.modified == modified)
+    }
+
+    // Cheap check for the run loop, nothing is hashed
+    pub fn has_new_bundle(&self) -> bool {
+        let records = self.records();
+        self.bundle_files().iter().any(|(path, size, modified)| !Self::is_known(&records, path, *size, *modified))
+    }
+
+    pub fn next_bundle(&self) -> Option<BundleFile> {
+        let records = self.records();
+        for (path, size, modified) in self.bundle_files() {
+            if Self::is_known(&records, &path, size, modified) {
+                continue;
+            }
+            let checksum = match get_sha1_checksum(&path) {
+                Ok(checksum) => checksum,
+                Err(e) => {
+                    log::warn!("Skipping bundle {}: {}", path.to_string_lossy(), e);
+                    continue;
+                }
+            };
+            let bundle = BundleFile { path, size, modified, checksum };
+            if let Some(applied) = records.iter().find(|record| record.checksum == bundle.checksum) {
+                log::info!("Bundle {} is a copy of {}, which was already sideloaded", bundle.name(), applied.file);
+                self.record(&bundle, &format!("copy of {}", applied.file));
+                continue;
+            }
+            return Some(bundle);
+        }
+        None
+    }
+
+    // The bundle is picked up again
+    pub fn forget(&self, bundle: &BundleFile) {
+        let mut records = self.records();
+        records.retain(|record| record.checksum != bundle.checksum);
+        self.save(&records);
+    }
+
+    pub fn record(&self, bundle: &BundleFile, result: &str) {
+        let mut records = self.records();
+        records.retain(|record| !(record.file == bundle.name() && record.checksum == bundle.checksum));
+        records.push(SideloadRecord {
+            file: bundle.name(),
+            size: bundle.size,
+            modified: bundle.modified,
+            checksum: bundle.checksum.clone(),
+            result: result.to_string(),
+            time: Utc::now().to_rfc3339(),
+        });
+        let skip = records.len().saturating_sub(MAX_RECORDS);
+        self.save(&records[skip..]);
+    }
+}
+
+// Stands in for the coupling server during a sideload, progress only goes to the log and /status
+pub struct OfflineSubmitter;
+
+impl CouplingRestSubmitter for OfflineSubmitter {
+    fn post_checksums(&self, _checksums: serde_json::Value) -> Result<String, String> {
+        Err("No coupling server while sideloading".to_string())
+    }
+
+    fn put_ota_status(&self, message: Option<String>, _eta: Option<u64>, ota_progress: NodeOtaProgressStatus) {
+        log::info!("Sideload status: {} {}", ota_progress.as_str(), message.unwrap_or_default());
+    }
+
+    fn send_file_to_jira(&self, _file: &Path, _ticket: &str) -> Result<String, String> {
+        Err("No coupling server while sideloading".to_string())
+    }
+
+    fn get_url_and_token(&self) -> (Url, String) {
+        (Url::parse("http://localhost").unwrap(), String::default())
+    }
+
+    fn check_versions(&self) -> Result<String, String> {
+        Err("No coupling server while sideloading".to_string())
+    }
+
+    fn get_node_info(&self) -> Result<String, String> {
+        Err("No coupling server while sideloading".to_string())
+    }
+
+    fn get_ota_status(&self) -> NodeOtaProgressStatus {
+        NodeOtaProgressStatus::Triggered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ota::manifest::ComponentType;
+    use std::str::FromStr;
+
+    fn read_function(_path: &Path) -> Result<String, String> {
+        Ok(r#"{ "V_": { "core":"core checksum", "sim_gps_info":"gps checksum" } }"#.to_string())
+    }
+
+    #[test]
+    fn bundle_artifacts_are_verified() {
+        let dir = std::env::temp_dir().join("sideload_bundle_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("artifacts")).unwrap();
+        let core_path = dir.join("artifacts/core.snap");
+        fs::write(&core_path, "core package").unwrap();
+        let checksum = get_sha1_checksum(&core_path).unwrap();
+        fs::write(dir.join(BUNDLE_MANIFEST_FILE), format!(r#"{{"version":"3.2.1","missingComponents":[
+            {{"component":"core","checksum":"{}","version":"3.2.1","package_type":"snap","file":"artifacts/core.snap"}},
+            {{"component":"sim_gps_info","checksum":"gps checksum","version":"1.0.0"}}
+        ]}}"#, checksum)).unwrap();
+        let bundle = Bundle::open(&dir).unwrap();
+        assert_eq!(bundle.version, "3.2.1");
+        assert_eq!(bundle.components, vec!["core", "sim_gps_info"]);
+        assert!(bundle.signature.is_none());
+
+        let manifest = || {
+            Manifest::new(false, PathBuf::from("./hash_manifest.json"), PathBuf::default(), Default::default(), read_function, |_, _| Ok(()))
+                .unwrap()
+                .update_with_json(&bundle.manifest_json)
+                .unwrap()
+        };
+        let core = ComponentType::from_str("core").unwrap();
+        let attached = bundle.attach_artifacts(manifest()).unwrap();
+        assert_eq!(attached.components[&core].path, Some(core_path.clone()));
+        assert!(attached.components[&core].should_install());
+
+        fs::write(&core_path, "tampered package").unwrap();
+        assert_eq!(bundle.attach_artifacts(manifest()).err().unwrap().code(), "OTA-CHECKSUM");
+
+        fs::write(dir.join(BUNDLE_MANIFEST_FILE), r#"{"version":"1","missingComponents":[{"component":"core","checksum":"x","file":"../core.snap"}]}"#).unwrap();
+        assert!(Bundle::open(&dir).is_err());
+        assert!(is_bundle_name("depot-3.2.1.tar.gz"));
+        assert!(!is_bundle_name("depot-3.2.1.tar.part"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::ota::{manifest::Manifest, ota_error::OTAError};
+use crate::utils::file_utils::{get_checksum, ChecksumAlgorithm};
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    path::Path,
+};
+
+//"signatures":{
+//       "keys":["<base64 ed25519 public key>"],
+//       "default":"optional",
+//       "components":{
+//          "core":"required",
+//          "sim_gps_info":"off"
+//       }
+//    }
+// A package is signed with ed25519 over the hex SHA-512 digest of the file, the base64 signature comes with
+// the component in the manifest: {"component":"core", ..., "signature":"<base64>"}
+// required rejects unsigned packages, optional only the badly signed ones. Any of the keys may have signed it.
+// bundle.sig of offline bundles is checked the same way against bundle.json, always as required.
+// Exports are signed with a key file holding the base64 32 byte ed25519 seed of one of the pinned keys.
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SignaturePolicy {
+    Required,
+    Optional,
+    #[default]
+    Off,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct SignatureConfig {
+    #[serde(default)]
+    pub keys: Vec<String>,
+    #[serde(default)]
+    pub default: SignaturePolicy,
+    #[serde(default)]
+    pub components: HashMap<String, SignaturePolicy>,
+}
+
+impl Display for SignatureConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let self_serialized = serde_json::to_string_pretty(&self).unwrap();
+        write!(f, "\n{self_serialized}")
+    }
+}
+
+// Base64 signature of path, made like the ones verify_file checks
+pub fn sign_file(key_path: &Path, path: &Path) -> Result<String, String> {
+    let seed = std::fs::read_to_string(key_path).map_err(|e| format!("Cannot read the signing key {}: {}", key_path.to_string_lossy(), e))?;
+    let seed = decode_key(&seed).map_err(|e| format!("Signing key {} is invalid, {}", key_path.to_string_lossy(), e))?;
+    let (secret_key, _) = crypto::ed25519::keypair(&seed);
+    let digest = get_checksum(path, ChecksumAlgorithm::Sha512, false)?;
+    Ok(general_purpose::STANDARD.encode(crypto::ed25519::signature(digest.as_bytes(), &secret_key)))
+}
+
+fn decode_key(key: &str) -> Result<Vec<u8>, String> {
+    match general_purpose::STANDARD.decode(key.trim()) {
+        Ok(key) if key.len() == 32 => Ok(key),
+        Ok(key) => Err(format!("a public key has {} bytes instead of 32", key.len())),
+        Err(e) => Err(format!("public key is not base64 ({})", e)),
+    }
+}
+
+impl SignatureConfig {
+    // Falling back to no checks would let unsigned packages in, every package is rejected instead
+    pub fn reject_all() -> Self {
+        Self { keys: vec![], default: SignaturePolicy::Required, components: Default::default() }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for key in &self.keys {
+            decode_key(key)?;
+        }
+        let checked = self.default != SignaturePolicy::Off || self.components.values().any(|policy| *policy != SignaturePolicy::Off);
+        if checked && self.keys.is_empty() {
+            return Err("signatures are checked but no public key is pinned".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn policy(&self, component: &str) -> SignaturePolicy {
+        self.components.get(component).copied().unwrap_or(self.default)
+    }
+
+    // signature is the base64 one from the manifest or the signature file
+    pub fn verify_file(&self, name: &str, path: &Path, signature: Option<&str>, policy: SignaturePolicy) -> Result<(), OTAError> {
+        let signature = match (policy, signature.map(str::trim).filter(|signature| !signature.is_empty())) {
+            (SignaturePolicy::Off, _) => return Ok(()),
+            (SignaturePolicy::Optional, None) => {
+                log::warn!("{} is not signed, installing it since its signature is optional", name);
+                return Ok(());
+            }
+            (SignaturePolicy::Required, None) => return Err(OTAError::signature(name, format!("{} is not signed", name))),
+            (_, Some(signature)) => signature,
+        };
+        let signature = general_purpose::STANDARD
+            .decode(signature)
+            .ok()
+            .filter(|signature| signature.len() == 64)
+            .ok_or_else(|| OTAError::signature(name, format!("Signature of {} is malformed", name)))?;
+        let digest = get_checksum(path, ChecksumAlgorithm::Sha512, false).map_err(|e| OTAError::signature(name, e))?;
+        let signed = self
+            .keys
+            .iter()
+            .filter_map(|key| decode_key(key).ok())
+            .any(|key| crypto::ed25519::verify(digest.as_bytes(), &key, &signature));
+        if !signed {
+            return Err(OTAError::signature(name, format!("Bad signature for {} ({})", name, path.to_string_lossy())));
+        }
+        log::info!("Signature of {} verified", name);
+        Ok(())
+    }
+
+    // Every downloaded package, before anything is installed
+    pub fn verify_manifest(&self, manifest: &Manifest) -> Result<(), OTAError> {
+        for component in manifest.components.values().filter(|component| component.should_install()) {
+            if let Some(path) = &component.path {
+                self.verify_file(&component.component, path, component.signature.as_deref(), self.policy(&component.component))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn signatures_follow_the_policy() {
+        let (secret_key, public_key) = crypto::ed25519::keypair(&[7; 32]);
+        let (other_secret_key, _) = crypto::ed25519::keypair(&[8; 32]);
+        let path = std::env::temp_dir().join("signature_test_package");
+        fs::write(&path, "core package").unwrap();
+        let digest = get_checksum(&path, ChecksumAlgorithm::Sha512, false).unwrap();
+        let signature = general_purpose::STANDARD.encode(crypto::ed25519::signature(digest.as_bytes(), &secret_key));
+        let forged = general_purpose::STANDARD.encode(crypto::ed25519::signature(digest.as_bytes(), &other_secret_key));
+
+        let config: SignatureConfig = serde_json::from_str(&format!(
+            r#"{{ "keys":["{}"], "default":"optional", "components":{{ "core":"required" }} }}"#,
+            general_purpose::STANDARD.encode(public_key)
+        )).unwrap();
+        assert_eq!(config.validate(), Ok(()));
+        assert_eq!(config.policy("core"), SignaturePolicy::Required);
+        assert_eq!(config.policy("sim_gps_info"), SignaturePolicy::Optional);
+
+        assert!(config.verify_file("core", &path, Some(&signature), SignaturePolicy::Required).is_ok());
+        assert_eq!(config.verify_file("core", &path, None, SignaturePolicy::Required).unwrap_err().code(), "OTA-SIGNATURE");
+        assert_eq!(config.verify_file("core", &path, Some(&forged), SignaturePolicy::Optional).unwrap_err().code(), "OTA-SIGNATURE");
+        assert!(config.verify_file("core", &path, None, SignaturePolicy::Optional).is_ok());
+        assert!(config.verify_file("core", &path, Some("garbage"), SignaturePolicy::Off).is_ok());
+        fs::write(&path, "tampered package").unwrap();
+        assert!(config.verify_file("core", &path, Some(&signature), SignaturePolicy::Required).is_err());
+
+        let unpinned = SignatureConfig { default: SignaturePolicy::Required, ..Default::default() };
+        assert!(unpinned.validate().is_err());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn unreadable_settings_reject_every_package() {
+        let path = std::env::temp_dir().join("signature_config_test.json");
+        fs::write(&path, r#"{ "signatures":{ "default":"sometimes" } }"#).unwrap();
+        assert_eq!(crate::config::Config::from_file(&path).signatures, SignatureConfig::reject_all());
+        fs::write(&path, r#"{ "signatures":"#).unwrap();
+        assert_eq!(crate::config::Config::from_file(&path).signatures, SignatureConfig::reject_all());
+        fs::write(&path, "{}").unwrap();
+        assert_eq!(crate::config::Config::from_file(&path).signatures, SignatureConfig::default());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -65,7 +65,7 @@ impl SnapInstaller {
             let response: SnapdResponse =
                 serde_json::from_str(&response).map_err(|e| e.to_string())?;
             if response.status_code != 200 || response.result.is_none() {
-                return Err(OTAError::nonfatal(response.status));
+                return Err(OTAError::installer("snap", response.status));
             }
             let status = response.result.unwrap();
             log::info!(
@@ -78,7 +78,7 @@ impl SnapInstaller {
             match status.status.as_str() {
                 "Done" => return Ok(status.status),
                 "Do" | "Doing" => {}
-                _ => return Err(OTAError::nonfatal(status.status)),
+                _ => return Err(OTAError::installer("snap", status.status)),
             }
             sleep(std::time::Duration::new(1, 0));
         }
@@ -89,7 +89,7 @@ impl SnapInstaller {
         let path = &component.path.clone().unwrap_or_default();
         let path_str = path.to_string_lossy();
         if !path.exists() {
-            return Err(OTAError::nonfatal(format!(
+            return Err(OTAError::installer("snap", format!(
                 "{} not found",
                 path_str
             )));
@@ -120,7 +120,7 @@ impl SnapInstaller {
             response.change.clone().unwrap_or_else(|| "NONE".to_string())
         );
         if response.status_code != 202 || response.change.is_none() {
-            return Err(OTAError::nonfatal(response.status));
+            return Err(OTAError::installer("snap", response.status));
         }
         let result = SnapInstaller::wait_until_done(&response.change.unwrap(), exec_command)?;
         if disable_after_install {
@@ -178,7 +178,7 @@ impl SnapInstaller {
             response.change.clone().unwrap_or_else(|| "NONE".to_string())
         );
         if response.status_code != 202 || response.change.is_none() {
-            return Err(OTAError::nonfatal(response.status));
+            return Err(OTAError::installer("snap", response.status));
         }
         SnapInstaller::wait_until_done(&response.change.unwrap(), exec_command)
     }
@@ -197,7 +197,7 @@ impl SnapInstaller {
             response.change.clone().unwrap_or_else(|| "NONE".to_string())
         );
         if response.status_code != 202 || response.change.is_none() {
-            return Err(OTAError::nonfatal(response.status));
+            return Err(OTAError::installer("snap", response.status));
         }
         SnapInstaller::wait_until_done(&response.change.unwrap(), exec_command)
     }
@@ -214,15 +214,15 @@ impl SnapInstaller {
                     if snapd_response.status_code == 202 {
                         Ok(snapd_response.status)
                     } else {
-                        Err(OTAError::nonfatal(snapd_response.status))
+                        Err(OTAError::installer("snap", snapd_response.status))
                     }
                 } else {
-                    Err(OTAError::nonfatal(String::from(
+                    Err(OTAError::installer("snap", String::from(
                         core_response.msg.as_str().expect("The error is not string"),
                     )))
                 }
             }
-            Err(error) => Err(OTAError::nonfatal(error.to_string())),
+            Err(error) => Err(OTAError::installer("snap", error.to_string())),
         }
     }
     #[cfg(windows)]
@@ -243,7 +243,7 @@ impl SnapInstaller {
         log::info!("installing {}", path.to_str().unwrap());
         if !path.exists() {
             log::error!("{} was not found", path.to_str().unwrap());
-            return Err(OTAError::nonfatal(format!(
+            return Err(OTAError::installer("snap", format!(
                 "{} was not found",
                 path.to_str().unwrap()
             )));
@@ -270,7 +270,9 @@ impl SnapInstaller {
     #[cfg(unix)]
     pub fn cleanup_deprecated_if_needed() {
         use crate::BashExec;
-        for deprecated in ["sim-gps-info", "vapp-translator", "phau-core"] {
+        use crate::ota::component_registry::registered_components;
+        let deprecated_snaps: Vec<String> = registered_components().into_iter().flat_map(|(_, info)| info.legacy_snap_names).collect();
+        for deprecated in deprecated_snaps.iter().map(String::as_str) {
             if SnapInstaller::extract_snap_enabled(deprecated, BashExec::exec_arg).is_ok() {
                 if let Err(e) = SnapInstaller::uninstall_by_name(deprecated, BashExec::exec_arg) {
                     log::info!("We tried to uninstall deprecated {} but couldn't: {}", deprecated, e.message);
@@ -295,7 +297,7 @@ impl SnapInstaller {
             response.change.clone().unwrap_or_else(|| "NONE".to_string())
         );
         if response.status_code != 202 || response.change.is_none() {
-            return Err(OTAError::nonfatal(response.status));
+            return Err(OTAError::installer("snap", response.status));
         }
         SnapInstaller::wait_until_done(&response.change.unwrap(), exec_command)
     }
@@ -306,14 +308,13 @@ impl SnapInstaller {
         use std::str::FromStr;
         let path = &component.path.clone().unwrap_or_default();
         let snap_name = if !path.exists() {
-            if ComponentType::from_str(&component.component).unwrap() == ComponentType::sim_gps_info {
-                "sim-gps-info".to_string() // We are uninstalling snap gps even without being given a path!
-            } else if ComponentType::from_str(&component.component).unwrap() == ComponentType::translator {
-                "vapp-translator".to_string() // We are uninstalling vapp translator even without being given a path!
-            } else if ComponentType::from_str(&component.component).unwrap() == ComponentType::core {
-                "phau-core".to_string() // We are uninstalling phau core even without being given a path!
-            } else {
-                return Err(OTAError::nonfatal(format!("{} not found", path.to_string_lossy())));
+            // Legacy snaps are uninstalled even without being given a path!
+            let legacy_snap_name = ComponentType::from_str(&component.component)
+                .ok()
+                .and_then(|component_type| component_type.info().legacy_snap_names.first().cloned());
+            match legacy_snap_name {
+                Some(snap_name) => snap_name,
+                None => return Err(OTAError::installer("snap", format!("{} not found", path.to_string_lossy()))),
             }
         } else {
             Self::extract_snap_name(path, exec_command)?
@@ -325,6 +326,8 @@ impl SnapInstaller {
 #[cfg(test)]
 #[cfg(unix)]
 mod tests {
+    use crate::ota::update_journal::UpdateJournal;
+    use crate::ota::cancellation::CancellationToken;
     use std::cell::RefCell;
     use std::env::current_dir;
     use std::fs;
@@ -426,7 +429,7 @@ mod tests {
         let update_ota_status = |_status, _message|{};
         let sys_mock = RefCell::new(mock);
         let download_manager =
-            DownloadManager::new(&sys_mock, &rest_comm, test_dir.clone(), update_ota_status).unwrap();
+            DownloadManager::new(&sys_mock, &rest_comm, test_dir.clone(), update_ota_status, UpdateJournal::default(), CancellationToken::default()).unwrap();
 
         println!("Creating manifest");
         let write_function = |_: &Path, _: &str| Ok(());
@@ -450,6 +453,11 @@ mod tests {
             package_type: "snap".to_string(),
             previous_install_path: Some(previous.clone()),
             processes: vec![],
+            health_checks: vec![],
+            depends_on: vec![],
+            conflicts_with: vec![],
+            hooks: Default::default(),
+            signature: None,
         };
 
         let updated_manifest = manifest.update_single_component(&component).unwrap();
@@ -568,6 +576,7 @@ mod tests {
 #[cfg(test)]
 #[cfg(windows)]
 mod tests {
+    use crate::ota::update_journal::UpdateJournal;
     use crate::ota::snap_installer::SnapInstaller;
     use crate::utils::bash_exec::BashExec;
     use std::path::{Path, PathBuf};
@@ -716,7 +725,7 @@ mod tests {
         let sys_mock = RefCell::new(mock);
         let update_ota_status =  |_ota_status, _message |{};
         let download_manager =
-            DownloadManager::new(&sys_mock, &rest_comm, test_dir.clone(), update_ota_status).unwrap();
+            DownloadManager::new(&sys_mock, &rest_comm, test_dir.clone(), update_ota_status, UpdateJournal::default(), CancellationToken::default()).unwrap();
 
         println!("Creating manifest");
         let write_function = |_: &Path, _: &str| Ok(());
@@ -741,6 +750,11 @@ mod tests {
             package_type: "snap".to_string(),
             previous_install_path: Some(previous.clone()),
             processes: vec![],
+            health_checks: vec![],
+            depends_on: vec![],
+            conflicts_with: vec![],
+            hooks: Default::default(),
+            signature: None,
         };
 
         let updated_manifest = manifest.update_single_component(&component).unwrap();
@@ -1,11 +1,11 @@
 use crate::{BashExec, create_dir_if_not_exists};
 use crate::ota::ota_error::OTAError;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::{fs, str};
 use std::env::temp_dir;
 use std::string::String;
 use crate::ota::manifest::Component;
-use crate::utils::file_utils::get_sha1_checksum;
+use crate::utils::file_utils::{get_checksum, ChecksumAlgorithm};
 use crate::utils::color::Coloralex;
 use crate::utils::bash_exec::ExecArgType;
 
@@ -31,7 +31,7 @@ impl TarInstaller {
     ) -> Result<String, OTAError> {
         let path = component.path.clone().unwrap_or_default();
         if !path.exists() {
-            return Err(OTAError::nonfatal(format!(
+            return Err(OTAError::installer("tar", format!(
                 "{} not found",
                 path.to_str().unwrap()
             )));
@@ -48,7 +48,7 @@ impl TarInstaller {
             target_path_str = format!("./{target_path_str}");
         }
         create_dir_if_not_exists(&target_path);
-        let files: Vec<String> = BashExec::list_files_in_archive(path.clone(), exec_command).map_err(OTAError::nonfatal)?;
+        let files: Vec<String> = BashExec::list_files_in_archive(path.clone(), exec_command).map_err(|e| OTAError::installer("tar", e))?;
         let flags = if zipped { "-xzf" } else { "-xf" };
 
         for file in &files { // Checking whether is the new (multiple clients) version paths
@@ -60,47 +60,48 @@ impl TarInstaller {
 
         let target_path = PathBuf::from(target_path_str.clone()); // Syncing the target_path to match
         let temp_dir = temp_dir().join(format!("TMP_ARCHIVE_{}", component.component));
-        if temp_dir.exists() { fs::remove_dir_all(temp_dir.clone()).expect("Failed to clear temp dir"); }
-        fs::create_dir(temp_dir.clone()).expect("Failed to create temp dir");
+        if temp_dir.exists() {
+            fs::remove_dir_all(&temp_dir).map_err(|e| OTAError::installer("tar", format!("Failed to clear temp dir: {}", e)))?;
+        }
+        fs::create_dir(&temp_dir).map_err(|e| OTAError::installer("tar", format!("Failed to create temp dir: {}", e)))?;
         log::info!("TEMP DIR: Unpacking {} to {}", path.to_string_lossy(), temp_dir.to_string_lossy());
-        (exec_command)("tar", &[flags, &path_str, "-C", &temp_dir.to_string_lossy()]).expect("Failed to execute command!");
+        if let Err(e) = (exec_command)("tar", &[flags, &path_str, "-C", &temp_dir.to_string_lossy()]) {
+            Self::remove_temp_dir(&temp_dir);
+            return Err(OTAError::installer("tar", format!("Failed to execute command: {}", e)));
+        }
         log::info!("Unpacking {} to {}", path.to_string_lossy(), target_path.to_string_lossy());
         if let Err(e) = (exec_command)("tar", &[flags, &path_str, "-C",  &target_path_str]) {
-            let message = format!("Failed to execute command: {}", e);
-            if e.contains("Can't unlink") {
-                return Err(OTAError::nonfatal(message));
-            }
-            else {
-                panic!("{}", message);
-            }
+            Self::remove_temp_dir(&temp_dir);
+            return Err(OTAError::installer("tar", format!("Failed to execute command: {}", e)));
         }
 
-        // Additionally checking whether we can see all the listed unpacked files
+        // Additionally checking whether we can see all the listed unpacked files, with the algorithm of the package checksum
+        let algorithm = ChecksumAlgorithm::of(&component.checksum).unwrap_or(ChecksumAlgorithm::Sha1);
         for file in files {
             let file_path = target_path.join(PathBuf::from(file.clone()));
             let temp_path = temp_dir.join(PathBuf::from(file.clone()));
             if !file_path.exists() {
                 log::error!("Cannot find unpacked: {}", file_path.to_string_lossy());
-                fs::remove_dir_all(temp_dir).expect("Failed to remove temp dir!");
-                return Err(OTAError::nonfatal("Failure".to_string()));
+                Self::remove_temp_dir(&temp_dir);
+                return Err(OTAError::installer("tar", "Failure".to_string()));
             }
             if !temp_path.exists() {
                 log::error!("Cannot find unpacked: {}", temp_path.to_string_lossy());
-                fs::remove_dir_all(temp_dir).expect("Failed to remove temp dir!");
-                return Err(OTAError::nonfatal("Failure".to_string()));
+                Self::remove_temp_dir(&temp_dir);
+                return Err(OTAError::installer("tar", "Failure".to_string()));
             }
-            let file_checksum =  match get_sha1_checksum(&file_path) {
+            let file_checksum =  match get_checksum(&file_path, algorithm, false) {
                 Ok(checksum) => { checksum }
                 Err(e) => {
-                    fs::remove_dir_all(temp_dir).expect("Failed to remove temp dir!");
-                    return Err(OTAError::nonfatal(e));
+                    Self::remove_temp_dir(&temp_dir);
+                    return Err(OTAError::installer("tar", e));
                 }
             };
-            let temp_checksum =  match get_sha1_checksum(&temp_path) {
+            let temp_checksum =  match get_checksum(&temp_path, algorithm, false) {
                 Ok(checksum) => { checksum }
                 Err(e) => {
-                    fs::remove_dir_all(temp_dir).expect("Failed to remove temp dir!");
-                    return Err(OTAError::nonfatal(e));
+                    Self::remove_temp_dir(&temp_dir);
+                    return Err(OTAError::installer("tar", e));
                 }
             };
             if file_checksum == temp_checksum {
@@ -110,15 +111,21 @@ impl TarInstaller {
             else {
                 let message = format!("{} integrity failed: expected {} but file has {}", file, file_checksum, temp_checksum).red(false);
                 log::info!("{}", message);
-                fs::remove_dir_all(temp_dir).expect("Failed to remove temp dir!");
-                return Err(OTAError::nonfatal(message));
+                Self::remove_temp_dir(&temp_dir);
+                return Err(OTAError::installer("tar", message));
             }
         }
         log::info!("{}", "All content unpacked successfully".green(true));
-        fs::remove_dir_all(temp_dir).expect("Failed to remove temp dir!");
+        Self::remove_temp_dir(&temp_dir);
         Ok("Success".to_string())
     }
 
+    fn remove_temp_dir(temp_dir: &Path) {
+        if let Err(e) = fs::remove_dir_all(temp_dir) {
+            log::warn!("Failed to remove temp dir {}: {}", temp_dir.to_string_lossy(), e);
+        }
+    }
+
     pub fn uninstall(
         component: &Component,
         zipped: bool,
@@ -157,7 +164,7 @@ impl TarInstaller {
         let command = format!("tar -{}", list_flags);
         let text = match (exec_command)(&command, &[&archive_path.to_string_lossy()]) {
             Ok(text) => text,
-            Err(e) => { return Err(OTAError::nonfatal(format!("Couldn't list the archive: {}", e))); }
+            Err(e) => { return Err(OTAError::installer("tar", format!("Couldn't list the archive: {}", e))); }
         };
 
         let files: Vec<&str> = text.lines().collect();
@@ -179,6 +186,8 @@ impl TarInstaller {
 
 #[cfg(test)]
 mod tests {
+    use crate::ota::update_journal::UpdateJournal;
+    use crate::ota::cancellation::CancellationToken;
     use crate::auth::license_manager::LicenseManager;
     use crate::auth::license_manager_trait::LicenseManagerTrait;
     use crate::ota::download_manager::DownloadManager;
@@ -243,7 +252,7 @@ mod tests {
 
         let update_ota_status =  |_ota_status, _message |{};
         let download_manager =
-            DownloadManager::new(&sys_mock, &rest_comm, test_dir.clone(), update_ota_status).unwrap();
+            DownloadManager::new(&sys_mock, &rest_comm, test_dir.clone(), update_ota_status, UpdateJournal::default(), CancellationToken::default()).unwrap();
 
         println!("Creating manifest");
         let write_function = |_: &Path, _: &str| Ok(());
@@ -267,6 +276,11 @@ mod tests {
             package_type: "tar".to_string(),
             previous_install_path: Some(target_dir.clone().join("oden_plugin")),
             processes: vec![],
+            health_checks: vec![],
+            depends_on: vec![],
+            conflicts_with: vec![],
+            hooks: Default::default(),
+            signature: None,
         };
 
         let updated_manifest = manifest.update_single_component(&component).unwrap();
@@ -390,7 +404,7 @@ mod tests {
 
         let update_ota_status = |_status, _message|{};
         let download_manager =
-            DownloadManager::new(&sys_mock, &rest_mock, test_dir.clone(), update_ota_status).unwrap();
+            DownloadManager::new(&sys_mock, &rest_mock, test_dir.clone(), update_ota_status, UpdateJournal::default(), CancellationToken::default()).unwrap();
 
         println!("Creating manifest");
         let write_function = |_: &Path, _: &str| Ok(());
@@ -412,6 +426,11 @@ mod tests {
             package_type: "tar".to_string(),
             previous_install_path: Some(target_dir.clone().join("oden_plugin")),
             processes: vec![],
+            health_checks: vec![],
+            depends_on: vec![],
+            conflicts_with: vec![],
+            hooks: Default::default(),
+            signature: None,
         };
 
         let updated_manifest = manifest.update_single_component(&component).unwrap();
@@ -490,4 +509,29 @@ mod tests {
         TarInstaller::install(&component, zipped, exec).unwrap();
         assert!(result_path.exists());
     }
+
+    #[test]
+    fn failed_untar_is_an_error() {
+        let test_dir = std::env::temp_dir().join("tar_failed_untar_test_dir");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(&test_dir).unwrap();
+        let archive = test_dir.join("plugin.tar");
+        fs::write(&archive, "not really a tar").unwrap();
+        let component = Component {
+            path: Some(archive),
+            target_path: Some(test_dir.join("target")),
+            package_type: "tar".to_string(),
+            component: "plugin".to_string(),
+            ..Component::empty()
+        };
+        // Listing works, unpacking fails
+        let exec = |command: &str, _: &[&str]| -> Result<String, String> {
+            if command.starts_with("tar -tf") { Ok("lib.so".to_string()) } else { Err("tar: Unexpected EOF in archive".to_string()) }
+        };
+
+        let error = TarInstaller::install(&component, false, exec).unwrap_err();
+        assert_eq!(error.code(), "OTA-INSTALLER");
+        assert!(!std::env::temp_dir().join("TMP_ARCHIVE_plugin").exists());
+        fs::remove_dir_all(test_dir).unwrap();
+    }
 }
@@ -0,0 +1,350 @@
+use crate::ota::{
+    component_hooks::HookOutput,
+    manifest::{Component, ComponentType, Manifest},
+    ota_status::OTAStatus,
+    update_journal::{JournalStep, PendingCycle},
+    update_plan::PlanAction,
+};
+use chrono::{DateTime, Utc};
+use hyper::Uri;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+pub const UPDATE_HISTORY_FILE: &str = "update_history";
+const MAX_HISTORY_RECORDS: usize = 500;
+const DEFAULT_PAGE_SIZE: usize = 20;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentResult {
+    Pending,
+    DownloadFailed,
+    Downloaded,
+    Failed,
+    Succeeded,
+    RolledBack,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct ComponentRecord {
+    pub component: String,
+    pub action: PlanAction,
+    pub from_version: String,
+    pub to_version: String,
+    pub result: ComponentResult,
+}
+
+// Milliseconds
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, Debug)]
+pub struct PhaseDurations {
+    pub check: u64,
+    pub download: u64,
+    pub install: u64,
+    pub rollback: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct HistoryRecord {
+    pub started: String,
+    pub finished: String,
+    pub manifest_version: String,
+    pub server: String,
+    pub operator: bool,
+    pub components: Vec<ComponentRecord>,
+    pub bytes_downloaded: u64,
+    pub durations: PhaseDurations,
+    pub status: String,
+    pub message: String,
+    // See OTAErrorKind
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    // What the component hooks printed, in the order they ran
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<HookOutput>,
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+// What the cycle is about to do to each component that is not up to date, see UpdatePlan
+pub fn component_records(current_components: &HashMap<ComponentType, Component>, manifest: &Manifest) -> Vec<ComponentRecord> {
+    let mut records: Vec<ComponentRecord> = manifest
+        .components
+        .values()
+        .filter(|component| !component.updated)
+        .map(|component| {
+            let current = current_components.values().find(|current| current.component == component.component);
+            let action = if component.link.is_none() && component.path.is_none() {
+                PlanAction::Uninstall
+            } else if current.map(|current| current.currently_installed()).unwrap_or(false) {
+                PlanAction::Upgrade
+            } else {
+                PlanAction::Install
+            };
+            ComponentRecord {
+                component: component.component.clone(),
+                action,
+                from_version: current.map(|current| current.version.clone()).unwrap_or_default(),
+                to_version: if action == PlanAction::Uninstall { String::default() } else { component.version.clone() },
+                result: ComponentResult::Pending,
+            }
+        })
+        .collect();
+    records.sort_by(|r1, r2| r1.component.cmp(&r2.component));
+    records
+}
+
+// Collects one run_once cycle while it runs
+pub struct CycleRecorder {
+    started: DateTime<Utc>,
+    started_at: Instant,
+    // Cycles that never reached the server (e.g. core has a connected session) are not recorded
+    pub checked: bool,
+    pub status: OTAStatus,
+    pub message: String,
+    // Taken from the journal once the cycle is completed
+    pub closed_cycle: Option<PendingCycle>,
+    pub record: HistoryRecord,
+}
+
+impl Default for CycleRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CycleRecorder {
+    pub fn new() -> Self {
+        let started = Utc::now();
+        Self {
+            started,
+            started_at: Instant::now(),
+            checked: false,
+            status: OTAStatus::CHECKING,
+            message: String::default(),
+            closed_cycle: None,
+            record: HistoryRecord {
+                started: started.to_rfc3339(),
+                finished: String::default(),
+                manifest_version: String::default(),
+                server: String::default(),
+                operator: false,
+                components: vec![],
+                bytes_downloaded: 0,
+                durations: PhaseDurations::default(),
+                status: String::default(),
+                message: String::default(),
+                error_code: None,
+                hooks: vec![],
+            },
+        }
+    }
+
+    // Check phase lasts until the download starts, a cycle with nothing to download has no download phase
+    pub fn downloaded(&mut self, download_started: Option<Instant>, bytes: u64) {
+        let now = Instant::now();
+        let download_started = download_started.unwrap_or(now);
+        self.record.durations.check = millis(download_started.saturating_duration_since(self.started_at));
+        self.record.durations.download = millis(now.saturating_duration_since(download_started));
+        self.record.bytes_downloaded = bytes;
+    }
+
+    pub fn installed(&mut self, install_started: Instant, rollback: Duration) {
+        self.record.durations.install = millis(install_started.elapsed().saturating_sub(rollback));
+        self.record.durations.rollback = millis(rollback);
+    }
+
+    pub fn finish(mut self) -> HistoryRecord {
+        let steps: HashMap<String, JournalStep> = self
+            .closed_cycle
+            .take()
+            .map(|cycle| cycle.components.into_iter().map(|(component, journaled)| (component, journaled.step)).collect())
+            .unwrap_or_default();
+        let failed = matches!(self.status, OTAStatus::ERROR | OTAStatus::DEGRADED);
+        for component in self.record.components.iter_mut() {
+            component.result = match steps.get(&component.component) {
+                None if failed => ComponentResult::Failed,
+                None => ComponentResult::Pending,
+                Some(JournalStep::DownloadStarted) => ComponentResult::DownloadFailed,
+                Some(JournalStep::DownloadVerified) if failed => ComponentResult::Failed,
+                Some(JournalStep::DownloadVerified) => ComponentResult::Downloaded,
+                Some(JournalStep::RolledBack) => ComponentResult::RolledBack,
+                Some(_) => ComponentResult::Succeeded,
+            };
+        }
+        if self.record.durations == PhaseDurations::default() {
+            self.record.durations.check = millis(self.started_at.elapsed());
+        }
+        self.record.finished = Utc::now().to_rfc3339();
+        self.record.status = match serde_json::to_value(&self.status) {
+            Ok(serde_json::Value::String(status)) => status,
+            Ok(serde_json::Value::Object(status)) => status.keys().next().cloned().unwrap_or_default(),
+            _ => String::default(),
+        };
+        self.record.message = self.message;
+        log::debug!("Cycle started at {} ended with {}", self.started.to_rfc3339(), self.record.status);
+        self.record
+    }
+}
+
+// JSON lines, oldest first. An empty path disables the history (used by tests)
+#[derive(Clone, Default)]
+pub struct UpdateHistory {
+    path: PathBuf,
+}
+
+impl UpdateHistory {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn read(&self) -> Vec<HistoryRecord> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(_) => return vec![],
+        };
+        text.lines()
+            .filter_map(|line| serde_json::from_str::<HistoryRecord>(line).ok())
+            .collect()
+    }
+
+    pub fn append(&self, record: &HistoryRecord) {
+        if self.path == PathBuf::default() {
+            return;
+        }
+        let line = match serde_json::to_string(record) {
+            Ok(line) => line + "\n",
+            Err(e) => {
+                log::error!("Failed to serialize history record: {}", e);
+                return;
+            }
+        };
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+        if let Err(e) = result {
+            log::error!("Failed to write into update history {}: {}", self.path.to_string_lossy(), e);
+            return;
+        }
+        let records = self.read();
+        if records.len() > MAX_HISTORY_RECORDS {
+            self.rewrite(&records[records.len() - MAX_HISTORY_RECORDS..]);
+        }
+    }
+
+    fn rewrite(&self, records: &[HistoryRecord]) {
+        let content: String = records
+            .iter()
+            .filter_map(|record| serde_json::to_string(record).ok())
+            .map(|line| line + "\n")
+            .collect();
+        let temp_path = self.path.with_extension("tmp");
+        let result = File::create(&temp_path)
+            .and_then(|mut file| file.write_all(content.as_bytes()))
+            .and_then(|_| fs::rename(&temp_path, &self.path));
+        if let Err(e) = result {
+            log::warn!("Failed to trim update history: {}", e);
+        }
+    }
+
+    // Newest first, pages start from 1
+    pub fn page(&self, page: usize, per_page: usize) -> serde_json::Value {
+        let records = self.read();
+        let per_page = per_page.max(1);
+        let page = page.max(1);
+        let page_records: Vec<&HistoryRecord> = records.iter().rev().skip((page - 1).saturating_mul(per_page)).take(per_page).collect();
+        json!({
+            "total": records.len(),
+            "page": page,
+            "per_page": per_page,
+            "records": page_records,
+        })
+    }
+}
+
+static UPDATE_HISTORY: OnceLock<UpdateHistory> = OnceLock::new();
+
+pub fn set_update_history(history: UpdateHistory) {
+    if UPDATE_HISTORY.set(history).is_err() {
+        log::warn!("Update history was already set");
+    }
+}
+
+// history?page=2&per_page=10
+pub fn get_update_history(uri: Uri, _: String) -> Result<String, String> {
+    let history = UPDATE_HISTORY.get().ok_or("Update history is not initialized".to_string())?;
+    let query: HashMap<&str, &str> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .collect();
+    let number = |key: &str, default: usize| -> Result<usize, String> {
+        match query.get(key) {
+            Some(value) => value.parse().map_err(|_| format!("Invalid {} [{}]", key, value)),
+            None => Ok(default),
+        }
+    };
+    let page = history.page(number("page", 1)?, number("per_page", DEFAULT_PAGE_SIZE)?);
+    serde_json::to_string_pretty(&page).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ota::update_journal::JournaledComponent;
+
+    #[test]
+    fn record_and_page() {
+        let path = std::env::temp_dir().join("update_history_test");
+        let _ = fs::remove_file(&path);
+        let history = UpdateHistory::new(path.clone());
+        for version in ["1.0", "1.1", "1.2"] {
+            let mut recorder = CycleRecorder::new();
+            recorder.status = OTAStatus::ERROR;
+            recorder.message = "failed".to_string();
+            recorder.record.manifest_version = version.to_string();
+            recorder.record.components.push(ComponentRecord {
+                component: "core".to_string(),
+                action: PlanAction::Upgrade,
+                from_version: "0.1".to_string(),
+                to_version: "0.2".to_string(),
+                result: ComponentResult::Pending,
+            });
+            recorder.closed_cycle = Some(PendingCycle {
+                server: "server".to_string(),
+                operator: false,
+                components: HashMap::from([("core".to_string(), JournaledComponent {
+                    step: JournalStep::RolledBack,
+                    checksum: String::default(),
+                    path: None,
+                })]),
+                agent_version: None,
+                manifest_version: None,
+            });
+            history.append(&recorder.finish());
+        }
+        let records = history.read();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].status, "error");
+        assert_eq!(records[0].components[0].result, ComponentResult::RolledBack);
+
+        let page = history.page(2, 2);
+        assert_eq!(page["total"], 3);
+        assert_eq!(page["records"].as_array().unwrap().len(), 1);
+        assert_eq!(page["records"][0]["manifest_version"], "1.0");
+        assert_eq!(history.page(usize::MAX, usize::MAX)["records"].as_array().unwrap().len(), 0);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,334 @@
+use crate::ota::manifest::{HashManifest, Manifest};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+pub const UPDATE_JOURNAL_FILE: &str = "update_journal";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalStep {
+    DownloadStarted,
+    DownloadVerified,
+    Installed,
+    Uninstalled,
+    BackupSaved,
+    RolledBack,
+    HashCommitted,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "entry", rename_all = "snake_case")]
+pub enum JournalEntry {
+    CycleStarted {
+        server: String,
+        operator: bool,
+        time: String,
+    },
+    Component {
+        component: String,
+        step: JournalStep,
+        checksum: String,
+        #[serde(default)]
+        path: Option<PathBuf>,
+        time: String,
+    },
+    CycleCompleted {
+        time: String,
+    },
+    // Written before the future version file, see write_agent_version
+    AgentVersion {
+        version: String,
+    },
+    // Written before the hash manifest and the version table, see write_manifest
+    ManifestVersion {
+        server: String,
+        version: String,
+    },
+    UpdateBoth {
+        stage: String,
+    },
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct JournaledComponent {
+    pub step: JournalStep,
+    pub checksum: String,
+    pub path: Option<PathBuf>,
+}
+
+// A cycle that was started but never completed, i.e. the agent died in the middle of an update
+#[derive(Clone, PartialEq, Debug)]
+pub struct PendingCycle {
+    pub server: String,
+    pub operator: bool,
+    pub components: HashMap<String, JournaledComponent>,
+    // The future version the cycle wrote, if any
+    pub agent_version: Option<String>,
+    // Server and version the cycle was committing the hash manifest with
+    pub manifest_version: Option<(String, String)>,
+}
+
+// Append-only record of every update step, flushed to disk before the step is considered done.
+// An empty path disables the journal (used by tests and dry runs).
+#[derive(Clone, Default)]
+pub struct UpdateJournal {
+    path: PathBuf,
+    // Shared by the clones, so the cycle's owner sees how it ended
+    closed_cycle: Arc<Mutex<Option<PendingCycle>>>,
+}
+
+impl UpdateJournal {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, closed_cycle: Default::default() }
+    }
+
+    fn now() -> String {
+        Utc::now().to_rfc3339()
+    }
+
+    fn append(&self, entry: &JournalEntry) {
+        if self.path == PathBuf::default() {
+            return;
+        }
+        let line = match serde_json::to_string(entry) {
+            Ok(line) => line + "\n",
+            Err(e) => {
+                log::error!("Failed to serialize journal entry: {}", e);
+                return;
+            }
+        };
+        let result = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| {
+                // A line cut by a power loss must not swallow the next entry
+                let length = file.metadata()?.len();
+                if length > 0 {
+                    let mut last = [0u8; 1];
+                    file.seek(SeekFrom::Start(length - 1))?;
+                    file.read_exact(&mut last)?;
+                    if last[0] != b'\n' {
+                        file.write_all(b"\n")?;
+                    }
+                }
+                file.write_all(line.as_bytes())?;
+                file.sync_all()
+            });
+        if let Err(e) = result {
+            log::error!("Failed to write into update journal {}: {}", self.path.to_string_lossy(), e);
+        }
+    }
+
+    pub fn read(&self) -> Vec<JournalEntry> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(_) => return vec![],
+        };
+        text.lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str::<JournalEntry>(line) {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    // Most likely the last line was cut by a power loss
+                    log::warn!("Skipping corrupted journal line [{}]: {}", line, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn start_cycle(&self, server: &str, operator: bool) {
+        self.append(&JournalEntry::CycleStarted {
+            server: server.to_string(),
+            operator,
+            time: Self::now(),
+        });
+    }
+
+    pub fn record(&self, component: &str, step: JournalStep, checksum: &str, path: Option<PathBuf>) {
+        log::debug!("Journal: {} {:?}", component, step);
+        self.append(&JournalEntry::Component {
+            component: component.to_string(),
+            step,
+            checksum: checksum.to_string(),
+            path,
+            time: Self::now(),
+        });
+    }
+
+    // Marks every installed component of the open cycle as committed and closes the cycle
+    pub fn commit_cycle(&self) {
+        if let Some(cycle) = self.pending_cycle() {
+            for (component, journaled) in cycle.components {
+                if matches!(journaled.step, JournalStep::Installed | JournalStep::Uninstalled | JournalStep::BackupSaved) {
+                    self.record(&component, JournalStep::HashCommitted, &journaled.checksum, None);
+                }
+            }
+        }
+        self.complete_cycle();
+    }
+
+    pub fn complete_cycle(&self) {
+        *self.closed_cycle.lock().unwrap() = self.pending_cycle();
+        self.append(&JournalEntry::CycleCompleted { time: Self::now() });
+        self.compact();
+    }
+
+    // The last cycle that was completed, with the last step of each component
+    pub fn take_closed_cycle(&self) -> Option<PendingCycle> {
+        self.closed_cycle.lock().unwrap().take()
+    }
+
+    pub fn pending_cycle(&self) -> Option<PendingCycle> {
+        let mut pending: Option<PendingCycle> = None;
+        for entry in self.read() {
+            match entry {
+                JournalEntry::CycleStarted { server, operator, .. } => {
+                    pending = Some(PendingCycle { server, operator, components: HashMap::new(), agent_version: None, manifest_version: None });
+                }
+                JournalEntry::Component { component, step, checksum, path, .. } => {
+                    if let Some(cycle) = pending.as_mut() {
+                        cycle.components.insert(component, JournaledComponent { step, checksum, path });
+                    }
+                }
+                JournalEntry::AgentVersion { version } => {
+                    if let Some(cycle) = pending.as_mut() {
+                        cycle.agent_version = Some(version);
+                    }
+                }
+                JournalEntry::ManifestVersion { server, version } => {
+                    if let Some(cycle) = pending.as_mut() {
+                        cycle.manifest_version = Some((server, version));
+                    }
+                }
+                JournalEntry::CycleCompleted { .. } => { pending = None; }
+                JournalEntry::UpdateBoth { .. } => {}
+            }
+        }
+        pending
+    }
+
+    // The future version file is only written once the journal has it, OTAManager repairs it on startup
+    pub fn write_agent_version(&self, hash_manifest: &HashManifest, version: &str) -> Result<(), String> {
+        self.append(&JournalEntry::AgentVersion { version: version.to_string() });
+        hash_manifest.update_version_file(version.to_string())
+    }
+
+    // Hash manifest and version table, resuming the cycle writes the version table again
+    pub fn write_manifest(&self, manifest: Manifest) -> Result<Manifest, String> {
+        self.append(&JournalEntry::ManifestVersion { server: manifest.server_name.clone(), version: manifest.version.clone() });
+        manifest.write_to_file()
+    }
+
+    pub fn update_both_stage(&self) -> Option<String> {
+        self.read()
+            .into_iter()
+            .rev()
+            .find_map(|entry| match entry {
+                JournalEntry::UpdateBoth { stage } => Some(stage),
+                _ => None,
+            })
+            .filter(|stage| stage != "none")
+    }
+
+    // The node type an update both stage forces, see OTAManager::get_operator
+    pub fn operator_override(&self) -> Option<bool> {
+        match self.update_both_stage().as_deref() {
+            Some("operator") => Some(true),
+            Some("vehicle") => Some(false),
+            _ => None,
+        }
+    }
+
+    pub fn set_update_both_stage(&self, stage: &str) {
+        self.append(&JournalEntry::UpdateBoth { stage: stage.to_string() });
+    }
+
+    // Once no cycle is open, only the update both stage is still meaningful
+    fn compact(&self) {
+        if self.path == PathBuf::default() || self.pending_cycle().is_some() {
+            return;
+        }
+        let content = match self.update_both_stage() {
+            Some(stage) => match serde_json::to_string(&JournalEntry::UpdateBoth { stage }) {
+                Ok(line) => line + "\n",
+                Err(_) => return,
+            },
+            None => String::default(),
+        };
+        let temp_path = self.path.with_extension("tmp");
+        let result = File::create(&temp_path)
+            .and_then(|mut file| {
+                file.write_all(content.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&temp_path, &self.path));
+        if let Err(e) = result {
+            log::warn!("Failed to compact update journal: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_journal(name: &str) -> UpdateJournal {
+        let path = std::env::temp_dir().join(name);
+        let _ = fs::remove_file(&path);
+        UpdateJournal::new(path)
+    }
+
+    #[test]
+    fn pending_cycle_resumes_last_step() {
+        let journal = get_journal("pending_cycle_journal");
+        journal.start_cycle("server", true);
+        journal.record("core", JournalStep::DownloadStarted, "core checksum", None);
+        journal.record("core", JournalStep::DownloadVerified, "core checksum", Some(PathBuf::from("core.snap")));
+        journal.record("core", JournalStep::Installed, "core checksum", Some(PathBuf::from("core.snap")));
+        journal.record("vapp", JournalStep::DownloadStarted, "vapp checksum", None);
+        journal.append(&JournalEntry::AgentVersion { version: "1.9.5".to_string() });
+        journal.append(&JournalEntry::ManifestVersion { server: "server".to_string(), version: "2.1".to_string() });
+
+        let cycle = journal.pending_cycle().unwrap();
+        assert_eq!(cycle.agent_version, Some("1.9.5".to_string()));
+        assert_eq!(cycle.manifest_version, Some(("server".to_string(), "2.1".to_string())));
+        assert_eq!(cycle.server, "server");
+        assert!(cycle.operator);
+        assert_eq!(cycle.components["core"].step, JournalStep::Installed);
+        assert_eq!(cycle.components["vapp"].step, JournalStep::DownloadStarted);
+
+        journal.commit_cycle();
+        assert_eq!(journal.pending_cycle(), None);
+        assert!(journal.read().is_empty());
+    }
+
+    #[test]
+    fn corrupted_tail_and_update_both() {
+        let journal = get_journal("corrupted_tail_journal");
+        journal.set_update_both_stage("operator");
+        journal.start_cycle("server", false);
+        let mut file = OpenOptions::new().append(true).open(&journal.path).unwrap();
+        file.write_all(br#"{"entry":"component","compo"#).unwrap();
+
+        assert!(journal.pending_cycle().unwrap().components.is_empty());
+        assert_eq!(journal.update_both_stage(), Some("operator".to_string()));
+
+        journal.complete_cycle();
+        assert_eq!(journal.pending_cycle(), None);
+        assert_eq!(journal.update_both_stage(), Some("operator".to_string()));
+        assert_eq!(journal.operator_override(), Some(true));
+        journal.set_update_both_stage("none");
+        assert_eq!(journal.update_both_stage(), None);
+        assert_eq!(journal.operator_override(), None);
+    }
+}
@@ -0,0 +1,289 @@
+use crate::{auth::license_manager_trait::{AuthError, LicenseManagerTrait}, config::is_operator_arch, ota::{
+    cancellation::CancellationToken,
+    component_order::{check_constraints, install_order},
+    component_holds::held_components,
+    disk_space_verifier::DiskSpaceVerifier,
+    download_manager::DownloadManager,
+    install_manager::{sort_by_package, MIN_PROCESS_NAME_LENGTH},
+    manifest::{Component, ComponentType, Manifest},
+    ota_error::OTAError,
+    service_control_trait::SystemControlTrait,
+    system_ctl::SystemCtl,
+    update_journal::UpdateJournal,
+}, rest_comm::coupling_rest_comm::{CouplingRestComm, RESTRequestFunction}};
+use hyper::Uri;
+use serde::{Deserialize, Serialize};
+use std::{cell::RefCell, collections::HashMap, ops::Deref, path::{Path, PathBuf}, sync::OnceLock};
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanAction {
+    Install,
+    Upgrade,
+    Uninstall,
+    Ignore,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ComponentPlan {
+    pub component: String,
+    pub action: PlanAction,
+    pub package_type: String,
+    pub current_version: String,
+    pub new_version: String,
+    pub current_checksum: String,
+    pub new_checksum: String,
+    pub download_size: Option<u64>,
+    pub processes: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UpdatePlan {
+    pub server_name: String,
+    pub manifest_version: String,
+    pub update_needed: bool,
+    pub total_download_size: u64,
+    pub available_disk_space: Option<u64>,
+    pub enough_disk_space: Option<bool>,
+    // Components are listed in install order, unless the dependencies can't be satisfied
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dependency_error: Option<String>,
+    pub components: Vec<ComponentPlan>,
+}
+
+impl UpdatePlan {
+    // Compares the manifest before and after update_with_json, nothing is downloaded or killed
+    pub fn new<A: SystemControlTrait>(
+        current_components: &HashMap<ComponentType, Component>,
+        manifest: &Manifest,
+        disk_space_verifier: Option<&DiskSpaceVerifier>,
+        system_control: &RefCell<A>,
+    ) -> Self {
+        let all_components: Vec<Component> = manifest.components.values().cloned().collect();
+        let (new_components, dependency_error) = match check_constraints(&all_components).and_then(|_| install_order(all_components.clone())) {
+            Ok(ordered) => (ordered, None),
+            Err(e) => {
+                let mut components = all_components;
+                components.sort_by(|c1, c2| sort_by_package(c1, c2).then(c1.component.cmp(&c2.component)));
+                (components, Some(e.report()))
+            }
+        };
+
+        let components: Vec<ComponentPlan> = new_components
+            .into_iter()
+            .map(|new_component| {
+                let current_component = current_components
+                    .values()
+                    .find(|component| component.component == new_component.component);
+                let (current_version, current_checksum, currently_installed) = match current_component {
+                    Some(component) => (component.version.clone(), component.checksum.clone(), component.currently_installed()),
+                    None => (String::default(), String::default(), false),
+                };
+                let action = if new_component.updated {
+                    PlanAction::Ignore
+                } else if new_component.link.is_none() && new_component.path.is_none() {
+                    PlanAction::Uninstall
+                } else if currently_installed {
+                    PlanAction::Upgrade
+                } else {
+                    PlanAction::Install
+                };
+                let download_size = match (action, disk_space_verifier) {
+                    (PlanAction::Install | PlanAction::Upgrade, Some(verifier)) => {
+                        match verifier.get_component_size(&new_component) {
+                            Ok(size) => Some(size),
+                            Err(e) => {
+                                log::warn!("Could not get the download size of {}: {}", new_component.component, e);
+                                None
+                            }
+                        }
+                    }
+                    _ => None,
+                };
+                let processes = if action == PlanAction::Ignore {
+                    vec![]
+                } else {
+                    Self::running_processes(&new_component, system_control)
+                };
+                ComponentPlan {
+                    component: new_component.component.clone(),
+                    action,
+                    package_type: new_component.package_type.clone(),
+                    current_version,
+                    new_version: new_component.version.clone(),
+                    current_checksum,
+                    new_checksum: new_component.checksum.clone(),
+                    download_size,
+                    processes,
+                }
+            })
+            .collect();
+
+        let total_download_size = components.iter().filter_map(|component| component.download_size).sum();
+        Self {
+            server_name: manifest.server_name.clone(),
+            manifest_version: manifest.version.clone(),
+            update_needed: components.iter().any(|component| component.action != PlanAction::Ignore),
+            total_download_size,
+            available_disk_space: disk_space_verifier.map(|verifier| verifier.disk_space),
+            enough_disk_space: disk_space_verifier.map(|verifier| verifier.has_space_for(total_download_size)),
+            dependency_error,
+            components,
+        }
+    }
+
+    // Same filter as InstallManager::kill_component_processes, but only looking
+    fn running_processes<A: SystemControlTrait>(component: &Component, system_control: &RefCell<A>) -> Vec<String> {
+        component
+            .processes
+            .iter()
+            .filter(|process_name| process_name.len() >= MIN_PROCESS_NAME_LENGTH)
+            .filter(|process_name| !system_control.borrow_mut().find_process(process_name).is_empty())
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Clone)]
+pub struct UpdatePlanner {
+    pub hash_manifest_path: PathBuf,
+    pub previous_install_path: PathBuf,
+    pub fetch_license_manager: fn() -> Result<Box<dyn LicenseManagerTrait>, AuthError>,
+    pub send_json: RESTRequestFunction,
+    pub read_function: fn(path: &Path) -> Result<String, String>,
+    pub write_function: fn(path: &Path, content: &str) -> Result<(), String>,
+    pub journal: UpdateJournal,
+}
+
+impl UpdatePlanner {
+    // Same node type as the next cycle, an update both stage overrides the architecture
+    pub fn operator(&self) -> bool {
+        self.journal.operator_override().unwrap_or_else(is_operator_arch)
+    }
+
+    pub fn plan<A: SystemControlTrait>(&self, system_control: &RefCell<A>, operator: bool) -> Result<UpdatePlan, OTAError> {
+        let license_manager = (self.fetch_license_manager)()
+            .map_err(|e| OTAError::auth(format!("Error occurred during loading the license manager file: {e}")))?;
+        let coupling_rest_comm = CouplingRestComm::new(license_manager.deref(), self.send_json);
+        let manifest = Manifest::new(
+            operator,
+            self.hash_manifest_path.clone(),
+            self.previous_install_path.clone(),
+            license_manager.get_server().unwrap_or_default(),
+            self.read_function,
+            self.write_function,
+        )?.with_holds(held_components());
+        let download_manager = DownloadManager::new(
+            system_control,
+            &coupling_rest_comm,
+            PathBuf::default(),
+            |_, _| {}, // The plan must not touch the OTA status
+            UpdateJournal::default(),
+            CancellationToken::default(),
+        )?;
+        download_manager.plan(manifest)
+    }
+}
+
+static UPDATE_PLANNER: OnceLock<UpdatePlanner> = OnceLock::new();
+
+pub fn set_update_planner(planner: UpdatePlanner) {
+    if UPDATE_PLANNER.set(planner).is_err() {
+        log::warn!("Update planner was already set");
+    }
+}
+
+pub fn get_update_plan(_: Uri, _: String) -> Result<String, String> {
+    let planner = match UPDATE_PLANNER.get() {
+        Some(planner) => planner.clone(),
+        None => return Err("Update planner is not initialized".to_string()),
+    };
+    // Blocking requests can't run on the listener's runtime
+    match std::thread::spawn(move || -> Result<String, String> {
+        let system_control = RefCell::new(SystemCtl::new());
+        let plan = planner.plan(&system_control, planner.operator()).map_err(|e| e.message())?;
+        serde_json::to_string_pretty(&plan).map_err(|e| e.to_string())
+    }).join() {
+        Ok(result) => result,
+        Err(_) => {
+            log::error!("Crash in update plan thread");
+            Err("Update plan thread crashed".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ota::service_control_trait::MockSystemControlTrait;
+
+    fn read_function(_path: &Path) -> Result<String, String> {
+        Ok(String::from(
+            r#"{
+            "V_":
+                {
+                    "core":"core checksum",
+                    "sim_gps_info":"gps checksum"
+                }
+        }"#,
+        ))
+    }
+
+    #[test]
+    fn plan_from_server_manifest() {
+        let write_function = |_: &Path, _: &str| Ok(());
+        let manifest = Manifest::new(
+            false,
+            PathBuf::from("./hash_manifest.json"),
+            PathBuf::default(),
+            Default::default(),
+            read_function,
+            write_function,
+        ).unwrap();
+        let current_components = manifest.components.clone();
+        let server_manifest_json = r#"
+        {
+            "version": "1.28",
+            "missingComponents": [
+                {
+                    "token": "token",
+                    "component": "core",
+                    "version": "0.1.3",
+                    "link": "https://core_link",
+                    "checksum": "new core checksum",
+                    "processes": ["phantom-core"]
+                },
+                {
+                    "token": "token",
+                    "component": "vapp",
+                    "version": "0.2.0",
+                    "link": "https://vapp_link",
+                    "checksum": "vapp checksum"
+                }
+            ]
+        }"#;
+        let manifest = manifest.update_with_json(server_manifest_json).unwrap();
+        let disk_space_verifier = DiskSpaceVerifier {
+            get_remote_file_size: |_, _| Ok(20),
+            disk_space: 10,
+        };
+        let mut system_control = MockSystemControlTrait::new();
+        system_control.expect_find_process().returning(|_| vec![42]);
+        let system_control = RefCell::new(system_control);
+
+        let plan = UpdatePlan::new(&current_components, &manifest, Some(&disk_space_verifier), &system_control);
+        let find = |name: &str| plan.components.iter().find(|component| component.component == name).unwrap();
+
+        assert!(plan.update_needed);
+        assert_eq!(plan.manifest_version, "1.28");
+        assert_eq!(find("core").action, PlanAction::Upgrade);
+        assert_eq!(find("core").current_checksum, "core checksum");
+        assert_eq!(find("core").new_checksum, "new core checksum");
+        assert_eq!(find("core").processes, vec!["phantom-core".to_string()]);
+        assert_eq!(find("vapp").action, PlanAction::Install);
+        assert_eq!(find("sim_gps_info").action, PlanAction::Uninstall);
+        assert_eq!(find("sim_gps_info").download_size, None);
+        assert_eq!(plan.total_download_size, 40);
+        assert_eq!(plan.enough_disk_space, Some(false));
+    }
+}
@@ -55,7 +55,7 @@ impl VersionTable {
         }
 
     }
-    fn get_version_table_path() -> PathBuf{
+    pub fn get_version_table_path() -> PathBuf{
         //snap/phantom-agent
         #[cfg(unix)]
             let dir = PathBuf::from(
@@ -5,6 +5,8 @@ use log::{warn, info};
 use serde_json::Value;
 #[cfg(unix)]
 use log::error;
+#[cfg(unix)]
+use crate::ota::component_holds::active_component_holds;
 use url::Url;
 use crate::auth::auth_manager::{AuthManager, fetch_license_manager};
 use crate::auth::license_manager_trait::LicenseManagerTrait;
@@ -104,6 +106,7 @@ impl CouplingRestSubmitter for CouplingRestComm {
             eta: eta.unwrap_or_default(),
             status: ota_progress,
             message: message.unwrap_or_default(),
+            holds: active_component_holds(),
         };
 
         let status_json = serde_json::json!(ota_status);
@@ -2,6 +2,7 @@ pub use mockall::*;
 use std::path::Path;
 use serde::Serialize;
 use url::Url;
+use crate::ota::component_holds::ComponentHold;
 
 
 #[derive(Debug,Serialize,PartialEq)] #[serde(rename_all = "lowercase")]
@@ -17,7 +18,9 @@ pub enum NodeOtaProgressStatus {
 pub struct NodeOtaStatus{
     pub eta: u64,
     pub status: NodeOtaProgressStatus,
-    pub message: String
+    pub message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub holds: Vec<ComponentHold>,
 }
 
 
@@ -18,6 +18,8 @@ use std::{
 };
 use hyper::Uri;
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use crate::ota::ota_error::OTAError;
+use crate::ota::update_history::UPDATE_HISTORY_FILE;
 use crate::rest_comm::coupling_rest_comm::fetch_coupling_rest_comm;
 use crate::utils::log_utils::hostname;
 
@@ -28,6 +30,7 @@ static STANDARD_LOG_LIMIT: usize = 5000000; // 5MB
 static SMALL_LOG_LIMIT: usize = 100000; // 100KB
 pub(crate) static JIRA_REPORT_TICKET: &str = "DEV-12719";
 static JIRA_REPORT_FLAG: &str = "jira_report_flag";
+static OTA_ERROR_FILE: &str = "ota_error.json";
 
 pub struct JiraLogSubmitter {
     pub logs_dir: PathBuf,
@@ -38,8 +41,17 @@ pub struct JiraLogSubmitter {
 }
 
 pub fn send_snapshot_to_jira(ticket: &str, force: bool) -> Result<(), String> {
+    send_error_snapshot(ticket, force, None)
+}
+
+// The snapshot of a failed update carries the error, so the reports can be grouped by its code
+pub fn send_error_snapshot_to_jira(ticket: &str, force: bool, error: &OTAError) -> Result<(), String> {
+    send_error_snapshot(ticket, force, Some(error))
+}
+
+fn send_error_snapshot(ticket: &str, force: bool, error: Option<&OTAError>) -> Result<(), String> {
     let submitter = JiraLogSubmitter::new();
-    let result = submitter.send_snapshot_to_jira(ticket, force);
+    let result = submitter.send_error_snapshot_to_jira(ticket, force, error);
 
     if let Err(e) = result.clone() {
         log::error!("{}", e);
@@ -48,7 +60,7 @@ pub fn send_snapshot_to_jira(ticket: &str, force: bool) -> Result<(), String> {
     if !force {
         match fetch_coupling_rest_comm() {
             Ok(coupling_rest_comm) => {
-                coupling_rest_comm.put_ota_status(None, None, NodeOtaProgressStatus::Failed);
+                coupling_rest_comm.put_ota_status(error.map(|error| error.report()), None, NodeOtaProgressStatus::Failed);
             }
             Err(e) => { log::error!("Cannot fetch Coupling Rest Comm for ota status: {}", e); }
         }
@@ -148,6 +160,14 @@ impl JiraLogSubmitter {
             }
         }
         zip_file.add_file_to_zip_with_limit(&self.logs_dir.join("phantom_agent.log"), limit)?;
+        let error_path = self.zip_dir.join(OTA_ERROR_FILE);
+        if error_path.exists() {
+            zip_file.add_file_to_zip(&error_path)?;
+        }
+        let history_path = self.zip_dir.join(UPDATE_HISTORY_FILE);
+        if history_path.exists() {
+            zip_file.add_file_to_zip_with_limit(&history_path, limit)?;
+        }
         zip_file.finish()
     }
 
@@ -176,6 +196,10 @@ impl JiraLogSubmitter {
     }
 
     pub fn send_snapshot_to_jira(&self, ticket: &str, force: bool) -> Result<(), String> {
+        self.send_error_snapshot_to_jira(ticket, force, None)
+    }
+
+    pub fn send_error_snapshot_to_jira(&self, ticket: &str, force: bool, error: Option<&OTAError>) -> Result<(), String> {
         let date = {
             if force {
                 let date = OffsetDateTime::from(SystemTime::now()).format(&Rfc3339).unwrap();
@@ -192,8 +216,20 @@ impl JiraLogSubmitter {
         let coupling_rest_comm = (self.fetch_coupling_rest_comm)()?;
         log::info!("Sending report to jira");
         let name = if coupling_rest_comm.named { hostname() } else { coupling_rest_comm.name.clone() };
+        let name = match error {
+            Some(error) => format!("{}_{}", name, error.code()),
+            None => name,
+        };
         let zip_path = self.zip_dir.join(format!("{}_{}.zip", date, name));
-        self.create_snapshot(&zip_path, &coupling_rest_comm.path, false)?;
+        let error_path = self.zip_dir.join(OTA_ERROR_FILE);
+        let _ = fs::remove_file(&error_path);
+        if let Some(error) = error {
+            let content = serde_json::to_string_pretty(&error.to_json()).map_err(|e| e.to_string())?;
+            fs::write(&error_path, content).map_err(|e| format!("Failed to write {}: {}", error_path.to_string_lossy(), e))?;
+        }
+        let snapshot = self.create_snapshot(&zip_path, &coupling_rest_comm.path, false);
+        let _ = fs::remove_file(&error_path);
+        snapshot?;
 
         let thread_path = self.zip_dir.join(format!("{}_{}.zip", date, name));
         let thread_ticket = ticket.to_string();
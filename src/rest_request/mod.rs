@@ -1,6 +1,8 @@
 use futures_util::StreamExt;
-use reqwest::header::{
-    HeaderMap, HeaderValue, ACCEPT_RANGES, AUTHORIZATION, CONTENT_TYPE, RANGE, USER_AGENT,
+use futures_util::future::join_all;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, ACCEPT_RANGES, AUTHORIZATION, CONTENT_TYPE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE, USER_AGENT},
+    StatusCode,
 };
 use url::{Position, Url};
 
@@ -17,17 +19,33 @@ use std::{
     sync::{Arc, Mutex},
     time::Duration,
 };
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tokio::fs;
 
 
 use tokio::time::Instant;
-use crate::utils::file_utils::get_sha1_checksum;
+use crate::utils::file_utils::get_checksum_like;
+use crate::ota::ota_error::OTAError;
+use crate::ota::bandwidth::{bandwidth_limiter, throttle};
 
 
 pub struct RestServer;
 
 const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+// Files that are big enough are fetched as this many ranges at once
+const SEGMENT_COUNT: u64 = 4;
+const MIN_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+// Sidecar of a part file, a download only resumes the part when the server still serves the same object
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct PartInfo {
+    url: String,
+    // Strong ETag, or Last-Modified without one
+    validator: String,
+    total_size: u64,
+    checksum: Option<String>,
+}
 
 #[derive(Debug, Clone, Copy)]
 pub enum SendType {
@@ -49,7 +67,11 @@ pub struct DownloadStats {
     last_report: Instant,
     last_size: u64,
     pub eta: u64,
-    pub download_count: usize
+    pub download_count: usize,
+    // Bytes that actually went over the wire, resumed parts are not counted
+    pub downloaded_bytes: u64,
+    // Progress of each segment of the files downloaded in segments
+    segments: HashMap<String, HashMap<usize, u64>>,
 }
 
 impl Default for DownloadStats { fn default() -> Self { Self::new() } }
@@ -67,6 +89,11 @@ pub fn report_progress(stats: &mut DownloadStats) {
         let speed_last = diff / (since_last as u64);
         let speed_average = size / (since_beginning as u64);
         let speed_estimate = std::cmp::max(speed_last, speed_average);
+        // Downloads don't get faster than the bandwidth limit
+        let speed_estimate = match bandwidth_limiter().limit(chrono::Utc::now()) {
+            Some(limit) => min(speed_estimate, limit),
+            None => speed_estimate,
+        };
         let estimate = {
             if speed_estimate == 0 {
                 "Unknown".to_string()
@@ -111,12 +138,26 @@ impl DownloadStats {
             last_report: Instant::now(),
             last_size: 0,
             eta: 0,
-            download_count: 0
+            download_count: 0,
+            downloaded_bytes: 0,
+            segments: HashMap::new(),
         }
     }
     pub fn inc_download_count(&mut self){ self.download_count += 1; }
     pub fn dec_download_count(&mut self){ self.download_count -= 1; }
+    // The progress of the whole file, to be reported with update_entry
+    pub fn update_segment(&mut self, key: &str, segment: usize, value: u64) -> u64 {
+        let segments = self.segments.entry(key.to_string()).or_default();
+        segments.insert(segment, value);
+        segments.values().sum()
+    }
     pub fn update_entry(&mut self, key: String, value: u64, size: u64) {
+        if let Some((previous, previous_size)) = self.stats.get(&key) {
+            // The first report of a file (size still unknown) is where it resumes from
+            if *previous_size != 0 && value > *previous {
+                self.downloaded_bytes += value - previous;
+            }
+        }
         let k = match self.stats.get_mut(&key) {
             None => {
                 self.stats.insert(key.clone(), (value, size));
@@ -181,50 +222,65 @@ impl RestServer {
         authorization: &String,
         stats: Arc<Mutex<DownloadStats>>,
         callback: F,
-    ) -> Result<String, String> {
+    ) -> Result<String, OTAError> {
         let client = reqwest::Client::new();
         let res = client
             .get(url.as_str())
             .header(AUTHORIZATION, authorization)
             .send()
             .await
-            .map_err(|_| format!("Failed to GET from '{}'", &url))?;
+            .map_err(|_| OTAError::network(format!("Failed to GET from '{}'", &url)))?;
         if !res.status().is_success() {
-            return Err(format!(
+            return Err(OTAError::network(format!(
                 "Failed to GET from '{}', Status: {}",
                 &url,
                 res.status()
-            ));
+            )));
         }
         let total_size = res
             .content_length()
-            .ok_or(format!("Failed to get content length from '{}'", &url))?;
+            .ok_or(OTAError::network(format!("Failed to get content length from '{}'", &url)))?;
         let resume_support = match res.headers().get(ACCEPT_RANGES) {
             None => false,
             Some(value) => value.to_str().unwrap() == "bytes",
         };
-        let mut file = match path.exists() && resume_support {
-            true => { // We are attempting to complete a partial download and append it to the file
-                OpenOptions::new()
-                    .append(true)
-                    .open(path.clone())
-                    .map_err(|_| format!("Failed to open file '{}'", &path.display()))?
-            }
-            false => { // We are creating a new file to download the component into
-                File::create(path.clone())
-                    .map_err(|_| format!("Failed to create file '{}'", &path.display()))?
+        // Without a validator there's no telling whether the part and the server object are the same
+        let part_info = Self::validator(res.headers()).map(|validator| PartInfo {
+            url: url.to_string(),
+            validator,
+            total_size,
+            checksum: checksum.clone(),
+        });
+        let part_path = Self::part_path(&path);
+        let resumable = resume_support && part_info.is_some() && Self::read_part_info(&path) == part_info;
+        if !resumable {
+            Self::remove_parts(&path);
+            if let Some(part_info) = &part_info {
+                Self::write_part_info(&path, part_info)?;
             }
+        }
+        let validator = part_info.map(|part_info| part_info.validator);
+        // A single stream download that was started before is resumed as it is
+        let segments = if resume_support { Self::segment_ranges(total_size, MIN_SEGMENT_SIZE) } else { vec![] };
+        if segments.len() > 1 && !part_path.exists() {
+            drop(res);
+            Self::download_segments(&client, url, &path, authorization, validator.as_deref(), total_size, &segments, stats.clone(), &callback).await?;
+            return Self::verify_download(&path, checksum, total_size, stats, &callback).await;
+        }
+        let mut start_size = match resumable {
+            true => std::fs::metadata(&part_path).map(|metadata| metadata.len()).unwrap_or(0),
+            false => 0,
         };
-        let mut start_size = file.metadata().expect("Failed to get file data").len();
-        if start_size >= total_size {
-            // File already downloaded or overdownloaded! That means it's bad
-            log::warn!(
-                "Partially downloaded file equal or bigger than stated total size, overwriting..."
-            );
-            file = File::create(path.clone())
-                .map_err(|_| format!("Failed to create file '{}'", &path.display()))?;
+        if start_size > total_size {
+            // Overdownloaded! That means it's bad
+            log::warn!("Partially downloaded file bigger than stated total size, overwriting...");
             start_size = 0;
         }
+        if start_size == total_size && total_size > 0 {
+            // Downloaded before, only the verification was missing
+            callback(path.to_str().unwrap(), start_size, total_size, stats.clone());
+            return Self::verify_download(&path, checksum, total_size, stats, &callback).await;
+        }
         log::info!(
             "Starting download from position {}/{} for file {}",
             start_size,
@@ -232,16 +288,33 @@ impl RestServer {
             path.to_string_lossy()
         );
         callback(path.to_str().unwrap(), start_size, total_size, stats.clone());
-        let res = match resume_support {
-            true => client
-                .get(url.as_str())
-                .header(AUTHORIZATION, authorization)
-                .header(RANGE, format!("bytes={}-{}", start_size, (total_size - 1)))
-                .send()
-                .await
-                .map_err(|_| format!("Failed to GET from '{}'", &url))?,
-
-            false => res,
+        let (res, mut file) = match (start_size, validator) {
+            (0, _) | (_, None) => { // We are creating a new part file to download the component into
+                let file = File::create(&part_path)
+                    .map_err(|_| OTAError::nonfatal(format!("Failed to create file '{}'", part_path.display())))?;
+                (res, file)
+            }
+            (_, Some(validator)) => { // We are attempting to complete a partial download and append it to the part file
+                drop(res);
+                let res = client
+                    .get(url.as_str())
+                    .header(AUTHORIZATION, authorization)
+                    .header(RANGE, format!("bytes={}-{}", start_size, (total_size - 1)))
+                    .header(IF_RANGE, validator)
+                    .send()
+                    .await
+                    .map_err(|_| OTAError::network(format!("Failed to GET from '{}'", &url)))?;
+                // The object changed between the two requests, the next attempt starts over
+                if res.status() != StatusCode::PARTIAL_CONTENT {
+                    Self::remove_parts(&path);
+                    return Err(OTAError::network(format!("'{}' changed while resuming, Status: {}", &url, res.status())));
+                }
+                let file = OpenOptions::new()
+                    .append(true)
+                    .open(&part_path)
+                    .map_err(|_| OTAError::nonfatal(format!("Failed to open file '{}'", part_path.display())))?;
+                (res, file)
+            }
         };
 
         let mut downloaded: u64 = start_size;
@@ -251,9 +324,10 @@ impl RestServer {
         last_time = if last_time > 60.0 { last_time - 60.0 } else { 0.0 };
         let mut last_percent: f32 = -100.0;
         while let Some(item) = stream.next().await {
-            let chunk = item.map_err(|_| "Error while downloading file".to_string())?;
+            let chunk = item.map_err(|_| OTAError::network("Error while downloading file".to_string()))?;
+            throttle(chunk.len()).await;
             file.write_all(&chunk)
-                .map_err(|_| "Error while writing to file".to_string())?;
+                .map_err(|_| OTAError::nonfatal("Error while writing to file".to_string()))?;
             let new = min(downloaded + (chunk.len() as u64), total_size);
             downloaded = new;
 
@@ -273,19 +347,219 @@ impl RestServer {
             callback(path.to_str().unwrap(), downloaded, total_size, stats.clone());
         }
 
+        if downloaded < total_size {   // The part is kept, the next attempt resumes it
+            return Err(OTAError::network(format!("Download of '{}' ended at {}/{}", path.display(), downloaded, total_size)));
+        }
+        file.flush().map_err(|_| OTAError::nonfatal("Error while writing to file".to_string()))?;
+        drop(file);
+        Self::verify_download(&path, checksum, total_size, stats, &callback).await
+    }
+
+    async fn verify_download<F: Fn(&str, u64, u64, Arc<Mutex<DownloadStats>>)>(
+        path: &Path,
+        checksum: Option<String>,
+        total_size: u64,
+        stats: Arc<Mutex<DownloadStats>>,
+        callback: &F,
+    ) -> Result<String, OTAError> {
+        let part_path = Self::part_path(path);
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let size = fs::metadata(&part_path).await.map(|metadata| metadata.len()).unwrap_or(0);
+        if size != total_size {
+            Self::remove_parts(path);
+            callback(path.to_str().unwrap(), 0, total_size, stats.clone()); // Reset progress bar back to 0
+            return Err(OTAError::network(format!("Downloaded {} has {} bytes instead of {}", file_name, size, total_size)));
+        }
         if let Some(expected_checksum) = checksum {
-            if downloaded >= total_size {   // We got the full file
-                let actual_checksum = get_sha1_checksum(&path).unwrap_or_default();
-                if actual_checksum != expected_checksum {   // Checksum is now what we expected
-                    fs::remove_file(&path).await.map_err(|_| "Failed to delete file")?;
-                    callback(path.to_str().unwrap(), 0, total_size, stats.clone()); // Reset progress bar back to 0
-                    return Err(format!("Checksums don't match! Expected {} but got {}", expected_checksum, actual_checksum));
-                }
+            // With the algorithm the checksum declares
+            let actual_checksum = get_checksum_like(&part_path, &expected_checksum).unwrap_or_default();
+            if !actual_checksum.eq_ignore_ascii_case(&expected_checksum) {   // Checksum is now what we expected
+                Self::remove_parts(path);
+                callback(path.to_str().unwrap(), 0, total_size, stats.clone()); // Reset progress bar back to 0
+                return Err(OTAError::checksum_mismatch(&file_name, &expected_checksum, &actual_checksum));
             }
         }
+        fs::rename(&part_path, path)
+            .await
+            .map_err(|e| OTAError::nonfatal(format!("Failed to move '{}' into place: {}", part_path.display(), e)))?;
+        let _ = fs::remove_file(Self::part_info_path(path)).await;
         Ok(String::from("success"))
     }
 
+    // The file is only moved to its own name once it's verified
+    pub fn part_path(path: &Path) -> PathBuf {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!("{}.part", file_name))
+    }
+
+    fn part_info_path(path: &Path) -> PathBuf {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!("{}.part.json", file_name))
+    }
+
+    // Weak ETags can't be used with If-Range
+    fn validator(headers: &HeaderMap) -> Option<String> {
+        let etag = headers.get(ETAG).and_then(|etag| etag.to_str().ok()).filter(|etag| !etag.starts_with("W/"));
+        etag.or_else(|| headers.get(LAST_MODIFIED).and_then(|last_modified| last_modified.to_str().ok()))
+            .map(|validator| validator.to_string())
+    }
+
+    fn read_part_info(path: &Path) -> Option<PartInfo> {
+        let content = std::fs::read_to_string(Self::part_info_path(path)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn write_part_info(path: &Path, part_info: &PartInfo) -> Result<(), OTAError> {
+        let content = serde_json::to_string(part_info).map_err(|e| OTAError::nonfatal(e.to_string()))?;
+        std::fs::write(Self::part_info_path(path), content)
+            .map_err(|e| OTAError::nonfatal(format!("Failed to write '{}': {}", Self::part_info_path(path).display(), e)))
+    }
+
+    // Part file, segments and sidecar of a download
+    fn remove_parts(path: &Path) {
+        let _ = std::fs::remove_file(Self::part_path(path));
+        let _ = std::fs::remove_file(Self::part_info_path(path));
+        for segment in 0..SEGMENT_COUNT as usize {
+            let _ = std::fs::remove_file(Self::segment_path(path, segment));
+        }
+    }
+
+    pub fn segment_path(path: &Path, segment: usize) -> PathBuf {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!("{}.segment{}.part", file_name, segment))
+    }
+
+    // Inclusive byte ranges, a single one when the file is too small to split
+    fn segment_ranges(total_size: u64, min_segment_size: u64) -> Vec<(u64, u64)> {
+        if total_size == 0 {
+            return vec![]; // Nothing to split, a single stream
+        }
+        let count = (total_size / min_segment_size.max(1)).clamp(1, SEGMENT_COUNT);
+        let segment_size = total_size.div_ceil(count);
+        (0..count)
+            .map(|segment| (segment * segment_size, min((segment + 1) * segment_size, total_size) - 1))
+            .filter(|(start, end)| start <= end)
+            .collect()
+    }
+
+    // Each segment goes into its own part file, so an interrupted download resumes every segment where it stopped
+    #[allow(clippy::too_many_arguments)]
+    async fn download_segments<F: Fn(&str, u64, u64, Arc<Mutex<DownloadStats>>)>(
+        client: &reqwest::Client,
+        url: &Url,
+        path: &Path,
+        authorization: &String,
+        validator: Option<&str>,
+        total_size: u64,
+        segments: &[(u64, u64)],
+        stats: Arc<Mutex<DownloadStats>>,
+        callback: &F,
+    ) -> Result<(), OTAError> {
+        let key = path.to_string_lossy().to_string();
+        // Everything that was downloaded before is reported at once, it isn't counted as downloaded now
+        let resumed = {
+            let mut stats = stats.lock().unwrap();
+            let mut resumed = 0;
+            for (segment, (start, end)) in segments.iter().enumerate() {
+                let done = std::fs::metadata(Self::segment_path(path, segment)).map(|metadata| metadata.len()).unwrap_or(0);
+                resumed = stats.update_segment(&key, segment, min(done, end - start + 1));
+            }
+            resumed
+        };
+        log::info!(
+            "Downloading {} in {} segments, {} of {} already downloaded",
+            path.to_string_lossy(),
+            segments.len(),
+            size_as_string(resumed),
+            size_as_string(total_size)
+        );
+        callback(&key, resumed, total_size, stats.clone());
+        let results = join_all(segments.iter().enumerate().map(|(segment, (start, end))| {
+            Self::download_segment(client, url, path, authorization, validator, segment, (*start, *end), total_size, stats.clone(), callback)
+        })).await;
+        if let Some(Err(e)) = results.into_iter().find(|result| result.is_err()) {
+            return Err(e);
+        }
+
+        let part_path = Self::part_path(path);
+        let mut file = File::create(&part_path).map_err(|_| OTAError::nonfatal(format!("Failed to create file '{}'", part_path.display())))?;
+        for segment in 0..segments.len() {
+            let segment_path = Self::segment_path(path, segment);
+            let copied = File::open(&segment_path).and_then(|mut segment_file| std::io::copy(&mut segment_file, &mut file));
+            if let Err(e) = copied {
+                return Err(OTAError::nonfatal(format!("Failed to assemble '{}': {}", path.display(), e)));
+            }
+        }
+        file.flush().map_err(|_| OTAError::nonfatal("Error while writing to file".to_string()))?;
+        for segment in 0..segments.len() {
+            let _ = std::fs::remove_file(Self::segment_path(path, segment));
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn download_segment<F: Fn(&str, u64, u64, Arc<Mutex<DownloadStats>>)>(
+        client: &reqwest::Client,
+        url: &Url,
+        path: &Path,
+        authorization: &String,
+        validator: Option<&str>,
+        segment: usize,
+        (start, end): (u64, u64),
+        total_size: u64,
+        stats: Arc<Mutex<DownloadStats>>,
+        callback: &F,
+    ) -> Result<(), OTAError> {
+        let key = path.to_string_lossy().to_string();
+        let segment_path = Self::segment_path(path, segment);
+        let segment_size = end - start + 1;
+        let mut done = std::fs::metadata(&segment_path).map(|metadata| metadata.len()).unwrap_or(0);
+        if done > segment_size {
+            log::warn!("Segment {} of {} is bigger than expected, downloading it again", segment, path.to_string_lossy());
+            done = 0;
+        }
+        if done == segment_size {
+            return Ok(());
+        }
+        let mut file = match done {
+            0 => File::create(&segment_path),
+            _ => OpenOptions::new().append(true).open(&segment_path),
+        }.map_err(|_| OTAError::nonfatal(format!("Failed to open file '{}'", segment_path.display())))?;
+        let mut request = client
+            .get(url.as_str())
+            .header(AUTHORIZATION, authorization)
+            .header(RANGE, format!("bytes={}-{}", start + done, end));
+        if let Some(validator) = validator {
+            request = request.header(IF_RANGE, validator);
+        }
+        let res = request
+            .send()
+            .await
+            .map_err(|_| OTAError::network(format!("Failed to GET from '{}'", &url)))?;
+        // A server that ignores the range, or whose object changed, sends the whole file
+        if res.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(OTAError::network(format!("Range request to '{}' failed, Status: {}", &url, res.status())));
+        }
+        let mut stream = res.bytes_stream();
+        while let Some(item) = stream.next().await {
+            let chunk = item.map_err(|_| OTAError::network("Error while downloading file".to_string()))?;
+            let length = min(chunk.len() as u64, segment_size - done);
+            throttle(length as usize).await;
+            file.write_all(&chunk[..length as usize])
+                .map_err(|_| OTAError::nonfatal("Error while writing to file".to_string()))?;
+            done += length;
+            let progress = stats.lock().unwrap().update_segment(&key, segment, done);
+            callback(&key, progress, total_size, stats.clone());
+            if done == segment_size {
+                break;
+            }
+        }
+        if done < segment_size {
+            return Err(OTAError::network(format!("Segment {} of '{}' ended early", segment, path.display())));
+        }
+        Ok(())
+    }
+
     pub async fn report_eta(coupling_url: Url, token: &str, eta: u64){
         let token = Some(format!("Bearer {}", token));
         let coupling_url = coupling_url.join("api/v3/nodes/self/ota").unwrap();
@@ -824,6 +1098,108 @@ mod tests {
         assert!(response > 0);
     }
 
+    // Serves the Range header of each request, the whole file when If-Range doesn't match the ETag
+    struct RangeResponder(Vec<u8>, &'static str);
+
+    impl wiremock::Respond for RangeResponder {
+        #[allow(clippy::unnecessary_map_or)] // is_none_or needs a newer toolchain than the agent is built with
+        fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+            let header = |name: &str| request.headers.get(&name.into()).map(|value| value.as_str().to_string());
+            let range = header(RANGE.as_str()).unwrap_or_default();
+            let bounds: Vec<usize> = range.trim_start_matches("bytes=").split('-').filter_map(|bound| bound.parse().ok()).collect();
+            let same_object = header(IF_RANGE.as_str()).map_or(true, |validator| validator == self.1);
+            match bounds[..] {
+                [start, end] if same_object => ResponseTemplate::new(206).set_body_bytes(self.0[start..=end].to_vec()),
+                _ => ResponseTemplate::new(200)
+                    .append_header("Accept-Ranges", "bytes")
+                    .append_header("ETag", self.1)
+                    .set_body_bytes(self.0.clone()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn segmented_download_resumes() {
+        let content: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/package"))
+            .respond_with(RangeResponder(content.clone(), "\"v1\""))
+            .mount(&server)
+            .await;
+        let url = Url::parse((server.uri() + "/package").as_str()).unwrap();
+        let dir = std::env::temp_dir().join("segmented_download_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("package");
+
+        let segments = RestServer::segment_ranges(content.len() as u64, 250);
+        assert_eq!(segments, vec![(0, 249), (250, 499), (500, 749), (750, 999)]);
+        assert_eq!(RestServer::segment_ranges(0, 250), vec![]);
+        // The second segment was interrupted half way
+        std::fs::write(RestServer::segment_path(&target, 1), &content[250..375]).unwrap();
+        let stats = Arc::new(Mutex::new(DownloadStats::new()));
+        stats.lock().unwrap().update_entry(target.to_string_lossy().to_string(), 0, 0);
+        let callback = |file: &str, progress: u64, total: u64, stats: Arc<Mutex<DownloadStats>>| {
+            stats.lock().unwrap().update_entry(file.to_string(), progress, total);
+        };
+        let client = reqwest::Client::new();
+        RestServer::download_segments(&client, &url, &target, &String::new(), None, 1000, &segments, stats.clone(), &callback).await.unwrap();
+
+        assert_eq!(std::fs::read(RestServer::part_path(&target)).unwrap(), content);
+        assert!(!RestServer::segment_path(&target, 1).exists());
+        let stats = stats.lock().unwrap();
+        assert_eq!(stats.stats[&target.to_string_lossy().to_string()], (1000, 1000));
+        assert_eq!(stats.downloaded_bytes, 875);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn part_is_resumed_only_for_the_same_object() {
+        let content: Vec<u8> = (0..1000u32).map(|i| (i % 241) as u8).collect();
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/package"))
+            .respond_with(RangeResponder(content.clone(), "\"v2\""))
+            .mount(&server)
+            .await;
+        let url = Url::parse((server.uri() + "/package").as_str()).unwrap();
+        let dir = std::env::temp_dir().join("part_download_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("package");
+        std::fs::write(dir.join("expected"), &content).unwrap();
+        let checksum = crate::utils::file_utils::get_sha1_checksum(&dir.join("expected")).unwrap();
+        let callback = |file: &str, progress: u64, total: u64, stats: Arc<Mutex<DownloadStats>>| {
+            stats.lock().unwrap().update_entry(file.to_string(), progress, total);
+        };
+        let part_info = |validator: &str| PartInfo {
+            url: url.to_string(),
+            validator: validator.to_string(),
+            total_size: 1000,
+            checksum: Some(checksum.clone()),
+        };
+
+        // Same object, the first 400 bytes are kept
+        std::fs::write(RestServer::part_path(&target), &content[..400]).unwrap();
+        RestServer::write_part_info(&target, &part_info("\"v2\"")).unwrap();
+        let stats = Arc::new(Mutex::new(DownloadStats::new()));
+        RestServer::download_file_with_callback(&url, target.clone(), Some(checksum.clone()), &String::new(), stats.clone(), callback).await.unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), content);
+        assert_eq!(stats.lock().unwrap().downloaded_bytes, 600);
+        assert!(!RestServer::part_path(&target).exists());
+        assert!(!RestServer::part_info_path(&target).exists());
+
+        // Republished under the same link, the old bytes must not end up in the file
+        std::fs::remove_file(&target).unwrap();
+        std::fs::write(RestServer::part_path(&target), vec![0u8; 400]).unwrap();
+        RestServer::write_part_info(&target, &part_info("\"v1\"")).unwrap();
+        let stats = Arc::new(Mutex::new(DownloadStats::new()));
+        RestServer::download_file_with_callback(&url, target.clone(), Some(checksum), &String::new(), stats, callback).await.unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), content);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
     #[test]
     fn convert_to_storage_url() {
         let url = Url::parse("https://phantomauto.jfrog.io/artifactory/Phantom.Binary/SDK-Phantom-Agent/dev--DEV-10831--alexl/arm64/phantom-agent_0.6.2_arm64.snap").unwrap();
@@ -1,5 +1,16 @@
+use serde::Serialize;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryReason {
+    // The license or coupling server could not be reached
+    Network,
+    // A busy detector (e.g. a connected core session) says updating would interrupt the node
+    Busy,
+}
+
 pub enum Action {
-    RETRY,
+    RETRY(RetryReason),
     CONTINUE,
 }
 pub trait ServiceTrait {
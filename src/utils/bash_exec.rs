@@ -3,19 +3,42 @@ use std::{
     io::{Read, Write},
     process::{Command, Stdio},
     string::String,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 pub type ExecArgType = fn(&str, &[&str]) -> Result<String, String>;
 
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
 #[cfg(windows)]
 use std::os::windows::process::CommandExt;
 use std::path::PathBuf;
 
 pub struct BashExec;
 
+fn read_in_background<R: Read + Send + 'static>(mut pipe: R) -> JoinHandle<String> {
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = pipe.read_to_end(&mut output);
+        String::from_utf8_lossy(&output).to_string()
+    })
+}
+
 #[cfg(windows)]
 const CREATE_NO_WINDOW: u32 = 0x08000000;
 
+// The shell and everything it started, the shell leads its own process group
+fn kill_process_tree(pid: u32) {
+    #[cfg(unix)]
+    let result = Command::new("kill").args(["-KILL", "--", &format!("-{pid}")]).output();
+    #[cfg(windows)]
+    let result = Command::new("taskkill").args(["/T", "/F", "/PID", &pid.to_string()]).creation_flags(CREATE_NO_WINDOW).output();
+    if let Err(e) = result {
+        log::warn!("Failed to kill the processes of {}: {}", pid, e);
+    }
+}
+
 impl BashExec {
     // This function reads from stdin and outputs "| Command arg1 arg2"
     pub fn exec_pipe(command: &str, _arg: Option<&str>, input: &str) -> Result<String, String> {
@@ -99,6 +122,55 @@ impl BashExec {
         }
     }
 
+    // Runs a command line through the shell and kills it once the timeout runs out, the output is stdout followed by stderr
+    pub fn exec_shell_timeout(command: &str, timeout: Duration) -> Result<String, String> {
+        #[cfg(unix)]
+        let mut shell = Command::new("sh");
+        #[cfg(unix)]
+        shell.arg("-c").arg(command).process_group(0);
+        #[cfg(windows)]
+        let mut shell = Command::new("cmd");
+        #[cfg(windows)]
+        shell.arg("/C").arg(command).creation_flags(CREATE_NO_WINDOW);
+        let mut child = shell
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Couldn't spawn [{command}]: {e}"))?;
+        let stdout = child.stdout.take().map(read_in_background);
+        let stderr = child.stderr.take().map(read_in_background);
+
+        // The output is complete once every process holding the pipes exited, a background one may outlive the shell
+        let started = Instant::now();
+        let mut status = None;
+        let status = loop {
+            if status.is_none() {
+                status = child.try_wait().map_err(|e| format!("Couldn't wait for [{command}]: {e}"))?;
+            }
+            let output_done = [&stdout, &stderr].into_iter().flatten().all(|reader| reader.is_finished());
+            if let Some(status) = status.filter(|_| output_done) {
+                break status;
+            }
+            if started.elapsed() >= timeout {
+                kill_process_tree(child.id());
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("[{command}] timed out after {} seconds", timeout.as_secs_f32()));
+            }
+            thread::sleep(Duration::from_millis(100));
+        };
+        let mut output = String::new();
+        for reader in [stdout, stderr].into_iter().flatten() {
+            output += &reader.join().unwrap_or_default();
+        }
+        if status.success() {
+            Ok(output)
+        } else {
+            Err(format!("[{command}] exited with {status}: {output}"))
+        }
+    }
+
     pub fn exec(command: &str) -> Result<String, String> {
         BashExec::exec_arg(command, &[])
     }
@@ -176,6 +248,17 @@ mod tests {
         // BashExec::exec(&command).unwrap();
     }
 
+    #[test]
+    #[cfg(unix)]
+    fn timeout_kills_what_the_command_started() {
+        assert_eq!(BashExec::exec_shell_timeout("echo done", Duration::from_secs(5)), Ok("done\n".to_string()));
+        // The background sleep keeps stdout open after the shell exits
+        let started = Instant::now();
+        let error = BashExec::exec_shell_timeout("sleep 30 & echo started", Duration::from_secs(1)).unwrap_err();
+        assert!(error.contains("timed out"), "{}", error);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
     #[test]
     #[cfg(all(unix, target_pointer_width = "64"))]
     fn pipe_test() {
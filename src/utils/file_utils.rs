@@ -1,5 +1,6 @@
 use crypto::{digest::Digest, sha1::Sha1};
 use log;
+use sha2::{Digest as Sha2Digest, Sha256, Sha512};
 use serde_json;
 use std::{
     fs::{self, File},
@@ -179,6 +180,11 @@ pub fn file_to_string(file_name: &Path) -> Result<String, String> {
     Ok(content)
 }
 
+// A hard link shares the blocks of the file, the copy is for another file system
+pub fn link_or_copy(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::hard_link(from, to).or_else(|_| fs::copy(from, to).map(|_| ()))
+}
+
 pub fn clear_folder(path: &Path) -> Result<(), String> {
     match fs::remove_dir_all(path) {
         Ok(_) => match fs::create_dir(path) {
@@ -254,7 +260,7 @@ pub fn get_path(user_common_path: &Path, file_path: &Path) -> PathBuf {
     user_common_path.join(Path::new(file_path))
 }
 
-fn get_sha1_checksum_by_chunks(path: &Path, chunk_size: usize) -> Result<String, String> {
+fn read_by_chunks(path: &Path, chunk_size: usize, mut consume: impl FnMut(&[u8])) -> Result<(), String> {
     let file = match File::open(path) {
         Ok(file) => file,
         Err(e) => {
@@ -267,7 +273,6 @@ fn get_sha1_checksum_by_chunks(path: &Path, chunk_size: usize) -> Result<String,
     };
 
     let mut reader = BufReader::with_capacity(chunk_size, file);
-    let mut sh = Box::new(Sha1::new());
     loop {
         let length = {
             let buffer = match reader.fill_buf() {
@@ -280,7 +285,7 @@ fn get_sha1_checksum_by_chunks(path: &Path, chunk_size: usize) -> Result<String,
                     ));
                 }
             };
-            sh.input(buffer);
+            consume(buffer);
             buffer.len()
         };
         if length == 0 {
@@ -288,6 +293,12 @@ fn get_sha1_checksum_by_chunks(path: &Path, chunk_size: usize) -> Result<String,
         }
         reader.consume(length);
     }
+    Ok(())
+}
+
+fn get_sha1_checksum_by_chunks(path: &Path, chunk_size: usize) -> Result<String, String> {
+    let mut sh = Box::new(Sha1::new());
+    read_by_chunks(path, chunk_size, |buffer| sh.input(buffer))?;
     let out_str = (*sh).result_str();
     assert_eq!(out_str.len(), 40);
     sh.reset();
@@ -299,6 +310,85 @@ pub fn get_sha1_checksum(path: &Path) -> Result<String, String> {
     get_sha1_checksum_by_chunks(path, CAP)
 }
 
+// A checksum declares its algorithm with a prefix, "sha256:<hex>". A bare digest is sha1, like before
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChecksumAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl ChecksumAlgorithm {
+    pub fn of(checksum: &str) -> Result<Self, String> {
+        match checksum.split_once(':') {
+            None => Ok(ChecksumAlgorithm::Sha1),
+            Some((algorithm, _)) => match algorithm.to_lowercase().as_str() {
+                "sha1" => Ok(ChecksumAlgorithm::Sha1),
+                "sha256" => Ok(ChecksumAlgorithm::Sha256),
+                "sha512" => Ok(ChecksumAlgorithm::Sha512),
+                _ => Err(format!("Unsupported checksum algorithm {}", algorithm)),
+            },
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            ChecksumAlgorithm::Sha1 => "sha1",
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Sha512 => "sha512",
+        }
+    }
+
+    fn digest_length(&self) -> usize {
+        match self {
+            ChecksumAlgorithm::Sha1 => 40,
+            ChecksumAlgorithm::Sha256 => 64,
+            ChecksumAlgorithm::Sha512 => 128,
+        }
+    }
+}
+
+// Whether the value is a well formed checksum, bare sha1 or typed
+pub fn is_checksum(value: &str) -> bool {
+    let digest = value.split_once(':').map(|(_, digest)| digest).unwrap_or(value);
+    match ChecksumAlgorithm::of(value) {
+        Ok(algorithm) => digest.len() == algorithm.digest_length() && digest.chars().all(|c| c.is_ascii_hexdigit()),
+        Err(_) => false,
+    }
+}
+
+// Typed as "<algorithm>:<hex>", the sha1 of a bare expected checksum stays bare
+pub fn get_checksum(path: &Path, algorithm: ChecksumAlgorithm, typed: bool) -> Result<String, String> {
+    const CAP: usize = 1024 * 128;
+    let digest = match algorithm {
+        ChecksumAlgorithm::Sha1 => get_sha1_checksum_by_chunks(path, CAP)?,
+        ChecksumAlgorithm::Sha256 => {
+            let mut hasher = Sha256::new();
+            read_by_chunks(path, CAP, |buffer| hasher.update(buffer))?;
+            format!("{:x}", hasher.finalize())
+        }
+        ChecksumAlgorithm::Sha512 => {
+            let mut hasher = Sha512::new();
+            read_by_chunks(path, CAP, |buffer| hasher.update(buffer))?;
+            format!("{:x}", hasher.finalize())
+        }
+    };
+    match typed {
+        true => Ok(format!("{}:{}", algorithm.name(), digest)),
+        false => Ok(digest),
+    }
+}
+
+// The checksum of the file in the form of the expected one, so the two can be compared
+pub fn get_checksum_like(path: &Path, expected: &str) -> Result<String, String> {
+    get_checksum(path, ChecksumAlgorithm::of(expected)?, expected.contains(':'))
+}
+
+pub fn checksum_matches(path: &Path, expected: &str) -> Result<bool, String> {
+    let actual = get_checksum_like(path, expected)?;
+    Ok(actual.eq_ignore_ascii_case(expected))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -439,6 +529,22 @@ mod tests {
         fs::remove_file(test_file).expect("Failed to remove temp file!");
     }
 
+    #[test]
+    fn test_typed_checksums() {
+        let test_file = std::env::temp_dir().join("typed_checksum_test");
+        fs::write(&test_file, "The quick brown fox jumps over the lazy dog").expect("Couldn't write into file!");
+        let sha256 = "sha256:d7a8fbb307d7809469ca9abcb0082e4f8d5651e46d3cdb762d02d0bf37c9e592";
+        assert_eq!(get_checksum_like(&test_file, sha256).unwrap(), sha256);
+        assert_eq!(get_checksum_like(&test_file, "2fd4e1c67a2d28fced849ee1bb76e7391b93eb12").unwrap(), "2fd4e1c67a2d28fced849ee1bb76e7391b93eb12");
+        assert_eq!(get_checksum(&test_file, ChecksumAlgorithm::Sha1, true).unwrap(), "sha1:2fd4e1c67a2d28fced849ee1bb76e7391b93eb12");
+        assert!(checksum_matches(&test_file, &sha256.to_uppercase().replace("SHA256", "sha256")).unwrap());
+        assert!(!checksum_matches(&test_file, "sha512:00").unwrap());
+        assert!(checksum_matches(&test_file, "md5:9e107d9d372bb6826bd81d3542a419d6").is_err());
+        assert!(is_checksum(sha256) && is_checksum("2fd4e1c67a2d28fced849ee1bb76e7391b93eb12"));
+        assert!(!is_checksum("sha256:2fd4e1c67a2d28fced849ee1bb76e7391b93eb12") && !is_checksum("../license"));
+        fs::remove_file(&test_file).expect("Failed to remove temp file!");
+    }
+
     #[test]
     fn test_file_tail() {
         let test_file = Path::new("file.log");